@@ -10,6 +10,10 @@
 function displayFrame() {
   const ms = millisecondsSinceStart(window.videoStartedAt)
   console.debug("displayFrame", ms)
+  updatePlayhead(ms)
+
+  // frames are rendered on demand, the first ones might not have arrived yet
+  if (window.frames.size === 0) return
 
   if (window.previouslyRenderedFrame) {
     let f = window.frames.get(window.previouslyRenderedFrame)
@@ -88,13 +92,17 @@ function lastLoadedFrame() {
   return Math.max(...[...window.frames.keys()])
 }
 
-async function updateBuffer() {
+/**
+ *
+ * @param {number} [count] number of frames to request
+ */
+async function updateBuffer(count = 4 * window.FRAMES_BUFFER_SIZE) {
   console.time("fetchFrames")
   console.log("set updatingBuffer to true")
   window.updatingBuffer = true
   console.info(
     "updateBuffer",
-    4 * window.FRAMES_BUFFER_SIZE,
+    count,
     window.previouslyRenderedFrame
   )
   // request half the buffer size for the next frames
@@ -102,8 +110,10 @@ async function updateBuffer() {
     new URL(
       "/frames?" +
         new URLSearchParams({
-          next: 4 * window.FRAMES_BUFFER_SIZE,
+          next: count,
           from: window.previouslyRenderedFrame,
+          // layer groups that did not change since the previous frame are left out
+          delta: 1,
         }),
       window.SERVER_ORIGIN
     )
@@ -115,10 +125,14 @@ async function updateBuffer() {
     console.timeEnd("fetchFrames")
 
     console.time("insertFramesToDOM")
-    response.text().then((frames) => {
-      document.body.insertAdjacentHTML("beforeend", frames)
+    // returned so that frames are in the DOM before loadFramesFromDOM runs
+    return response.text().then((frames) => {
+      const template = document.createElement("template")
+      template.innerHTML = frames
+      restoreUnchangedLayers([...template.content.children])
+      document.body.append(template.content)
+      console.timeEnd("insertFramesToDOM")
     })
-    console.timeEnd("insertFramesToDOM")
   })
 
   console.time("pruneFramesFromDOM")
@@ -131,11 +145,27 @@ async function updateBuffer() {
   console.timeEnd("pruneFramesFromDOM")
 
   loadFramesFromDOM()
+  addLayerToggles()
   console.log("set updatingBuffer to false")
   window.updatingBuffer = false
   window.lastBufferUpdateWasOn = window.previouslyRenderedFrame
 }
 
+/**
+ * Puts back the layer groups the server left out because they did not change since the previous frame
+ * @param {Element[]} frames consecutive frames, in chronological order. The first one is complete.
+ */
+function restoreUnchangedLayers(frames) {
+  frames.forEach((frame, i) => {
+    for (const placeholder of frame.querySelectorAll("g[data-unchanged]")) {
+      const previousLayers =
+        frames[i - 1]?.querySelectorAll(":scope > svg > .layer") ?? []
+      const unchanged = previousLayers[placeholder.dataset.unchanged]
+      if (unchanged) placeholder.replaceWith(unchanged.cloneNode(true))
+    }
+  })
+}
+
 window.addEventListener("keypress", (e) => {
   if (e.key === " ") {
     if (window.intervalID) {
@@ -160,7 +190,9 @@ function loadFramesFromDOM() {
 window.startVideo = () => {
   loadFramesFromDOM()
   window.refreshRate = 50
-  window.videoStartedAt = new Date().getTime()
+  // the audio might not start at the beginning, e.g. after seeking
+  window.videoStartedAt =
+    new Date().getTime() - document.querySelector("audio").currentTime * 1000
   window.previouslyRenderedFrame = null
   window.lastBufferUpdateWasOn = null
   window.updatingBuffer = false
@@ -173,3 +205,304 @@ window.stopVideo = () => {
   console.info("stopVideo", window.currentFrame)
   clearInterval(window.intervalID)
 }
+
+/**
+ * Listens for reload events pushed by the preview server, which can come with re-rendered frames
+ */
+function connectToLiveUpdates() {
+  const socket = new WebSocket(
+    new URL("/live", window.SERVER_ORIGIN.replace(/^http/, "ws"))
+  )
+
+  socket.addEventListener("message", async (event) => {
+    const message = JSON.parse(event.data)
+    switch (message.type) {
+      case "reload":
+        console.info("Frames were re-rendered, reloading them")
+        document.querySelectorAll("[id^=frame-]").forEach((el) => el.remove())
+        const pushed = Object.entries(message.frames ?? {})
+        if (pushed.length > 0) {
+          for (const [ms, svg] of pushed) {
+            document.body.insertAdjacentHTML(
+              "beforeend",
+              `<div style="display: none;" id="frame-${ms}" class="frame">${svg}</div>`
+            )
+          }
+          loadFramesFromDOM()
+          addLayerToggles()
+          break
+        }
+        const wasShowing = window.previouslyRenderedFrame
+        window.previouslyRenderedFrame = wasShowing ?? 0
+        await updateBuffer()
+        window.previouslyRenderedFrame = wasShowing
+        break
+    }
+  })
+
+  socket.addEventListener("open", () => {
+    // the server restarted (e.g. in watch mode): reload the page, and go back to where we were
+    if (window.liveUpdatesWereConnected) {
+      sessionStorage.setItem(
+        "shapemaker-preview-position",
+        document.querySelector("audio").currentTime * 1000
+      )
+      location.reload()
+    }
+    window.liveUpdatesWereConnected = true
+  })
+
+  socket.addEventListener("close", () => {
+    setTimeout(connectToLiveUpdates, 1000)
+  })
+}
+
+connectToLiveUpdates()
+
+/**
+ * Draws beats, markers and the playhead from window.TIMELINE. Clicking anywhere seeks there, clicking a marker seeks to it.
+ */
+function setupScrubber() {
+  const scrubber = document.getElementById("scrubber")
+  const { duration_ms, beats, markers } = window.TIMELINE
+  const percent = (ms) => `${(100 * ms) / duration_ms}%`
+
+  beats.forEach((ms, beat) => {
+    const tick = document.createElement("div")
+    tick.className = beat % 4 === 0 ? "beat bar" : "beat"
+    tick.style.left = percent(ms)
+    scrubber.appendChild(tick)
+  })
+
+  for (const [ms, text] of Object.entries(markers)) {
+    const marker = document.createElement("div")
+    marker.className = "marker"
+    marker.textContent = text
+    marker.title = `${text} @ ${ms}ms`
+    marker.style.left = percent(ms)
+    marker.addEventListener("click", (e) => {
+      e.stopPropagation()
+      seekTo(parseInt(ms))
+    })
+    scrubber.appendChild(marker)
+  }
+
+  const playhead = document.createElement("div")
+  playhead.className = "playhead"
+  scrubber.appendChild(playhead)
+
+  const position = document.createElement("div")
+  position.className = "position"
+  scrubber.appendChild(position)
+
+  scrubber.addEventListener("click", (e) => {
+    const { left, width } = scrubber.getBoundingClientRect()
+    seekTo(((e.clientX - left) / width) * duration_ms)
+  })
+
+  document.querySelector("audio").addEventListener("seeked", onSeeked)
+  setupStemMeters()
+  setupParameterSliders()
+  drawWaveform()
+  updatePlayhead(0)
+}
+
+/**
+ * Draws the loudest stem at each point in time, with the same horizontal scale as the scrubber
+ */
+function drawWaveform() {
+  const canvas = document.getElementById("waveform")
+  const amplitudes = Object.values(window.TIMELINE.stems)
+  canvas.width = canvas.clientWidth
+  canvas.height = canvas.clientHeight
+  if (amplitudes.length === 0) return
+
+  const { duration_ms, stems_resolution_ms } = window.TIMELINE
+  const context = canvas.getContext("2d")
+  context.fillStyle = "rgba(255, 255, 255, 0.6)"
+  for (let x = 0; x < canvas.width; x++) {
+    const index = Math.floor(
+      (x / canvas.width) * (duration_ms / stems_resolution_ms)
+    )
+    const amplitude = Math.max(...amplitudes.map((stem) => stem[index] ?? 0))
+    const height = amplitude * canvas.height
+    context.fillRect(x, (canvas.height - height) / 2, 1, height)
+  }
+}
+
+function setupStemMeters() {
+  const meters = document.getElementById("stem-meters")
+  for (const name of Object.keys(window.TIMELINE.stems)) {
+    const row = document.createElement("div")
+    row.innerHTML = `<span class="meter"><div class="level"></div></span>`
+    row.append(name)
+    row.dataset.stem = name
+    meters.appendChild(row)
+  }
+}
+
+/**
+ * Adds a slider for each of the video's parameters. The server renders frames again when one moves, and tells us to reload them.
+ */
+function setupParameterSliders() {
+  const form = document.getElementById("parameters")
+  for (const { name, range, default: value } of window.TIMELINE.parameters) {
+    const label = document.createElement("label")
+    const slider = document.createElement("input")
+    const output = document.createElement("output")
+    slider.type = "range"
+    slider.min = range.start
+    slider.max = range.end
+    slider.step = (range.end - range.start) / 100
+    slider.value = value
+    output.textContent = value
+    slider.addEventListener("input", () => {
+      output.textContent = slider.value
+    })
+    slider.addEventListener("change", () => {
+      fetch(
+        new URL(
+          `/parameters/${encodeURIComponent(name)}?value=${slider.value}`,
+          window.SERVER_ORIGIN
+        )
+      )
+    })
+    label.append(name, " ", slider, " ", output)
+    form.appendChild(label)
+  }
+}
+
+/**
+ *
+ * @param {number} ms
+ */
+function updateStemMeters(ms) {
+  const { stems, stems_resolution_ms } = window.TIMELINE
+  const index = Math.floor(ms / stems_resolution_ms)
+  for (const row of document.querySelectorAll("#stem-meters [data-stem]")) {
+    const amplitude = stems[row.dataset.stem][index] ?? 0
+    row.querySelector(".level").style.width = `${100 * amplitude}%`
+  }
+}
+
+/**
+ *
+ * @param {number} ms
+ */
+function updatePlayhead(ms) {
+  updateStemMeters(ms)
+  const { duration_ms, beats } = window.TIMELINE
+  document.querySelector("#scrubber .playhead").style.left = `${
+    (100 * ms) / duration_ms
+  }%`
+  document.querySelector("#scrubber .position").textContent = `${(
+    ms / 1000
+  ).toFixed(2)}s · beat ${Math.max(0, beatAt(ms))}`
+}
+
+/**
+ * Index of the last beat starting at or before ms, -1 if there's none
+ * @param {number} ms
+ */
+function beatAt(ms) {
+  const { beats } = window.TIMELINE
+  let low = 0
+  let high = beats.length
+  while (low < high) {
+    const middle = Math.floor((low + high) / 2)
+    if (beats[middle] <= ms) low = middle + 1
+    else high = middle
+  }
+  return low - 1
+}
+
+/**
+ *
+ * @param {number} ms
+ */
+function seekTo(ms) {
+  document.querySelector("audio").currentTime = ms / 1000
+}
+
+async function onSeeked() {
+  const ms = document.querySelector("audio").currentTime * 1000
+  const shown = window.frames.get(window.previouslyRenderedFrame)
+  if (shown) {
+    shown.style.display = "none"
+    shown.classList.remove("shown")
+  }
+
+  window.videoStartedAt = new Date().getTime() - ms
+  // load frames from the new position on, the ones already loaded might be far away
+  window.previouslyRenderedFrame = ms
+  await updateBuffer()
+  window.previouslyRenderedFrame = null
+  displayFrame()
+}
+
+window.addEventListener("DOMContentLoaded", async () => {
+  loadFramesFromDOM()
+  setupScrubber()
+  addLayerToggles()
+  if (window.frames.size === 0) {
+    // only ask for a few seconds, so that the preview can start playing quickly
+    window.previouslyRenderedFrame = 0
+    await updateBuffer(window.FRAMES_BUFFER_SIZE / 2)
+    window.previouslyRenderedFrame = null
+  }
+
+  const position = sessionStorage.getItem("shapemaker-preview-position")
+  if (position !== null) {
+    sessionStorage.removeItem("shapemaker-preview-position")
+    seekTo(parseFloat(position))
+  }
+})
+
+/**
+ * Adds a checkbox for each layer that does not have one yet: the canvas' layers, and layers that appeared in loaded frames.
+ * Layers are hidden with CSS, so that frames loaded later are affected too.
+ */
+function addLayerToggles() {
+  const form = document.getElementById("layer-toggles")
+  const names = new Set([
+    ...window.LAYERS,
+    ...[...document.querySelectorAll(".layer[data-layer]")].map(
+      (el) => el.dataset.layer
+    ),
+  ])
+
+  for (const name of names) {
+    if (form.querySelector(`input[value="${CSS.escape(name)}"]`)) continue
+
+    const label = document.createElement("label")
+    const checkbox = document.createElement("input")
+    checkbox.type = "checkbox"
+    checkbox.checked = true
+    checkbox.value = name
+    checkbox.addEventListener("change", updateHiddenLayers)
+    label.append(checkbox, " ", name || "(unnamed)")
+    form.appendChild(label)
+  }
+}
+
+function updateHiddenLayers() {
+  const hidden = [
+    ...document.querySelectorAll("#layer-toggles input:not(:checked)"),
+  ].map((checkbox) => checkbox.value)
+
+  document.getElementById("hidden-layers").textContent = hidden
+    .map((name) => `.layer[data-layer="${CSS.escape(name)}"] { display: none; }`)
+    .join("\n")
+}
+
+/**
+ * Downloads the frame currently displayed, rendered at full resolution for PNGs
+ * @param {"svg" | "png"} format
+ */
+window.exportFrame = (format) => {
+  const ms = window.previouslyRenderedFrame ?? closestFrame(0)
+  const link = document.createElement("a")
+  link.href = new URL(`/frame/${ms}.${format}`, window.SERVER_ORIGIN)
+  link.download = `frame-${ms}.${format}`
+  link.click()
+}
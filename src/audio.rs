@@ -8,7 +8,7 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
-use crate::sync::SyncData;
+use crate::{sync::SyncData, Color, ColorRamp};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Stem {
@@ -39,6 +39,22 @@ impl Stem {
         file.write_all(&bytes).unwrap();
     }
 
+    /// Maximum relative amplitude (between 0 and 1) over each chunk of `every_ms` milliseconds.
+    /// All zeros if the stem is silent.
+    pub fn downsampled_amplitudes(&self, every_ms: usize) -> Vec<f32> {
+        let chunks = self.amplitude_db.chunks(every_ms.max(1));
+        if self.amplitude_max <= 0.0 {
+            return vec![0.0; chunks.len()];
+        }
+        chunks
+            .map(|chunk| {
+                StemWindow::from_amplitudes(chunk, self.amplitude_max)
+                    .max_relative()
+                    .clamp(0.0, 1.0)
+            })
+            .collect()
+    }
+
     pub fn cbor_path(path: PathBuf, name: String) -> String {
         format!(
             "{}/{}.cbor",
@@ -102,6 +118,46 @@ pub enum MusicalDurationUnit {
     Sixteenths,
 }
 
+/// How MIDI note velocities are remapped when loading a stem.
+/// Useful when the DAW exports every note with the same velocity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VelocityCurve {
+    /// Maps velocities 1..=127 linearly onto min..=max
+    Linear(u8, u8),
+    /// Applies (velocity / 127) ^ exponent. Exponents above 1 make soft notes softer.
+    Exponential(f32),
+    /// Every note gets the same velocity
+    Fixed(u8),
+}
+
+impl VelocityCurve {
+    /// Note-off events (velocity 0) are left untouched.
+    pub fn apply(&self, velocity: u8) -> u8 {
+        if velocity == 0 {
+            return 0;
+        }
+
+        let remapped = match self {
+            VelocityCurve::Linear(min, max) => {
+                *min as f32 + (velocity as f32 - 1.0) / 126.0 * (*max as f32 - *min as f32)
+            }
+            VelocityCurve::Exponential(exponent) => {
+                (velocity as f32 / 127.0).powf(*exponent) * 127.0
+            }
+            VelocityCurve::Fixed(value) => *value as f32,
+        };
+
+        // a note-on should never become a note-off
+        remapped.round().clamp(1.0, 127.0) as u8
+    }
+}
+
+impl Default for VelocityCurve {
+    fn default() -> Self {
+        Self::Linear(1, 127)
+    }
+}
+
 #[derive(Debug)]
 pub struct StemAtInstant {
     pub amplitude: f32,
@@ -120,4 +176,96 @@ impl StemAtInstant {
             / self.notes.len() as f32
             / self.velocity_max as f32
     }
+
+    /// Color of the ramp at the current relative amplitude, e.g. for heat-map style visualizations
+    pub fn amplitude_color(&self, ramp: &ColorRamp) -> Color {
+        ramp.color_at(self.amplitude_relative())
+    }
+}
+
+/// An audio input analysed while the video plays, instead of beforehand. See `web_audio::LiveAudio`.
+#[derive(Debug, Clone, Default)]
+pub struct LiveStem {
+    /// Root mean square of the latest samples
+    pub amplitude: f32,
+    /// Loudest amplitude so far
+    pub amplitude_max: f32,
+    /// Magnitude of each frequency bin in decibels, from 0Hz to half the sample rate
+    pub spectrum: Vec<f32>,
+}
+
+impl From<&LiveStem> for StemAtInstant {
+    fn from(stem: &LiveStem) -> Self {
+        Self {
+            amplitude: stem.amplitude,
+            amplitude_max: stem.amplitude_max,
+            duration: 0,
+            velocity_max: 0,
+            notes: vec![],
+        }
+    }
+}
+
+/// Amplitude statistics of a stem over the last few milliseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct StemWindow {
+    pub amplitude_max: f32,
+    pub amplitude_mean: f32,
+    /// Least-squares slope of the amplitude, per millisecond
+    pub amplitude_slope: f32,
+    /// Maximum amplitude over the whole stem, to compute relative values
+    pub stem_amplitude_max: f32,
+}
+
+impl StemWindow {
+    pub fn from_amplitudes(amplitudes: &[f32], stem_amplitude_max: f32) -> Self {
+        if amplitudes.is_empty() {
+            return Self {
+                amplitude_max: 0.0,
+                amplitude_mean: 0.0,
+                amplitude_slope: 0.0,
+                stem_amplitude_max,
+            };
+        }
+
+        let n = amplitudes.len() as f32;
+        let mean = amplitudes.iter().sum::<f32>() / n;
+        let mean_t = (n - 1.0) / 2.0;
+        let (covariance, variance) = amplitudes.iter().enumerate().fold(
+            (0.0, 0.0),
+            |(covariance, variance), (t, amplitude)| {
+                let dt = t as f32 - mean_t;
+                (covariance + dt * (amplitude - mean), variance + dt * dt)
+            },
+        );
+
+        Self {
+            amplitude_max: amplitudes.iter().cloned().fold(f32::MIN, f32::max),
+            amplitude_mean: mean,
+            amplitude_slope: if variance > 0.0 {
+                covariance / variance
+            } else {
+                0.0
+            },
+            stem_amplitude_max,
+        }
+    }
+
+    /// Maximum amplitude over the window, relative to the stem's maximum (0 if the stem is silent)
+    pub fn max_relative(&self) -> f32 {
+        self.relative(self.amplitude_max)
+    }
+
+    /// Mean amplitude over the window, relative to the stem's maximum (0 if the stem is silent)
+    pub fn mean_relative(&self) -> f32 {
+        self.relative(self.amplitude_mean)
+    }
+
+    fn relative(&self, amplitude: f32) -> f32 {
+        if self.stem_amplitude_max > 0.0 {
+            amplitude / self.stem_amplitude_max
+        } else {
+            0.0
+        }
+    }
 }
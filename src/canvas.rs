@@ -1,16 +1,27 @@
 use core::panic;
 use std::{collections::HashMap, io::Write as _, ops::Range};
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+use indexmap::IndexMap;
 use itertools::Itertools as _;
-use rand::Rng;
+use rand::{distributions::WeightedIndex, prelude::Distribution as _, seq::SliceRandom as _, Rng};
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
 
 use crate::{
-    layer::Layer, objects::Object, random_color, Angle, Color, ColorMapping, ColoredObject,
-    Containable, Fill, Filter, LineSegment, ObjectSizes, Point, Region,
+    layer::Layer,
+    objects::Object,
+    color::{random_color, SetColor},
+    point::{layout, GridLayout},
+    random::{chance, rng},
+    text::{text_width, LINE_HEIGHT_EM},
+    BlendMode, Color, ColorMapping, ColoredObject, Containable, Fill, Filter, FilterChain,
+    GridTopology, HatchDirection, LineSegment, Mask, ObjectSizes, Point, PointF, Projection,
+    Region, Transformation,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Canvas {
     pub grid_size: (usize, usize),
     pub cell_size: usize,
@@ -22,10 +33,184 @@ pub struct Canvas {
     /// The layers are in order of top to bottom: the first layer will be rendered on top of the second, etc.
     pub layers: Vec<Layer>,
     pub background: Option<Color>,
+    /// Colors that random fills are picked from. All colors if None. See `set_random_color_pool`.
+    pub random_color_pool: Option<Vec<Color>>,
+    /// If not empty, only layers matching these patterns are rendered. See `solo_layer`.
+    pub soloed_layers: Vec<String>,
+    /// Layers matching these patterns are not rendered. See `mute_layer`.
+    pub muted_layers: Vec<String>,
+    /// Relative weights of each object kind when generating random objects. Kinds not in the map are never generated.
+    #[serde(default = "ObjectKind::uniform_weights")]
+    pub object_kind_weights: HashMap<ObjectKind, f32>,
 
     pub world_region: Region,
+    pub grid_topology: GridTopology,
+    pub projection: Projection,
+}
+
+/// Kinds of objects that can be randomly generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter, Serialize, Deserialize)]
+pub enum ObjectKind {
+    Polygon,
+    BigCircle,
+    SmallCircle,
+    Dot,
+    CurveInward,
+    CurveOutward,
+    Line,
+}
+
+impl ObjectKind {
+    /// Every kind, with a weight of 1
+    pub fn uniform_weights() -> HashMap<ObjectKind, f32> {
+        ObjectKind::iter().map(|kind| (kind, 1.0)).collect()
+    }
+
+    /// Kinds designated by a name, singular or plural: polygon, big-circle, small-circle, dot, curve-inward, curve-outward and line,
+    /// or one of the groups circle (big and small circles) and curve (inward and outward curves).
+    pub fn from_name(name: &str) -> Option<Vec<ObjectKind>> {
+        let name = name.trim().to_lowercase().replace(['_', ' '], "-");
+        let singular = name.strip_suffix('s').unwrap_or(&name);
+        Some(match singular {
+            "polygon" => vec![ObjectKind::Polygon],
+            "big-circle" => vec![ObjectKind::BigCircle],
+            "small-circle" => vec![ObjectKind::SmallCircle],
+            "circle" => vec![ObjectKind::BigCircle, ObjectKind::SmallCircle],
+            "dot" => vec![ObjectKind::Dot],
+            "curve-inward" => vec![ObjectKind::CurveInward],
+            "curve-outward" => vec![ObjectKind::CurveOutward],
+            "curve" => vec![ObjectKind::CurveInward, ObjectKind::CurveOutward],
+            "line" => vec![ObjectKind::Line],
+            _ => return None,
+        })
+    }
+
+    /// Kinds with a positive weight, in declaration order rather than the map's so that seeded generation is reproducible, and a distribution to pick among them.
+    /// Weights can come from a deserialized canvas, so if none is positive every kind is equally likely instead.
+    fn distribution(weights: &HashMap<ObjectKind, f32>) -> (Vec<ObjectKind>, WeightedIndex<f32>) {
+        let kinds = ObjectKind::iter()
+            .filter(|kind| {
+                weights
+                    .get(kind)
+                    .is_some_and(|weight| *weight > 0.0 && weight.is_finite())
+            })
+            .collect::<Vec<_>>();
+        if kinds.is_empty() {
+            return Self::distribution(&Self::uniform_weights());
+        }
+        let distribution = WeightedIndex::new(kinds.iter().map(|kind| weights[kind]))
+            .expect("weights are all positive");
+        (kinds, distribution)
+    }
+}
+
+#[test]
+fn test_object_kind_from_name() {
+    assert_eq!(
+        ObjectKind::from_name("polygon"),
+        Some(vec![ObjectKind::Polygon])
+    );
+    assert_eq!(
+        ObjectKind::from_name("Big circles"),
+        Some(vec![ObjectKind::BigCircle])
+    );
+    assert_eq!(
+        ObjectKind::from_name("curve_inward"),
+        Some(vec![ObjectKind::CurveInward])
+    );
+    assert_eq!(
+        ObjectKind::from_name("circles"),
+        Some(vec![ObjectKind::BigCircle, ObjectKind::SmallCircle])
+    );
+    assert_eq!(
+        ObjectKind::from_name(" curve "),
+        Some(vec![ObjectKind::CurveInward, ObjectKind::CurveOutward])
+    );
+    assert_eq!(ObjectKind::from_name("triangle"), None);
+    assert_eq!(ObjectKind::from_name(""), None);
 }
 
+#[test]
+fn test_random_object_kind_without_positive_weights() {
+    let _lock = crate::random::TEST_RNG_LOCK.lock().unwrap();
+    let mut canvas = Canvas::default_settings();
+    canvas.object_kind_weights = HashMap::new();
+    canvas.random_object_kind();
+    canvas.object_kind_weights = HashMap::from([(ObjectKind::Dot, 0.0)]);
+    canvas.random_object_kind();
+
+    canvas.object_kind_weights = HashMap::from([(ObjectKind::Dot, 0.0), (ObjectKind::Line, 2.0)]);
+    assert_eq!(canvas.random_object_kind(), ObjectKind::Line);
+}
+
+#[test]
+fn test_clip_paths_defined_once() {
+    let mut canvas = Canvas::default_settings();
+    let clip = Object::BigCircle(Point(1, 1));
+    let layer = canvas.new_layer("clipped");
+    layer.add_object(
+        "a",
+        Object::Dot(Point(0, 0))
+            .color(Fill::Solid(Color::Red))
+            .clip(clip.clone()),
+    );
+    layer.add_object(
+        "b",
+        Object::Dot(Point(1, 0))
+            .color(Fill::Solid(Color::Red))
+            .clip(clip),
+    );
+    assert_eq!(
+        canvas.render(false).unwrap().matches("<clipPath").count(),
+        1
+    );
+}
+
+#[test]
+fn test_render_raw_svg() {
+    let mut canvas = Canvas::default_settings();
+    let circle = svg::node::element::Circle::new().set("r", 3);
+    canvas
+        .new_layer("raw")
+        .add_object("circle", Object::RawSVG(Box::new(circle)).into());
+    assert!(canvas.render(false).unwrap().contains(r#"<circle r="3"/>"#));
+
+    let circle = svg::node::element::Circle::new().set("r", 4);
+    canvas
+        .layer("raw")
+        .set_object("circle", Object::RawSVG(Box::new(circle)).into());
+    assert!(canvas.render(false).unwrap().contains(r#"<circle r="4"/>"#));
+}
+
+/// Tiles used by `Canvas::truchet_layer`. Each tile connects two opposite corners of its cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TruchetTiles {
+    /// A straight line, in one of two orientations
+    Diagonals,
+    /// A quarter-circle-like curve, in one of four orientations
+    QuarterCircles,
+}
+
+/// Parameters for random layer generation, see `Canvas::random_layer_with`.
+/// Get one pre-filled with the canvas' settings with `Canvas::random_layer_options`.
+#[derive(Debug, Clone)]
+pub struct RandomLayerOptions {
+    pub objects_count_range: Range<usize>,
+    /// Relative weights of each object kind. Kinds not in the map are never generated, unless no kind has a positive weight: then every kind is equally likely.
+    pub kind_weights: HashMap<ObjectKind, f32>,
+    /// Probability for an object to be filled (or colored, for line-like objects). Clamped to [0, 1].
+    pub fill_probability: f64,
+    /// Colors to pick from. If None, the canvas' random color pool is used.
+    pub colors: Option<Vec<Color>>,
+    /// Where to place the objects
+    pub region: Region,
+    /// If set, only objects entirely inside the mask are kept
+    pub mask: Option<Mask>,
+}
+
+/// How many times to try generating an object that fits inside a mask before giving up on it
+const MASKED_PLACEMENT_ATTEMPTS: usize = 100;
+
 impl Canvas {
     /// Create a new canvas.
     /// The layers are in order of top to bottom: the first layer will be rendered on top of the second, etc.
@@ -35,18 +220,25 @@ impl Canvas {
         if !layer_names.iter().any(|&name| name == "root") {
             layer_names.push("root");
         }
+        let settings = Self::default_settings();
         Self {
             layers: layer_names
                 .iter()
                 .map(|name| Layer {
-                    object_sizes: ObjectSizes::default(),
-                    objects: HashMap::new(),
+                    object_sizes: settings.object_sizes,
+                    objects: IndexMap::new(),
                     name: name.to_string(),
                     _render_cache: None,
                     hidden: false,
+                    clip: None,
+                    bodies: HashMap::new(),
+                    opacity: 1.0,
+                    transformations: vec![],
+                    blend_mode: BlendMode::Normal,
+                    children: vec![],
                 })
                 .collect(),
-            ..Self::default_settings()
+            ..settings
         }
     }
 
@@ -58,8 +250,9 @@ impl Canvas {
         };
     }
 
+    /// Layer with the given name, including layers nested in groups
     pub fn layer_safe(&mut self, name: &str) -> Option<&mut Layer> {
-        self.layers.iter_mut().find(|layer| layer.name == name)
+        self.layers.iter_mut().find_map(|layer| layer.find(name))
     }
 
     pub fn layer(&mut self, name: &str) -> &mut Layer {
@@ -75,10 +268,28 @@ impl Canvas {
             panic!("Layer {} already exists", name);
         }
 
-        self.layers.push(Layer::new(name));
+        self.layers.push(Layer {
+            object_sizes: self.object_sizes,
+            ..Layer::new(name)
+        });
         self.layers.last_mut().unwrap()
     }
 
+    /// Change the canvas' object sizes. Layers that did not override them (see [`Layer::set_object_sizes`]) follow.
+    pub fn set_object_sizes(&mut self, object_sizes: ObjectSizes) {
+        fn follow(layers: &mut [Layer], previous: ObjectSizes, object_sizes: ObjectSizes) {
+            for layer in layers {
+                if layer.object_sizes == previous {
+                    layer.set_object_sizes(object_sizes);
+                }
+                follow(&mut layer.children, previous, object_sizes);
+            }
+        }
+
+        follow(&mut self.layers, self.object_sizes, object_sizes);
+        self.object_sizes = object_sizes;
+    }
+
     pub fn layer_or_empty(&mut self, name: &str) -> &mut Layer {
         if self.layer_exists(name) {
             return self.layer(name);
@@ -88,7 +299,12 @@ impl Canvas {
     }
 
     pub fn layer_exists(&self, name: &str) -> bool {
-        self.layers.iter().any(|layer| layer.name == name)
+        self.all_layers().any(|layer| layer.name == name)
+    }
+
+    /// All layers, including layers nested in groups, depth-first
+    pub fn all_layers(&self) -> impl Iterator<Item = &Layer> {
+        self.layers.iter().flat_map(|layer| layer.descendants())
     }
 
     pub fn ensure_layer_exists(&self, name: &str) {
@@ -167,6 +383,57 @@ impl Canvas {
         }
     }
 
+    /// All objects with the given tag, across all layers
+    pub fn objects_tagged<'a>(
+        &'a mut self,
+        tag: &'a str,
+    ) -> impl Iterator<Item = &'a mut ColoredObject> + 'a {
+        self.layers
+            .iter_mut()
+            .flat_map(move |layer| layer.objects_tagged(tag))
+    }
+
+    /// Size (width, height) in pixels that content takes up when rendered at font_size pixels, one line per line of content.
+    /// Divide by cell_size to get the number of cells to reserve.
+    pub fn measure_text(&self, content: &str, font_size: f32) -> (f32, f32) {
+        let lines = content.lines().collect::<Vec<_>>();
+        let width = lines
+            .iter()
+            .map(|line| text_width(line, font_size))
+            .fold(0.0, f32::max);
+        (
+            width,
+            lines.len().max(1) as f32 * LINE_HEIGHT_EM * font_size,
+        )
+    }
+
+    /// Names and objects touching the region, across all layers. See [`Object::intersects_region`].
+    pub fn objects_overlapping<'a>(
+        &'a self,
+        region: &'a Region,
+    ) -> impl Iterator<Item = (&'a str, &'a ColoredObject)> + 'a {
+        self.layers.iter().flat_map(move |layer| {
+            layer
+                .objects
+                .iter()
+                .filter(move |(_, object)| {
+                    object
+                        .object
+                        .intersects_region(region, layer.object_sizes, self.cell_size)
+                })
+                .map(|(name, object)| (name.as_str(), object))
+        })
+    }
+
+    /// Restrict randomly-picked colors (see `random_fill` and `random_color`) to the given ones
+    pub fn set_random_color_pool(&mut self, colors: &[Color]) {
+        self.random_color_pool = Some(colors.to_vec());
+    }
+
+    pub fn clear_random_color_pool(&mut self) {
+        self.random_color_pool = None;
+    }
+
     pub fn set_background(&mut self, color: Color) {
         self.background = Some(color);
     }
@@ -186,7 +453,13 @@ impl Canvas {
             colormap: ColorMapping::default(),
             layers: vec![],
             world_region: Region::new(0, 0, 3, 3).unwrap(),
+            grid_topology: GridTopology::Square,
+            projection: Projection::Orthographic,
             background: None,
+            random_color_pool: None,
+            soloed_layers: vec![],
+            muted_layers: vec![],
+            object_kind_weights: ObjectKind::uniform_weights(),
         }
     }
 
@@ -206,16 +479,126 @@ impl Canvas {
         }
     }
 
+    /// Copies the layer (see [`Layer::duplicated_as`]) right below the original, e.g. for echo and trail effects.
+    /// An existing layer named new_name is replaced, so this can be called on every beat.
+    pub fn duplicate_layer(
+        &mut self,
+        name: &str,
+        new_name: &str,
+        offset: (i32, i32),
+    ) -> &mut Layer {
+        let duplicate = self.layer(name).duplicated_as(new_name, offset);
+        self.layers.retain(|l| l.name != new_name);
+
+        let index = match self.layers.iter().position(|l| l.name == name) {
+            Some(index) => index + 1,
+            // the original is nested in a group, put the copy on top
+            None => 0,
+        };
+        self.layers.insert(index, duplicate);
+        &mut self.layers[index]
+    }
+
+    /// Imports all layers of another canvas on top of this one's, moved by offset cells.
+    /// Imported layers are renamed to "prefix/layer name", and replace any existing layer with the same name.
+    pub fn composite(&mut self, other: &Canvas, prefix: &str, offset: (i32, i32)) {
+        let (dx, dy) = offset;
+        for layer in other.layers.iter().rev() {
+            let mut imported = layer.clone();
+            imported.name = format!("{}/{}", prefix, layer.name);
+            if offset != (0, 0) {
+                imported.move_all_objects(dx, dy);
+                if let Some(clip) = imported.clip.as_mut() {
+                    clip.translate(dx, dy);
+                }
+            }
+
+            if self.layer_exists(&imported.name) {
+                self.layers.retain(|l| l.name != imported.name);
+            }
+            self.layers.insert(0, imported);
+        }
+    }
+
     pub fn random_layer_within(&self, name: &str, region: &Region) -> Layer {
-        let mut objects: HashMap<String, ColoredObject> = HashMap::new();
-        let number_of_objects = rand::thread_rng().gen_range(self.objects_count_range.clone());
+        self.random_layer_with(
+            name,
+            &RandomLayerOptions {
+                region: *region,
+                ..self.random_layer_options()
+            },
+        )
+    }
+
+    /// Random objects within source_region, repeated `segments` times around the canvas center like in a kaleidoscope.
+    /// Copies are named "object@segment".
+    pub fn kaleidoscope_layer(&self, name: &str, source_region: &Region, segments: usize) -> Layer {
+        let wedge = self.random_layer_within(name, source_region);
+        let mut layer = Layer::new(name);
+        layer.object_sizes = self.object_sizes;
+        for segment in 0..segments {
+            let angle = segment as f32 * 360.0 / segments as f32;
+            for (object_name, object) in &wedge.objects {
+                let mut copy = object.clone();
+                // applied last, so that the object's own transformations stay relative to its center
+                copy.transformations
+                    .insert(0, Transformation::RotateAround(self.center(), angle));
+                layer.add_object(format!("{}@{}", object_name, segment), copy);
+            }
+        }
+        layer
+    }
+
+    /// Like random_layer_within, but objects are placed inside an arbitrarily-shaped mask
+    pub fn random_layer_within_mask(&self, name: &str, mask: &Mask) -> Layer {
+        self.random_layer_with(
+            name,
+            &RandomLayerOptions {
+                region: mask.bounding_region().unwrap_or(self.world_region),
+                mask: Some(mask.clone()),
+                ..self.random_layer_options()
+            },
+        )
+    }
+
+    /// Random layer generation options corresponding to the canvas' settings: every kind of object is equally likely, and all objects are filled.
+    pub fn random_layer_options(&self) -> RandomLayerOptions {
+        RandomLayerOptions {
+            objects_count_range: self.objects_count_range.clone(),
+            kind_weights: self.object_kind_weights.clone(),
+            fill_probability: 1.0,
+            colors: None,
+            region: self.world_region,
+            mask: None,
+        }
+    }
+
+    pub fn random_layer_with(&self, name: &str, options: &RandomLayerOptions) -> Layer {
+        let mut objects: IndexMap<String, ColoredObject> = IndexMap::new();
+        let number_of_objects = rng().gen_range(options.objects_count_range.clone());
+        let (kinds, kind_distribution) = ObjectKind::distribution(&options.kind_weights);
+
         for i in 0..number_of_objects {
-            let object = self.random_object_within(region);
+            let kind = kinds[kind_distribution.sample(&mut rng())];
+            let object = match &options.mask {
+                None => self.random_object_of_kind(kind, &options.region),
+                Some(mask) => {
+                    match (0..MASKED_PLACEMENT_ATTEMPTS)
+                        .map(|_| self.random_object_of_kind(kind, &options.region))
+                        .find(|object| mask.contains_object(object))
+                    {
+                        Some(object) => object,
+                        None => continue,
+                    }
+                }
+            };
             let hatchable = object.hatchable();
-            objects.insert(
-                format!("{}#{}", name, i),
-                object.color(self.random_fill(hatchable)),
-            );
+            let fill = if chance(options.fill_probability) {
+                Some(self.random_fill_among(hatchable, options.colors.as_deref()))
+            } else {
+                None
+            };
+            objects.insert(format!("{}#{}", name, i), (object, fill).into());
         }
         Layer {
             object_sizes: self.object_sizes,
@@ -223,9 +606,89 @@ impl Canvas {
             objects,
             _render_cache: None,
             hidden: false,
+            clip: None,
+            bodies: HashMap::new(),
+            opacity: 1.0,
+            transformations: vec![],
+            blend_mode: BlendMode::Normal,
+            children: vec![],
         }
     }
 
+    /// Voronoi diagram of n_seeds random points, with each cell as a randomly-filled polygon.
+    /// Cell corners are snapped to the grid, so neighboring cells still share their edges.
+    pub fn random_voronoi_layer(&self, name: &str, n_seeds: usize) -> Layer {
+        let Region { start, end } = self.world_region;
+        let (min, max) = (
+            (start.0 as f32, start.1 as f32),
+            ((end.0 + 1) as f32, (end.1 + 1) as f32),
+        );
+        let seeds: Vec<(f32, f32)> = (0..n_seeds)
+            .map(|_| (rng().gen_range(min.0..max.0), rng().gen_range(min.1..max.1)))
+            .collect();
+
+        let mut layer = Layer::new(name);
+        layer.object_sizes = self.object_sizes;
+        for (i, seed) in seeds.iter().enumerate() {
+            let cell = seeds.iter().filter(|other| *other != seed).fold(
+                vec![min, (max.0, min.1), max, (min.0, max.1)],
+                |polygon, other| clip_to_closer_half(&polygon, *seed, *other),
+            );
+
+            let mut corners: Vec<Point> = cell
+                .iter()
+                .map(|(x, y)| Point(x.round() as usize, y.round() as usize))
+                .dedup()
+                .collect();
+            if corners.len() > 1 && corners.first() == corners.last() {
+                corners.pop();
+            }
+            if corners.len() < 3 {
+                continue;
+            }
+
+            layer.add_object(
+                format!("{}#{}", name, i),
+                Object::Polygon(
+                    corners[0],
+                    corners[1..]
+                        .iter()
+                        .map(|corner| LineSegment::Straight(*corner))
+                        .collect(),
+                )
+                .color(self.random_fill(true)),
+            );
+        }
+        layer
+    }
+
+    /// Fills every cell of the region with a randomly-oriented tile. All tiles share the same random color.
+    pub fn truchet_layer(&self, name: &str, region: &Region, tile_set: TruchetTiles) -> Layer {
+        let fill = self.random_fill(false);
+        let width = self.object_sizes.default_line_width;
+        let mut layer = Layer::new(name);
+        layer.object_sizes = self.object_sizes;
+
+        for (i, cell) in region.iter().enumerate() {
+            let (start, end) = if rng().gen_bool(0.5) {
+                (cell, cell.translated(1, 1))
+            } else {
+                (cell.translated(1, 0), cell.translated(0, 1))
+            };
+
+            let tile = match tile_set {
+                TruchetTiles::Diagonals => Object::Line(start, end, width),
+                TruchetTiles::QuarterCircles if rng().gen_bool(0.5) => {
+                    Object::CurveInward(start, end, width)
+                }
+                TruchetTiles::QuarterCircles => Object::CurveOutward(start, end, width),
+            };
+
+            layer.add_object(format!("{}#{}", name, i), tile.color(fill.clone()));
+        }
+        layer
+    }
+
     pub fn random_linelikes(&self, layer_name: &str) -> Layer {
         self.random_linelikes_within(layer_name, &self.world_region)
     }
@@ -236,7 +699,7 @@ impl Canvas {
         region: &Region,
         count: usize,
     ) -> Layer {
-        let mut objects: HashMap<String, ColoredObject> = HashMap::new();
+        let mut objects: IndexMap<String, ColoredObject> = IndexMap::new();
         for i in 0..count {
             let object = self.random_linelike_within(region);
             let hatchable = object.fillable();
@@ -244,7 +707,7 @@ impl Canvas {
                 format!("{}#{}", layer_name, i),
                 ColoredObject::from((
                     object,
-                    if rand::thread_rng().gen_bool(0.5) {
+                    if rng().gen_bool(0.5) {
                         Some(self.random_fill(hatchable))
                     } else {
                         None
@@ -258,43 +721,59 @@ impl Canvas {
             objects,
             _render_cache: None,
             hidden: false,
+            clip: None,
+            bodies: HashMap::new(),
+            opacity: 1.0,
+            transformations: vec![],
+            blend_mode: BlendMode::Normal,
+            children: vec![],
         }
     }
 
     pub fn random_linelikes_within(&self, layer_name: &str, region: &Region) -> Layer {
-        let number_of_objects = rand::thread_rng().gen_range(self.objects_count_range.clone());
+        let number_of_objects = rng().gen_range(self.objects_count_range.clone());
         self.n_random_linelikes_within(layer_name, region, number_of_objects)
     }
 
+    /// Random object of one of the kinds allowed by `object_kind_weights`
     pub fn random_object_within(&self, region: &Region) -> Object {
+        self.random_object_of_kind(self.random_object_kind(), region)
+    }
+
+    /// Picks an object kind according to `object_kind_weights`, or any kind if none has a positive weight
+    pub fn random_object_kind(&self) -> ObjectKind {
+        let (kinds, distribution) = ObjectKind::distribution(&self.object_kind_weights);
+        kinds[distribution.sample(&mut rng())]
+    }
+
+    pub fn random_object_of_kind(&self, kind: ObjectKind, region: &Region) -> Object {
         let start = self.random_point(region);
-        match rand::thread_rng().gen_range(1..=7) {
-            1 => self.random_polygon(region),
-            2 => Object::BigCircle(start),
-            3 => Object::SmallCircle(start),
-            4 => Object::Dot(start),
-            5 => Object::CurveInward(
+        match kind {
+            ObjectKind::Polygon => self.random_polygon(region),
+            ObjectKind::BigCircle => Object::BigCircle(start),
+            ObjectKind::SmallCircle => Object::SmallCircle(start),
+            ObjectKind::Dot => Object::Dot(start),
+            ObjectKind::CurveInward => Object::CurveInward(
                 start,
                 self.random_end_anchor(start, region),
                 self.object_sizes.default_line_width,
             ),
-            6 => Object::CurveOutward(
+            ObjectKind::CurveOutward => Object::CurveOutward(
                 start,
                 self.random_end_anchor(start, region),
                 self.object_sizes.default_line_width,
             ),
-            7 => Object::Line(
+            ObjectKind::Line => Object::Line(
                 self.random_point(region),
                 self.random_point(region),
                 self.object_sizes.default_line_width,
             ),
-            _ => unreachable!(),
         }
     }
 
     pub fn random_linelike_within(&self, region: &Region) -> Object {
         let start = self.random_point(region);
-        match rand::thread_rng().gen_range(1..=3) {
+        match rng().gen_range(1..=3) {
             1 => Object::CurveInward(
                 start,
                 self.random_end_anchor(start, region),
@@ -333,19 +812,19 @@ impl Canvas {
                     continue;
                 }
 
-                // Check that the end anchor is in a square diagonal from the start anchor and that the end anchor is in bounds
-                if x.abs() == y.abs() && actual_region.contains(&end_anchor) {
+                // Check that the end anchor is in a square diagonal (or along a hex axis) from the start anchor and that the end anchor is in bounds
+                if self.grid_topology.is_axis_aligned(x, y) && actual_region.contains(&end_anchor) {
                     possible_end_anchors.push(end_anchor);
                 }
             }
         }
 
         // Pick a random end anchor from the possible end anchors
-        possible_end_anchors[rand::thread_rng().gen_range(0..possible_end_anchors.len())]
+        possible_end_anchors[rng().gen_range(0..possible_end_anchors.len())]
     }
 
     pub fn random_polygon(&self, region: &Region) -> Object {
-        let number_of_anchors = rand::thread_rng().gen_range(self.polygon_vertices_range.clone());
+        let number_of_anchors = rng().gen_range(self.polygon_vertices_range.clone());
         let start = self.random_point(region);
         let mut lines: Vec<LineSegment> = vec![];
         for _ in 0..number_of_anchors {
@@ -356,7 +835,7 @@ impl Canvas {
     }
 
     pub fn random_line(&self, end: Point) -> LineSegment {
-        match rand::thread_rng().gen_range(1..=3) {
+        match rng().gen_range(1..=3) {
             1 => LineSegment::Straight(end),
             2 => LineSegment::InwardCurve(end),
             3 => LineSegment::OutwardCurve(end),
@@ -381,31 +860,104 @@ impl Canvas {
     pub fn random_point(&self, region: &Region) -> Point {
         region.ensure_nonempty().unwrap();
         Point(
-            rand::thread_rng().gen_range(region.x_range()),
-            rand::thread_rng().gen_range(region.y_range()),
+            rng().gen_range(region.x_range()),
+            rng().gen_range(region.y_range()),
         )
     }
 
+    /// Point on the circle of radius `ring` (in cells) around the center of the world region.
+    /// `angle` is in degrees, clockwise, starting from the right.
+    pub fn polar_point(&self, ring: usize, angle: f32) -> PointF {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        self.center()
+            .translated(ring as f32 * cos, ring as f32 * sin)
+    }
+
+    /// Center of the world region. Regions are inclusive, so the last cell's far edge is at end + 1
+    pub fn center(&self) -> PointF {
+        let Region { start, end } = self.world_region;
+        PointF(
+            (start.0 + end.0 + 1) as f32 / 2.0,
+            (start.1 + end.1 + 1) as f32 / 2.0,
+        )
+    }
+
+    /// Up to count random points of the region, all at least min_distance cells away from each other (Poisson-disk sampling).
+    /// Returns fewer points if the region is too small to fit them all.
+    pub fn scatter(&self, region: &Region, min_distance: f32, count: usize) -> Vec<Point> {
+        let mut candidates: Vec<Point> = region.iter().collect();
+        candidates.shuffle(&mut rng());
+
+        let mut points: Vec<Point> = vec![];
+        for candidate in candidates {
+            if points.len() >= count {
+                break;
+            }
+
+            let far_enough = points.iter().all(|point| {
+                let (dx, dy) = (
+                    point.0 as f32 - candidate.0 as f32,
+                    point.1 as f32 - candidate.1 as f32,
+                );
+                (dx * dx + dy * dy).sqrt() >= min_distance
+            });
+            if far_enough {
+                points.push(candidate);
+            }
+        }
+        points
+    }
+
     pub fn random_fill(&self, hatchable: bool) -> Fill {
+        self.random_fill_among(hatchable, None)
+    }
+
+    /// Like `random_fill`, but only picks colors from `colors` (or from the canvas' random color pool if None).
+    pub fn random_fill_among(&self, hatchable: bool, colors: Option<&[Color]>) -> Fill {
+        let color = self.random_color_among(colors);
         if hatchable {
-            if rand::thread_rng().gen_bool(0.75) {
-                Fill::Solid(random_color(self.background))
+            if rng().gen_bool(0.75) {
+                Fill::Solid(color)
             } else {
-                let hatch_size = rand::thread_rng().gen_range(5..=100) as f32 * 1e-2;
-                Fill::Hatched(
-                    random_color(self.background),
-                    Angle(rand::thread_rng().gen_range(0.0..360.0)),
+                let hatch_size = rng().gen_range(5..=100) as f32 * 1e-2;
+                let directions = HatchDirection::iter().collect::<Vec<_>>();
+                Fill::hatches(
+                    color,
+                    directions[rng().gen_range(0..directions.len())],
                     hatch_size,
                     // under a certain hatch size, we can't see the hatching if the ratio is not ½
                     if hatch_size < 8.0 {
                         0.5
                     } else {
-                        rand::thread_rng().gen_range(1..=4) as f32 / 4.0
+                        rng().gen_range(1..=4) as f32 / 4.0
                     },
                 )
             }
         } else {
-            Fill::Solid(random_color(self.background))
+            Fill::Solid(color)
+        }
+    }
+
+    /// Picks a random color from the random color pool (see `set_random_color_pool`), avoiding the background color if possible.
+    pub fn random_color(&self) -> Color {
+        self.random_color_among(None)
+    }
+
+    /// Picks a random color from `colors`, avoiding the background color if possible. Picks from the random color pool if `colors` is None.
+    pub fn random_color_among(&self, colors: Option<&[Color]>) -> Color {
+        match colors.or(self.random_color_pool.as_deref()) {
+            None => random_color(self.background.clone()),
+            Some(colors) => {
+                let candidates = colors
+                    .iter()
+                    .filter(|&c| Some(c) != self.background.as_ref())
+                    .collect::<Vec<_>>();
+                if candidates.is_empty() {
+                    colors[rng().gen_range(0..colors.len())].clone()
+                } else {
+                    candidates[rng().gen_range(0..candidates.len())].clone()
+                }
+            }
         }
     }
 
@@ -414,12 +966,8 @@ impl Canvas {
         self.remove_background()
     }
 
-    pub fn save_as(
-        at: &str,
-        aspect_ratio: f32,
-        resolution: usize,
-        rendered: String,
-    ) -> Result<(), String> {
+    /// Width and height of raster renders, see `save_as`
+    pub fn raster_size(aspect_ratio: f32, resolution: usize) -> (usize, usize) {
         let (height, width) = if aspect_ratio > 1.0 {
             // landscape: resolution is width
             (resolution, (resolution as f32 * aspect_ratio) as usize)
@@ -427,6 +975,16 @@ impl Canvas {
             // portrait: resolution is height
             ((resolution as f32 / aspect_ratio) as usize, resolution)
         };
+        (width, height)
+    }
+
+    pub fn save_as(
+        at: &str,
+        aspect_ratio: f32,
+        resolution: usize,
+        rendered: String,
+    ) -> Result<(), String> {
+        let (width, height) = Self::raster_size(aspect_ratio, resolution);
 
         let mut spawned = std::process::Command::new("resvg")
             .args(["--background", "transparent"])
@@ -438,75 +996,164 @@ impl Canvas {
             .arg(at)
             .stdin(std::process::Stdio::piped())
             .spawn()
-            .unwrap();
+            .map_err(|e| format!("Failed to execute resvg (is it installed?): {}", e))?;
 
         let stdin = spawned.stdin.as_mut().unwrap();
-        stdin.write_all(rendered.as_bytes()).unwrap();
+        stdin
+            .write_all(rendered.as_bytes())
+            .map_err(|e| format!("Failed to send the SVG to resvg: {}", e))?;
 
         match spawned.wait_with_output() {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("Failed to execute convert: {}", e)),
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => Err(format!("resvg failed with {}", output.status)),
+            Err(e) => Err(format!("Failed to execute resvg: {}", e)),
         }
     }
 }
 
 impl Canvas {
     pub fn width(&self) -> usize {
-        self.cell_size * self.world_region.width() + 2 * self.canvas_outter_padding
+        let ((min_x, _), (max_x, _)) = self.grid_bounds();
+        (self.cell_size as f32 * (max_x - min_x + 1.0)).ceil() as usize
+            + 2 * self.canvas_outter_padding
     }
 
     pub fn height(&self) -> usize {
-        self.cell_size * self.world_region.height() + 2 * self.canvas_outter_padding
+        let ((_, min_y), (_, max_y)) = self.grid_bounds();
+        (self.cell_size as f32 * (max_y - min_y + 1.0)).ceil() as usize
+            + 2 * self.canvas_outter_padding
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    /// Top-left and bottom-right corners of the world region once laid out according to the grid topology and projection, in cells
+    fn grid_bounds(&self) -> ((f32, f32), (f32, f32)) {
+        let (right, bottom) = (
+            self.world_region.width() as f32 - 1.0,
+            self.world_region.height() as f32 - 1.0,
+        );
+        let corners = [(0.0, 0.0), (right, 0.0), (0.0, bottom), (right, bottom)]
+            .map(|(x, y)| layout(self.grid_topology, self.projection, x, y));
+
+        corners.iter().fold(
+            (corners[0], corners[0]),
+            |((min_x, min_y), (max_x, max_y)), &(x, y)| {
+                ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y)))
+            },
+        )
+    }
+
+    /// SVG coordinates of the top-left corner of the rendered canvas, padding included
+    pub(crate) fn viewbox_origin(&self) -> (f32, f32) {
+        let ((min_x, min_y), _) = self.grid_bounds();
+        (
+            min_x * self.cell_size as f32 - self.canvas_outter_padding as f32,
+            min_y * self.cell_size as f32 - self.canvas_outter_padding as f32,
+        )
     }
 
     pub fn aspect_ratio(&self) -> f32 {
         self.width() as f32 / self.height() as f32
     }
 
-    pub fn remove_all_objects_in(&mut self, region: &Region) {
+    pub fn remove_all_objects_in<C: Containable<Region>>(&mut self, area: &C) {
         self.layers
             .iter_mut()
-            .for_each(|layer| layer.remove_all_objects_in(region));
+            .for_each(|layer| layer.remove_all_objects_in(area));
+    }
+
+    /// Moves objects that have a physics body attached by `ms` milliseconds' worth of simulation
+    pub fn step_physics(&mut self, ms: usize) {
+        let world = self.world_region;
+        for layer in self.layers.iter_mut() {
+            layer.step_physics(ms, &world);
+        }
     }
 
     /// returns a list of all unique filters used throughout the canvas
     /// used to only generate one definition per filter
     ///
     fn unique_filters(&self) -> Vec<Filter> {
-        self.layers
-            .iter()
+        self.all_layers()
             .flat_map(|layer| layer.objects.iter().flat_map(|(_, o)| o.filters.clone()))
             .unique()
             .collect()
     }
 
+    /// returns a list of all unique combinations of filters used by objects with more than one filter
+    fn unique_filter_chains(&self) -> Vec<FilterChain> {
+        self.all_layers()
+            .flat_map(|layer| layer.objects.values())
+            .filter(|o| o.filters.len() > 1)
+            .map(|o| FilterChain::from(o.filters.clone()))
+            .unique()
+            .collect()
+    }
+
+    /// Shapes that layers or objects are clipped to, with the object sizes they are rendered with
+    fn unique_clips(&self) -> Vec<(Object, ObjectSizes)> {
+        let layout = self.grid_layout();
+        self.all_layers()
+            .flat_map(|layer| {
+                let layer_clip = layer
+                    .clip
+                    .map(|region| Object::Rectangle(region.start, region.end));
+                layer
+                    .objects
+                    .values()
+                    .filter_map(|o| o.clip.clone())
+                    .chain(layer_clip)
+                    .map(|clip| (clip, layer.object_sizes))
+            })
+            .unique_by(|(clip, object_sizes)| clip.clip_path_id(layout, *object_sizes))
+            .collect()
+    }
+
     fn unique_pattern_fills(&self) -> Vec<Fill> {
-        self.layers
-            .iter()
-            .flat_map(|layer| layer.objects.iter().flat_map(|(_, o)| o.fill))
-            .filter(|fill| matches!(fill, Fill::Hatched(..) | Fill::Dotted(..)))
+        self.all_layers()
+            .flat_map(|layer| layer.objects.iter().flat_map(|(_, o)| o.fill.clone()))
+            .filter(|fill| fill.needs_definition())
             .unique_by(|fill| fill.pattern_id())
             .collect()
     }
 
+    /// Fills the "grid" layer with a dot on every anchor point. The layer is put below all others.
+    pub fn draw_grid(&mut self, dot_radius: f32, color: Color) {
+        let world_region = self.world_region;
+        let layer = self.layer_or_empty("grid");
+        layer.object_sizes.dot_radius = dot_radius;
+        layer.objects.clear();
+
+        for point in world_region.iter() {
+            layer.add_object(
+                format!("{}", point),
+                Object::Dot(point).color(Fill::Solid(color.clone())),
+            );
+        }
+
+        self.put_layer_on_bottom("grid");
+    }
+
     pub fn debug_region(&mut self, region: &Region, color: Color) {
         let layer = self.layer_or_empty("debug plane");
 
         layer.add_object(
             format!("{}_corner_ss", region).as_str(),
-            Object::Dot(region.topleft()).color(Fill::Solid(color)),
+            Object::Dot(region.topleft()).color(Fill::Solid(color.clone())),
         );
         layer.add_object(
             format!("{}_corner_se", region).as_str(),
-            Object::Dot(region.topright().translated(1, 0)).color(Fill::Solid(color)),
+            Object::Dot(region.topright().translated(1, 0)).color(Fill::Solid(color.clone())),
         );
         layer.add_object(
             format!("{}_corner_ne", region).as_str(),
-            Object::Dot(region.bottomright().translated(1, 1)).color(Fill::Solid(color)),
+            Object::Dot(region.bottomright().translated(1, 1)).color(Fill::Solid(color.clone())),
         );
         layer.add_object(
             format!("{}_corner_nw", region).as_str(),
-            Object::Dot(region.bottomleft().translated(0, 1)).color(Fill::Solid(color)),
+            Object::Dot(region.bottomleft().translated(0, 1)).color(Fill::Solid(color.clone())),
         );
         layer.add_object(
             format!("{}_region", region).as_str(),
@@ -514,21 +1161,110 @@ impl Canvas {
         )
     }
 
+    /// Only render this layer (and others soloed before), to isolate it when debugging. Accepts patterns, see [`crate::layer_name_matches`].
+    pub fn solo_layer(&mut self, name: &str) {
+        self.soloed_layers.push(name.to_string());
+    }
+
+    pub fn unsolo_layers(&mut self) {
+        self.soloed_layers.clear();
+    }
+
+    /// Don't render this layer, without touching its `hidden` flag. Accepts patterns, see [`crate::layer_name_matches`].
+    pub fn mute_layer(&mut self, name: &str) {
+        self.muted_layers.push(name.to_string());
+    }
+
+    pub fn unmute_layer(&mut self, name: &str) {
+        self.muted_layers.retain(|muted| muted != name);
+    }
+
+    /// Whether a layer is let through by soloing and muting
+    fn is_audible(&self, layer: &Layer) -> bool {
+        let soloed: Vec<&str> = self.soloed_layers.iter().map(String::as_str).collect();
+        (soloed.is_empty() || layer.matches(&soloed))
+            && !self
+                .muted_layers
+                .iter()
+                .any(|muted| layer.matches(&[muted.as_str()]))
+    }
+
+    pub fn layers_matching(&self, patterns: &[&str]) -> Vec<&Layer> {
+        self.layers
+            .iter()
+            .filter(|layer| layer.matches(patterns))
+            .collect()
+    }
+
+    /// Rectangle covering the whole canvas, padding included, filled with the background color
+    pub(crate) fn background_rect(&self) -> svg::node::element::Rectangle {
+        let (origin_x, origin_y) = self.viewbox_origin();
+        svg::node::element::Rectangle::new()
+            .set("x", origin_x)
+            .set("y", origin_y)
+            .set("width", self.width())
+            .set("height", self.height())
+            .set_color(
+                "fill",
+                &self.background.clone().unwrap_or_default().render(&self.colormap),
+            )
+    }
+
     pub fn render(&mut self, render_background: bool) -> Result<String> {
-        let background_color = self.background.unwrap_or_default();
+        self.render_layers(&["*"], render_background)
+    }
+
+    /// How grid coordinates become SVG coordinates, according to the cell size, grid topology and projection
+    pub fn grid_layout(&self) -> GridLayout {
+        GridLayout {
+            cell_size: self.cell_size,
+            topology: self.grid_topology,
+            projection: self.projection,
+        }
+    }
+
+    /// Render only the layers matching the given patterns (see [`crate::layer_name_matches`]).
+    pub fn render_layers(&mut self, patterns: &[&str], render_background: bool) -> Result<String> {
+        Ok(self.render_document(patterns, render_background)?.to_string())
+    }
+
+    /// Like [`Canvas::render_layers`], but returns the document so that more elements can be added to it
+    pub(crate) fn render_document(
+        &mut self,
+        patterns: &[&str],
+        render_background: bool,
+    ) -> Result<svg::Document> {
+        for layer in self.all_layers() {
+            for (name, object) in &layer.objects {
+                object
+                    .object
+                    .ensure_renderable()
+                    .with_context(|| format!("Cannot render {} in layer {}", name, layer.name))?;
+            }
+        }
+
+        let (origin_x, origin_y) = self.viewbox_origin();
         let mut svg = svg::Document::new();
         if render_background {
-            svg = svg.add(
-                svg::node::element::Rectangle::new()
-                    .set("x", -(self.canvas_outter_padding as i32))
-                    .set("y", -(self.canvas_outter_padding as i32))
-                    .set("width", self.width())
-                    .set("height", self.height())
-                    .set("fill", background_color.render(&self.colormap)),
-            );
+            svg = svg.add(self.background_rect());
         }
-        for layer in self.layers.iter_mut().filter(|layer| !layer.hidden).rev() {
-            svg = svg.add(layer.render(self.colormap.clone(), self.cell_size, layer.object_sizes));
+        // layer transformations are applied around the center of the canvas
+        let layout = self.grid_layout();
+        let center = self.center().coords(layout);
+        let audible: Vec<bool> = self.layers.iter().map(|l| self.is_audible(l)).collect();
+        for (layer, _) in self
+            .layers
+            .iter_mut()
+            .zip(audible)
+            .filter(|(layer, audible)| *audible && !layer.hidden && layer.matches(patterns))
+            .rev()
+        {
+            svg = svg.add(layer.render(
+                self.colormap.clone(),
+                layout,
+                layer.object_sizes,
+                center,
+            ));
         }
 
         let mut defs = svg::node::element::Definitions::new();
@@ -536,27 +1272,85 @@ impl Canvas {
             defs = defs.add(filter.definition())
         }
 
+        for chain in self.unique_filter_chains() {
+            defs = defs.add(chain.definition())
+        }
+
         for pattern_fill in self.unique_pattern_fills() {
             if let Some(patterndef) = pattern_fill.pattern_definition(&self.colormap) {
                 defs = defs.add(patterndef)
             }
         }
 
-        let rendered = svg
+        for (clip, object_sizes) in self.unique_clips() {
+            defs = defs.add(clip.clip_path_definition(layout, object_sizes))
+        }
+
+        let document = svg
             .add(defs)
             .set(
                 "viewBox",
                 format!(
-                    "{0} {0} {1} {2}",
-                    -(self.canvas_outter_padding as i32),
+                    "{} {} {} {}",
+                    origin_x,
+                    origin_y,
                     self.width(),
                     self.height()
                 ),
             )
             .set("width", self.width())
-            .set("height", self.height())
-            .to_string();
+            .set("height", self.height());
+
+        Ok(document)
+    }
+
+    /// Serialize the whole scene (settings, colormap, layers and their objects) to JSON.
+    /// Fails if the canvas contains [`Object::RawSVG`] objects.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
 
-        Ok(rendered)
+    /// Write the scene to a JSON file, see [`Canvas::to_json`]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// Load a scene saved with [`Canvas::save`]
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// Part of the convex polygon that is closer to seed than to other (Sutherland-Hodgman clipping against their bisector)
+fn clip_to_closer_half(
+    polygon: &[(f32, f32)],
+    seed: (f32, f32),
+    other: (f32, f32),
+) -> Vec<(f32, f32)> {
+    let midpoint = ((seed.0 + other.0) / 2.0, (seed.1 + other.1) / 2.0);
+    let normal = (other.0 - seed.0, other.1 - seed.1);
+    // negative on seed's side of the bisector
+    let side = |(x, y): (f32, f32)| (x - midpoint.0) * normal.0 + (y - midpoint.1) * normal.1;
+
+    let mut clipped = vec![];
+    for (i, &current) in polygon.iter().enumerate() {
+        let next = polygon[(i + 1) % polygon.len()];
+        let (current_side, next_side) = (side(current), side(next));
+        if current_side <= 0.0 {
+            clipped.push(current);
+        }
+        if (current_side < 0.0) != (next_side < 0.0) && current_side != next_side {
+            let t = current_side / (current_side - next_side);
+            clipped.push((
+                current.0 + t * (next.0 - current.0),
+                current.1 + t * (next.1 - current.1),
+            ));
+        }
     }
+    clipped
 }
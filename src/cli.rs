@@ -1,6 +1,10 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Context as _, Result};
 use docopt::Docopt;
+use indexmap::IndexMap;
 use serde::Deserialize;
-use crate::{Canvas, ColorMapping};
+use crate::{palettes, Canvas, ColorMapping, ObjectKind, Stroke};
 
 const USAGE: &str = "
 ▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄▄
@@ -9,15 +13,22 @@ const USAGE: &str = "
 █▄▄▄█▄██▄█▄██▄█░████▄▄▄█▄███▄█▄██▄█▄█▄█▄▄▄█▄█▄▄█
 ▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀v?.?.?▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀
 
-Usage: shapemaker (image|video) [options] [--color <mapping>...] <file>
+Usage: shapemaker (image|video) [options] [--color <mapping>...] [--shape-weight <weight>...] [<file>]
+       shapemaker examples list
+       shapemaker examples render <name> [options] [<file>]
+       shapemaker gallery [options] [<file>]
        shapemaker --help
        shapemaker --version
 
 Options:
+    --config <file>                TOML or YAML file giving values to any of these options, named without the leading dashes,
+                                   e.g. grid-size = '16x10' or render-grid = true. Use output to set <file>.
+                                   Options given on the command line take precedence over the ones in the file.
     --resolution <pixelcount>      Size of the image (or frames)'s largest dimension in pixels [default: 1000]
-    --colors <file>                JSON file mapping color names to hex values
+    --colors <file>                JSON or CSS file mapping color names to hex values, a base16 YAML scheme, or an Xresources, kitty or Windows Terminal color scheme
                                    The supported color names are: black, white, red, green, blue, yellow, orange, purple, brown, pink, gray, and cyan.
     -c --color <mapping>           Color mapping in the form of <color>:<hex>. Can be used multiple times.
+    --palette <name>               Use a preset palette instead: viridis, solarized or okabe-ito
     --grid-size <WIDTHxHEIGHT>     Size of the grid (number of anchor points) [default: 3x3]
                                    Putting one of the dimensions to 1 can cause a crash.
     --cell-size <size>             Size of a cell in pixels [default: 50]
@@ -26,19 +37,48 @@ Options:
     --small-circle-radius <size>   Radius of small circles in pixels [default: 5]
     --dot-radius <size>            Radius of dots in pixels [default: 2]
     --empty-shape-stroke <size>    Width of the stroke when a closed shape is not filled [default: 0.5]
-    --render-grid                  Render the grid of anchor points
+    --render-grid                  Render the grid of anchor points, as dots below all layers
+    --grid-dot-radius <size>       With --render-grid, radius of the grid's dots in pixels [default: 1]
+    --grid-color <color>           With --render-grid, color name or CSS color of the grid's dots [default: gray]
+    --only-layers <patterns>       Only render layers matching these comma-separated patterns, e.g. drums*,bass
+    --skip-layers <patterns>       Don't render layers matching these comma-separated patterns
+    --layers <names>               Comma-separated layers to render, e.g. hatches,splines. Patterns work too.
+                                   Unlike --only-layers, this is a selection made when rendering, that hooks can't undo by soloing other layers.
     --objects-count <range>        Number of objects to render [default: 3..6]
     --polygon-vertices <range>     Number of vertices for polygons [default: 2..6]
+    --shapes <kinds>               Only generate objects of these comma-separated kinds, e.g. circles,lines,polygons
+                                   Kinds are polygon, big-circle, small-circle, dot, curve-inward, curve-outward and line,
+                                   or the groups circle and curve. Plurals work too.
+    --shape-weight <weight>        How often an object kind is generated relative to the others, in the form <kind>=<weight>,
+                                   e.g. curves=3. Kinds have a weight of 1 by default. Can be used multiple times.
+                                   Along with --shapes, applies to the random images of image --count and gallery,
+                                   and to the shapes layers of --scene.
+    --seed <number>                Seed for random generation: the same seed and options give the same artwork
 
         Note: <range>s are inclusive on both ends
 
+    Image and gallery-specific:
+    --count <number>               Generate this many images, each with its own seed, instead of one.
+                                   With gallery, number of images in the contact sheet, saved with an index.html in the <file> directory (24 by default)
+    --out <template>               With --count, where to save each image instead of <file>.
+                                   {seed} and {index} are replaced by the image's seed and number, e.g. art/{seed}.png
+                                   Seeds are also recorded in seeds.txt, next to the images.
+
     Video-specific:
-    --workers <number>             Number of parallel threads to use for rendering [default: 8]
+    --workers <number>             Number of parallel threads to use for rendering, also used by image --count [default: 8]
     --fps <fps>                    Frames per second [default: 30]
     --audio <file>                 Audio file to use for the video
     --duration <seconds>           Number of seconds to render. If not set, the video will be as long as the audio file.
     --start <seconds>              Start the video at this time in seconds. [default: 0]
+    --scene <file>                 YAML or JSON file describing the initial canvas and hooks of the video, see the scene module
+    --plugins <directory>          Directory of .wasm plugins whose generators and hooks --scene files can use, see the plugins module.
+                                   Needs shapemaker to be built with the plugins feature. [default: plugins]
+    --onion-skin <frames>          Draw this many previous frames, translucent, under each frame, to check motion smoothness
     --preview                      Only create preview.html, not the output video. Preview.html will be created in the same directory as <file>, but <file> will not be created.
+    --watch                        With --preview, render the previewed frames again when the --colors or --scene file changes
+    --composition <file>           YAML, JSON or TOML file mapping composition names to lists of layers (names or patterns).
+                                   Each composition is rendered to its own video, without background, in the <file> directory.
+    --compare <directory>          With --preview, show the frames of a previous render (the SVGs in its frames directory) next to the new ones
     --sync-with <directory>        Directory containing the audio files to sync to.
                                    The directory must contain:
                                    - stems/(instrument name).wav — stems
@@ -49,9 +89,12 @@ Options:
 
 ";
 
-pub fn cli_args() -> Args {
-    let args: Args = Docopt::new(USAGE.replace("?.?.?", env!("CARGO_PKG_VERSION")))
-        .and_then(|d| d.deserialize())
+pub fn cli_args() -> Result<Args> {
+    let (argv, output) =
+        with_config_file(std::env::args().collect()).context("Could not load the config file")?;
+
+    let mut args: Args = Docopt::new(USAGE.replace("?.?.?", env!("CARGO_PKG_VERSION")))
+        .and_then(|d| d.argv(argv).deserialize())
         .unwrap_or_else(|e| e.exit());
 
     if args.flag_version {
@@ -59,24 +102,111 @@ pub fn cli_args() -> Args {
         std::process::exit(0);
     }
 
-    args
+    if args.arg_file.is_empty() && !args.cmd_list && args.flag_out.is_none() {
+        args.arg_file = output.ok_or_else(|| {
+            format_err!("No output file given: pass <file>, or set output in the config file")
+        })?;
+    }
+
+    Ok(args)
+}
+
+/// A value in a --config file
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ConfigValue {
+    Switch(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    List(Vec<String>),
+}
+
+/// Adds the options of the --config file, if any, that aren't already given in `argv`.
+/// Also returns the config file's output, used when <file> is not given.
+fn with_config_file(mut argv: Vec<String>) -> Result<(Vec<String>, Option<String>)> {
+    let config_path = argv
+        .iter()
+        .enumerate()
+        .find_map(|(i, arg)| match arg.as_str() {
+            "--config" => argv.get(i + 1).cloned(),
+            _ => arg.strip_prefix("--config=").map(String::from),
+        });
+    let Some(config_path) = config_path else {
+        return Ok((argv, None));
+    };
+
+    let contents = std::fs::read_to_string(&config_path)
+        .with_context(|| format!("Could not read {config_path}"))?;
+    let mut config: IndexMap<String, ConfigValue> = match Path::new(&config_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some("toml") => toml::from_str(&contents)?,
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents)?,
+        _ => {
+            return Err(format_err!(
+                "{config_path} should be a .toml, .yaml or .yml file"
+            ))
+        }
+    };
+
+    let output = match config.shift_remove("output") {
+        Some(ConfigValue::Text(output)) => Some(output),
+        Some(other) => return Err(format_err!("output should be a file path, not {other:?}")),
+        None => None,
+    };
+
+    for (option, value) in config {
+        let flag = format!("--{option}");
+        let given_on_command_line = argv.iter().any(|arg| {
+            arg == &flag
+                || arg.starts_with(&format!("{flag}="))
+                || (option == "color" && arg == "-c")
+        });
+        if given_on_command_line {
+            continue;
+        }
+
+        match value {
+            ConfigValue::Switch(true) => argv.push(flag),
+            ConfigValue::Switch(false) => {}
+            ConfigValue::Integer(number) => argv.extend([flag, number.to_string()]),
+            ConfigValue::Float(number) => argv.extend([flag, number.to_string()]),
+            ConfigValue::Text(text) => argv.extend([flag, text]),
+            ConfigValue::List(values) => {
+                for value in values {
+                    argv.extend([flag.clone(), value]);
+                }
+            }
+        }
+    }
+
+    Ok((argv, output))
 }
 
-pub fn canvas_from_cli(args: &Args) -> Canvas {
+pub fn canvas_from_cli(args: &Args) -> Result<Canvas> {
     let mut canvas = Canvas::new(vec![]);
-    canvas.colormap = load_colormap(args);
-    set_canvas_settings_from_args(args, &mut canvas);
-    canvas
+    canvas.colormap = load_colormap(args)?;
+    set_canvas_settings_from_args(args, &mut canvas)?;
+    Ok(canvas)
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Args {
     pub cmd_image: bool,
     pub cmd_video: bool,
+    pub cmd_examples: bool,
+    pub cmd_list: bool,
+    pub cmd_render: bool,
+    pub cmd_gallery: bool,
+    pub arg_name: String,
     pub arg_file: String,
     pub flag_version: bool,
+    pub flag_config: Option<String>,
     pub flag_color: Vec<String>,
     pub flag_colors: Option<String>,
+    pub flag_palette: Option<String>,
     pub flag_grid_size: Option<String>,
     pub flag_cell_size: Option<usize>,
     pub flag_canvas_padding: Option<usize>,
@@ -85,8 +215,19 @@ pub struct Args {
     pub flag_dot_radius: Option<f32>,
     pub flag_empty_shape_stroke: Option<f32>,
     pub flag_render_grid: bool,
+    pub flag_grid_dot_radius: Option<f32>,
+    pub flag_grid_color: Option<String>,
+    pub flag_only_layers: Option<String>,
+    pub flag_skip_layers: Option<String>,
+    pub flag_layers: Option<String>,
+    pub flag_composition: Option<String>,
     pub flag_objects_count: Option<String>,
     pub flag_polygon_vertices: Option<String>,
+    pub flag_shapes: Option<String>,
+    pub flag_shape_weight: Vec<String>,
+    pub flag_seed: Option<u64>,
+    pub flag_count: Option<usize>,
+    pub flag_out: Option<String>,
     pub flag_fps: Option<usize>,
     pub flag_sync_with: Option<String>,
     pub flag_audio: Option<String>,
@@ -95,9 +236,95 @@ pub struct Args {
     pub flag_duration: Option<usize>,
     pub flag_start: Option<usize>,
     pub flag_preview: bool,
+    pub flag_onion_skin: Option<usize>,
+    pub flag_compare: Option<String>,
+    pub flag_scene: Option<String>,
+    pub flag_plugins: Option<String>,
+    pub flag_watch: bool,
+}
+
+impl Args {
+    /// Files that --watch looks at: the ones the video is built from
+    pub fn watched_files(&self) -> Vec<PathBuf> {
+        [&self.flag_colors, &self.flag_scene]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::from)
+            .filter(|path| path.exists())
+            .collect()
+    }
+}
+
+/// Fills the canvas' grid layer according to --render-grid, --grid-dot-radius and --grid-color.
+/// Call this once the other layers exist, so that the grid ends up below them.
+pub fn draw_grid_from_args(args: &Args, canvas: &mut Canvas) -> Result<()> {
+    if args.flag_render_grid {
+        let color = canvas
+            .colormap
+            .color(args.flag_grid_color.as_deref().unwrap_or("gray"))?;
+        canvas.draw_grid(args.flag_grid_dot_radius.unwrap_or(1.0), color);
+    }
+    Ok(())
 }
 
-fn set_canvas_settings_from_args(args: &Args, canvas: &mut Canvas) {
+/// Layers of a composition, as a list or comma-separated
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum CompositionLayers {
+    List(Vec<String>),
+    Text(String),
+}
+
+/// Layer patterns to render, from --layers
+pub fn layers_from_args(args: &Args) -> Vec<&str> {
+    match &args.flag_layers {
+        Some(layers) => layers.split(',').map(str::trim).collect(),
+        None => vec!["*"],
+    }
+}
+
+/// Parses a --composition file: layer names or patterns, by composition name
+pub fn load_compositions(path: &str) -> Result<IndexMap<String, Vec<String>>> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("Could not read {path}"))?;
+    let compositions: IndexMap<String, CompositionLayers> =
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents)?,
+            Some("yaml" | "yml") => serde_yaml::from_str(&contents)?,
+            Some("json") => serde_json::from_str(&contents)?,
+            _ => {
+                return Err(format_err!(
+                    "{path} should be a .yaml, .yml, .json or .toml file"
+                ))
+            }
+        };
+    Ok(compositions
+        .into_iter()
+        .map(|(name, layers)| {
+            let layers = match layers {
+                CompositionLayers::List(layers) => layers,
+                CompositionLayers::Text(layers) => layers
+                    .split(',')
+                    .map(|name| name.trim().to_string())
+                    .collect(),
+            };
+            (name, layers)
+        })
+        .collect())
+}
+
+/// Solos and mutes layers according to --only-layers and --skip-layers
+pub fn set_layer_filters_from_args(args: &Args, canvas: &mut Canvas) {
+    for pattern in args.flag_only_layers.iter().flat_map(|p| p.split(',')) {
+        canvas.solo_layer(pattern.trim());
+    }
+    for pattern in args.flag_skip_layers.iter().flat_map(|p| p.split(',')) {
+        canvas.mute_layer(pattern.trim());
+    }
+}
+
+fn set_canvas_settings_from_args(args: &Args, canvas: &mut Canvas) -> Result<()> {
+    set_layer_filters_from_args(args, canvas);
     if let Some(dimensions) = &args.flag_grid_size {
         let mut split = dimensions.split('x');
         let width = split.next().unwrap().parse::<usize>().unwrap();
@@ -110,18 +337,20 @@ fn set_canvas_settings_from_args(args: &Args, canvas: &mut Canvas) {
     if let Some(canvas_padding) = args.flag_canvas_padding {
         canvas.canvas_outter_padding = canvas_padding;
     }
+    let mut object_sizes = canvas.object_sizes;
     if let Some(line_width) = args.flag_line_width {
-        canvas.object_sizes.default_line_width = line_width;
+        object_sizes.default_line_width = line_width;
     }
     if let Some(small_circle_radius) = args.flag_small_circle_radius {
-        canvas.object_sizes.small_circle_radius = small_circle_radius;
+        object_sizes.small_circle_radius = small_circle_radius;
     }
     if let Some(dot_radius) = args.flag_dot_radius {
-        canvas.object_sizes.dot_radius = dot_radius;
+        object_sizes.dot_radius = dot_radius;
     }
     if let Some(empty_shape_stroke) = args.flag_empty_shape_stroke {
-        canvas.object_sizes.empty_shape_stroke_width = empty_shape_stroke;
+        object_sizes.empty_shape_stroke = Stroke::solid(empty_shape_stroke);
     }
+    canvas.set_object_sizes(object_sizes);
     if let Some(objects_count) = &args.flag_objects_count {
         let mut split = objects_count.split("..");
         let min = split.next().unwrap().parse::<usize>().unwrap();
@@ -135,12 +364,100 @@ fn set_canvas_settings_from_args(args: &Args, canvas: &mut Canvas) {
         let max = split.next().unwrap().parse::<usize>().unwrap();
         canvas.polygon_vertices_range = min..(max + 1);
     }
+    if let Some(shapes) = &args.flag_shapes {
+        canvas.object_kind_weights.clear();
+        for name in shapes.split(',') {
+            for kind in object_kinds(name)? {
+                canvas.object_kind_weights.insert(kind, 1.0);
+            }
+        }
+    }
+    for shape_weight in &args.flag_shape_weight {
+        let (name, weight) = shape_weight.split_once('=').ok_or_else(|| {
+            format_err!("Invalid shape weight {shape_weight}: should be in the form <kind>=<weight>")
+        })?;
+        let weight = match weight.trim().parse::<f32>() {
+            Ok(weight) if weight >= 0.0 => weight,
+            _ => {
+                return Err(format_err!(
+                    "Invalid shape weight {weight}: should be a positive number"
+                ))
+            }
+        };
+        for kind in object_kinds(name)? {
+            canvas.object_kind_weights.insert(kind, weight);
+        }
+    }
+    if !canvas
+        .object_kind_weights
+        .values()
+        .any(|weight| *weight > 0.0)
+    {
+        return Err(format_err!(
+            "At least one kind of object should have a positive weight"
+        ));
+    }
+    Ok(())
+}
+
+fn object_kinds(name: &str) -> Result<Vec<ObjectKind>> {
+    ObjectKind::from_name(name).ok_or_else(|| {
+        format_err!(
+            "Unknown object kind: {name}. Available kinds: polygon, big-circle, small-circle, dot, curve-inward, curve-outward, line, circle and curve"
+        )
+    })
 }
 
-fn load_colormap(args: &Args) -> ColorMapping {
-    if let Some(file) = &args.flag_colors {
-        ColorMapping::from_file(file.into())
+fn load_colormap(args: &Args) -> Result<ColorMapping> {
+    Ok(if let Some(file) = &args.flag_colors {
+        ColorMapping::from_file(file.into())?
+    } else if let Some(name) = &args.flag_palette {
+        palettes::by_name(name)
+            .ok_or_else(|| {
+                format_err!(
+                    "Unknown palette: {}. Available palettes: {}",
+                    name,
+                    palettes::ALL.map(|palette| palette.name).join(", ")
+                )
+            })?
+            .colormap()
     } else {
         ColorMapping::from_cli_args(&args.flag_color)
-    }
+    })
+}
+
+#[test]
+fn test_with_config_file() {
+    let config_path = std::env::temp_dir().join(format!("shapemaker-{}.toml", std::process::id()));
+    std::fs::write(
+        &config_path,
+        "output = \"out.svg\"\ngrid-size = \"4x4\"\ncanvas-padding = 10\nobjects-count = \"3..5\"\nrender-grid = true\nclip = false\ncolor = [\"red:#f00\", \"blue:#00f\"]\n",
+    )
+    .unwrap();
+    let argv = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+    let (args, output) = with_config_file(argv(&[
+        "shapemaker",
+        "image",
+        "--grid-size=2x2",
+        "-c",
+        "green:#0f0",
+        "--config",
+        config_path.to_str().unwrap(),
+    ]))
+    .unwrap();
+    std::fs::remove_file(&config_path).unwrap();
+
+    assert_eq!(output.as_deref(), Some("out.svg"));
+    assert_eq!(
+        args[7..],
+        argv(&[
+            "--canvas-padding",
+            "10",
+            "--objects-count",
+            "3..5",
+            "--render-grid"
+        ])
+    );
+    assert!(with_config_file(argv(&["shapemaker", "--config", "config.json"])).is_err());
 }
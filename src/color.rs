@@ -5,14 +5,16 @@ use std::{
     path::PathBuf,
 };
 
+use anyhow::{format_err, Context as _, Result};
 use rand::Rng;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
-#[derive(Debug, Clone, Copy, PartialEq, EnumIter)]
+use crate::random::rng;
+
+#[derive(Debug, Clone, PartialEq, EnumIter, Serialize, Deserialize)]
 pub enum Color {
     Black,
     White,
@@ -26,9 +28,10 @@ pub enum Color {
     Cyan,
     Pink,
     Gray,
+    /// A color from `ColorMapping::extra`, or any CSS color if the mapping has no such entry
+    Custom(String),
 }
 
-#[wasm_bindgen]
 pub fn random_color(except: Option<Color>) -> Color {
     let all = [
         Color::Black,
@@ -46,17 +49,20 @@ pub fn random_color(except: Option<Color>) -> Color {
     ];
     let candidates = all
         .iter()
-        .filter(|c| match except {
+        .filter(|c| match &except {
             None => true,
-            Some(color) => &&color != c,
+            Some(color) => &color != c,
         })
         .collect::<Vec<_>>();
 
-    *candidates[rand::thread_rng().gen_range(0..candidates.len())]
+    candidates[rng().gen_range(0..candidates.len())].clone()
 }
 
+/// All named colors, without custom ones
 pub fn all_colors() -> Vec<Color> {
-    Color::iter().collect()
+    Color::iter()
+        .filter(|color| !matches!(color, Color::Custom(_)))
+        .collect()
 }
 
 impl Default for Color {
@@ -65,28 +71,39 @@ impl Default for Color {
     }
 }
 
-impl From<&str> for Color {
-    fn from(s: &str) -> Self {
-        match s {
-            "black" => Color::Black,
-            "white" => Color::White,
-            "red" => Color::Red,
-            "green" => Color::Green,
-            "blue" => Color::Blue,
-            "yellow" => Color::Yellow,
-            "orange" => Color::Orange,
-            "purple" => Color::Purple,
-            "brown" => Color::Brown,
-            "cyan" => Color::Cyan,
-            "pink" => Color::Pink,
-            "gray" => Color::Gray,
-            _ => panic!("Invalid color: {}", s),
+/// One of the twelve named colors, or a custom one if the name is a CSS color.
+/// Use [`ColorMapping::color`] to also accept the mapping's extra colors.
+impl TryFrom<&str> for Color {
+    type Error = anyhow::Error;
+
+    fn try_from(name: &str) -> Result<Self> {
+        match Color::named(name) {
+            Some(color) => Ok(color),
+            None if csscolorparser::parse(name.trim()).is_ok() => Ok(Color::custom(name)),
+            None => Err(format_err!(
+                "Unknown color {name}, expected one of {} or a CSS color",
+                all_colors()
+                    .iter()
+                    .map(Color::name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
         }
     }
 }
 
 impl Color {
-    pub fn render(self, mapping: &ColorMapping) -> String {
+    /// The named color called name, if any
+    pub fn named(name: &str) -> Option<Color> {
+        all_colors().into_iter().find(|color| color.name() == name)
+    }
+
+    /// Custom color named name, see [`Color::Custom`]
+    pub fn custom(name: &str) -> Color {
+        Color::Custom(name.to_string())
+    }
+
+    pub fn render(&self, mapping: &ColorMapping) -> String {
         match self {
             Color::Black => mapping.black.to_string(),
             Color::White => mapping.white.to_string(),
@@ -100,6 +117,11 @@ impl Color {
             Color::Cyan => mapping.cyan.to_string(),
             Color::Pink => mapping.pink.to_string(),
             Color::Gray => mapping.gray.to_string(),
+            Color::Custom(name) => mapping
+                .extra
+                .get(name)
+                .map_or(name.as_str(), String::as_str)
+                .to_string(),
         }
     }
 
@@ -117,13 +139,197 @@ impl Color {
             Color::Cyan => "cyan",
             Color::Pink => "pink",
             Color::Gray => "gray",
+            Color::Custom(name) => name,
         }
         .to_string()
     }
+
+    /// CSS color between this one (t = 0) and other (t = 1), interpolated in OKLab space so that crossfades don't go through muddy grays.
+    /// Colors that can't be parsed snap from one to the other halfway through.
+    pub fn lerp(&self, other: &Color, t: f32, mapping: &ColorMapping) -> String {
+        lerp_css(&self.render(mapping), &other.render(mapping), t)
+    }
+
+    /// Hex value of the color made lighter by amount (0 to 1) of OKLCH lightness
+    pub fn lighten(&self, amount: f32, mapping: &ColorMapping) -> String {
+        self.adjust_oklch(mapping, |lightness, _, _| *lightness += amount as f64)
+    }
+
+    /// Hex value of the color made darker by amount (0 to 1) of OKLCH lightness
+    pub fn darken(&self, amount: f32, mapping: &ColorMapping) -> String {
+        self.lighten(-amount, mapping)
+    }
+
+    /// Hex value of the color with its chroma scaled by 1 + amount. Negative amounts desaturate.
+    pub fn saturate(&self, amount: f32, mapping: &ColorMapping) -> String {
+        self.adjust_oklch(mapping, |_, chroma, _| {
+            *chroma *= (1.0 + amount as f64).max(0.0)
+        })
+    }
+
+    /// Hex value of the color with its hue turned by the given angle, in degrees
+    pub fn rotate_hue(&self, degrees: f32, mapping: &ColorMapping) -> String {
+        self.adjust_oklch(mapping, |_, _, hue| *hue += (degrees as f64).to_radians())
+    }
+
+    /// The named color that is the most readable on the given background, see `ColorMapping::contrast_ratio`
+    pub fn best_contrast_on(background: &Color, mapping: &ColorMapping) -> Color {
+        all_colors()
+            .into_iter()
+            .max_by(|a, b| {
+                mapping
+                    .contrast_ratio(a, background)
+                    .total_cmp(&mapping.contrast_ratio(b, background))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Renders the color and adjusts it with `adjust_oklch`
+    fn adjust_oklch(
+        &self,
+        mapping: &ColorMapping,
+        adjust: impl FnOnce(&mut f64, &mut f64, &mut f64),
+    ) -> String {
+        adjust_oklch(&self.render(mapping), adjust)
+    }
+}
+
+/// Changes the OKLCH lightness, chroma and hue (in radians) of a CSS color and converts it back to hex.
+/// Colors that can't be parsed are returned unchanged.
+fn adjust_oklch(css: &str, adjust: impl FnOnce(&mut f64, &mut f64, &mut f64)) -> String {
+    let Ok(color) = csscolorparser::parse(css.trim()) else {
+        return css.to_string();
+    };
+
+    let (mut lightness, a, b, alpha) = color.to_oklaba();
+    let (mut chroma, mut hue) = (a.hypot(b), b.atan2(a));
+    adjust(&mut lightness, &mut chroma, &mut hue);
+
+    csscolorparser::Color::from_oklaba(
+        lightness.clamp(0.0, 1.0),
+        chroma * hue.cos(),
+        chroma * hue.sin(),
+        alpha,
+    )
+    .clamp()
+    .to_hex_string()
+}
+
+/// Splits the alpha channel out of #RGBA and #RRGGBBAA hex colors, as an opacity between 0 and 1.
+/// Not all SVG renderers understand alpha in hex colors, but all of them support fill-opacity and friends.
+/// Other colors are returned as is, with an opacity of 1.
+pub fn split_alpha(css: &str) -> (String, f32) {
+    let hex = css.trim().trim_start_matches('#');
+    if !css.trim().starts_with('#') || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return (css.to_string(), 1.0);
+    }
+
+    let (rgb, alpha) = match hex.len() {
+        4 => (&hex[..3], hex[3..].repeat(2)),
+        8 => (&hex[..6], hex[6..].to_string()),
+        _ => return (css.to_string(), 1.0),
+    };
+    match u8::from_str_radix(&alpha, 16) {
+        Ok(alpha) => (format!("#{}", rgb), alpha as f32 / 255.0),
+        Err(_) => (css.to_string(), 1.0),
+    }
+}
+
+/// CSS declaration setting property (e.g. fill or stroke) to the color, with its alpha channel (see `split_alpha`) in the corresponding -opacity property
+pub fn css_color_declaration(property: &str, css: &str) -> String {
+    match split_alpha(css) {
+        (color, alpha) if alpha >= 1.0 => format!("{}: {};", property, color),
+        (color, alpha) => format!("{}: {}; {}-opacity: {};", property, color, property, alpha),
+    }
+}
+
+/// Color attributes on SVG elements that also work with alpha channels, see `split_alpha`
+pub trait SetColor: svg::Node + Sized {
+    /// Sets the color attribute, and the corresponding -opacity attribute if the color has an alpha channel
+    fn set_color(mut self, attribute: &str, css: &str) -> Self {
+        let (color, alpha) = split_alpha(css);
+        self.assign(attribute, color);
+        if alpha < 1.0 {
+            // stop-color goes with stop-opacity
+            self.assign(
+                format!("{}-opacity", attribute.trim_end_matches("-color")),
+                alpha,
+            );
+        }
+        self
+    }
+}
+
+impl<N: svg::Node> SetColor for N {}
+
+/// Interpolates between two CSS colors in OKLab space, see `Color::lerp`
+pub fn lerp_css(from: &str, to: &str, t: f32) -> String {
+    match (
+        csscolorparser::parse(from.trim()),
+        csscolorparser::parse(to.trim()),
+    ) {
+        (Ok(from), Ok(to)) => from
+            .interpolate_oklab(&to, t.clamp(0.0, 1.0) as f64)
+            .to_hex_string(),
+        _ if t < 0.5 => from.to_string(),
+        _ => to.to_string(),
+    }
+}
+
+/// Colors at positions between 0 and 1, to map a value to a color scale, e.g. loudness to a heat map.
+/// Colors between stops are interpolated in OKLab space.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ColorRamp {
+    /// Position and CSS color of each stop, ordered by position
+    pub stops: Vec<(f32, String)>,
+}
+
+impl ColorRamp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_stop(self, position: f32, color: &str) -> Self {
+        let mut stops = self.stops;
+        stops.push((position, color.to_string()));
+        stops.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        Self { stops }
+    }
+
+    /// Evenly spaced stops, from the first color at 0 to the last one at 1
+    pub fn from_colors(colors: &[Color], mapping: &ColorMapping) -> Self {
+        let last = colors.len().max(2) - 1;
+        Self {
+            stops: colors
+                .iter()
+                .enumerate()
+                .map(|(i, color)| (i as f32 / last as f32, color.render(mapping)))
+                .collect(),
+        }
+    }
+
+    /// CSS color at the given position. Positions outside of the stops get the nearest stop's color.
+    pub fn at(&self, position: f32) -> String {
+        let after = self.stops.iter().position(|(at, _)| *at > position);
+        match after {
+            _ if self.stops.is_empty() => String::new(),
+            Some(0) => self.stops[0].1.clone(),
+            None => self.stops[self.stops.len() - 1].1.clone(),
+            Some(i) => {
+                let ((from_at, from), (to_at, to)) = (&self.stops[i - 1], &self.stops[i]);
+                lerp_css(from, to, (position - from_at) / (to_at - from_at))
+            }
+        }
+    }
+
+    /// Color at the given position, as a `Color::Custom` holding the CSS color
+    pub fn color_at(&self, position: f32) -> Color {
+        Color::Custom(self.at(position))
+    }
 }
 
 #[wasm_bindgen(getter_with_clone)]
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct ColorMapping {
     pub black: String,
     pub white: String,
@@ -137,6 +343,10 @@ pub struct ColorMapping {
     pub cyan: String,
     pub pink: String,
     pub gray: String,
+    /// Colors beyond the twelve named ones, used by `Color::Custom`
+    #[wasm_bindgen(skip)]
+    #[serde(default)]
+    pub extra: HashMap<String, String>,
 }
 
 #[wasm_bindgen]
@@ -155,6 +365,7 @@ impl ColorMapping {
             pink: "pink".to_string(),
             gray: "gray".to_string(),
             cyan: "cyan".to_string(),
+            extra: HashMap::new(),
         }
     }
 
@@ -173,6 +384,15 @@ impl ColorMapping {
 }
 
 impl ColorMapping {
+    /// The color called name: one of the twelve named ones, one of the extra colors or a CSS color
+    pub fn color(&self, name: &str) -> Result<Color> {
+        if self.extra.contains_key(name) {
+            Ok(Color::custom(name))
+        } else {
+            Color::try_from(name)
+        }
+    }
+
     pub fn from_cli_args(args: &Vec<String>) -> ColorMapping {
         let mut colormap: HashMap<String, String> = HashMap::new();
         for mapping in args {
@@ -188,6 +408,93 @@ impl ColorMapping {
         ColorMapping::from_hashmap(colormap)
     }
 
+    /// Mapping between this one (t = 0) and other (t = 1), every color being interpolated with `Color::lerp`.
+    /// Extra colors only present in one of the mappings are kept as is.
+    pub fn lerp(&self, other: &ColorMapping, t: f32) -> ColorMapping {
+        let mut extra = self.extra.clone();
+        for (name, value) in &other.extra {
+            let lerped = match self.extra.get(name) {
+                Some(from) => lerp_css(from, value, t),
+                None => value.clone(),
+            };
+            extra.insert(name.clone(), lerped);
+        }
+
+        ColorMapping {
+            black: lerp_css(&self.black, &other.black, t),
+            white: lerp_css(&self.white, &other.white, t),
+            red: lerp_css(&self.red, &other.red, t),
+            green: lerp_css(&self.green, &other.green, t),
+            blue: lerp_css(&self.blue, &other.blue, t),
+            yellow: lerp_css(&self.yellow, &other.yellow, t),
+            orange: lerp_css(&self.orange, &other.orange, t),
+            purple: lerp_css(&self.purple, &other.purple, t),
+            brown: lerp_css(&self.brown, &other.brown, t),
+            cyan: lerp_css(&self.cyan, &other.cyan, t),
+            pink: lerp_css(&self.pink, &other.pink, t),
+            gray: lerp_css(&self.gray, &other.gray, t),
+            extra,
+        }
+    }
+
+    /// WCAG contrast ratio between two colors, from 1 (same luminance) to 21 (black on white).
+    /// Text should have a ratio of at least 4.5 with its background to be readable. Colors that can't be parsed have a ratio of 1.
+    pub fn contrast_ratio(&self, a: &Color, b: &Color) -> f32 {
+        let luminance = |color: &Color| {
+            csscolorparser::parse(color.render(self).trim())
+                .map(|color| {
+                    let (r, g, b, _) = color.to_linear_rgba();
+                    0.2126 * r + 0.7152 * g + 0.0722 * b
+                })
+                .ok()
+        };
+
+        match (luminance(a), luminance(b)) {
+            (Some(a), Some(b)) => ((a.max(b) + 0.05) / (a.min(b) + 0.05)) as f32,
+            _ => 1.0,
+        }
+    }
+
+    /// Every color with its perceived lightness flipped: black becomes white, dark blue becomes light blue, and so on. Hues and saturation are kept.
+    pub fn inverted(&self) -> ColorMapping {
+        self.map_values(|_, value| {
+            adjust_oklch(value, |lightness, _, _| *lightness = 1.0 - *lightness)
+        })
+    }
+
+    /// Counterpart of a light palette for dark backgrounds: black, white and gray are inverted, and other colors are made lighter so that they stand out on black while keeping their hue.
+    pub fn dark_variant(&self) -> ColorMapping {
+        self.map_values(|name, value| match name {
+            "black" | "white" | "gray" => {
+                adjust_oklch(value, |lightness, _, _| *lightness = 1.0 - *lightness)
+            }
+            _ => adjust_oklch(value, |lightness, _, _| *lightness = 0.5 + *lightness / 2.0),
+        })
+    }
+
+    /// Mapping with every value replaced by `transform(color name, value)`, including extra colors
+    fn map_values(&self, transform: impl Fn(&str, &str) -> String) -> ColorMapping {
+        ColorMapping {
+            black: transform("black", &self.black),
+            white: transform("white", &self.white),
+            red: transform("red", &self.red),
+            green: transform("green", &self.green),
+            blue: transform("blue", &self.blue),
+            yellow: transform("yellow", &self.yellow),
+            orange: transform("orange", &self.orange),
+            purple: transform("purple", &self.purple),
+            brown: transform("brown", &self.brown),
+            cyan: transform("cyan", &self.cyan),
+            pink: transform("pink", &self.pink),
+            gray: transform("gray", &self.gray),
+            extra: self
+                .extra
+                .iter()
+                .map(|(name, value)| (name.clone(), transform(name, value)))
+                .collect(),
+        }
+    }
+
     pub fn from_hashmap(hashmap: HashMap<String, String>) -> ColorMapping {
         ColorMapping {
             black: hashmap
@@ -238,37 +545,167 @@ impl ColorMapping {
                 .get("gray")
                 .unwrap_or(&ColorMapping::default().gray)
                 .to_string(),
+            extra: hashmap
+                .iter()
+                .filter(|(name, _)| Color::named(name).is_none())
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
         }
     }
 
-    pub fn from_file(path: PathBuf) -> ColorMapping {
-        match path.extension().map(|e| e.to_str().unwrap()) {
-            Some("css") => ColorMapping::from_css_file(path),
-            Some("json") => ColorMapping::from_json_file(path),
-            ext => panic!(
-                "Invalid colormap file format. Must be css or json, is {:?}.",
-                ext
-            ),
+    /// Loads a mapping from a CSS, JSON, base16 YAML or terminal color scheme file, depending on its extension.
+    /// JSON files with bright colors (brightBlack, …) are read as Windows Terminal schemes, and files with any other extension (.Xresources, kitty's .conf, …) as terminal schemes.
+    pub fn from_file(path: PathBuf) -> Result<ColorMapping> {
+        let mapping = match path.extension().and_then(|e| e.to_str()) {
+            Some("css") => ColorMapping::from_css_file(path.clone()),
+            Some("json") => ColorMapping::from_json_file(path.clone()),
+            Some("yaml" | "yml") => ColorMapping::from_base16_yaml(path.clone()),
+            _ => ColorMapping::from_terminal_scheme(path.clone()),
+        };
+        mapping.with_context(|| format!("Could not load colors from {}", path.display()))
+    }
+
+    /// Loads a base16 scheme file (https://github.com/chriskempson/base16), see [`ColorMapping::from_base16`].
+    pub fn from_base16_yaml(path: PathBuf) -> Result<ColorMapping> {
+        ColorMapping::from_base16(&std::fs::read_to_string(path)?)
+    }
+
+    /// Reads a base16 scheme.
+    /// base08 to base0F go to red, orange, yellow, green, cyan, blue, purple and brown, base00 to black, base03 to gray and base07 to white.
+    /// All base0X entries are also available as custom colors, e.g. `Color::custom("base0D")`.
+    pub fn from_base16(content: &str) -> Result<ColorMapping> {
+        let yaml: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(content)?;
+        let scheme: HashMap<String, String> = yaml
+            .into_iter()
+            .filter(|(key, _)| key.starts_with("base"))
+            .filter_map(|(key, value)| {
+                let hex = match value {
+                    serde_yaml::Value::String(hex) => hex.trim_start_matches('#').to_string(),
+                    // unquoted hex values made only of digits are read as numbers
+                    serde_yaml::Value::Number(number) => format!("{:06}", number.as_u64()?),
+                    _ => return None,
+                };
+                Some((key, format!("#{}", hex)))
+            })
+            .collect();
+
+        if scheme.len() < 16 {
+            return Err(format_err!(
+                "Not a base16 scheme: expected base00 to base0F, found {} of them",
+                scheme.len()
+            ));
         }
+
+        let mut hashmap = scheme.clone();
+        for (slot, base) in [
+            ("black", "base00"),
+            ("gray", "base03"),
+            ("white", "base07"),
+            ("red", "base08"),
+            ("orange", "base09"),
+            ("yellow", "base0A"),
+            ("green", "base0B"),
+            ("cyan", "base0C"),
+            ("blue", "base0D"),
+            ("purple", "base0E"),
+            ("pink", "base0E"),
+            ("brown", "base0F"),
+        ] {
+            if let Some(value) = scheme.get(base) {
+                hashmap.insert(slot.to_string(), value.clone());
+            }
+        }
+        Ok(ColorMapping::from_hashmap(hashmap))
     }
 
-    pub fn from_json_file(path: PathBuf) -> ColorMapping {
-        let file = File::open(path).unwrap();
-        let reader = BufReader::new(file);
-        let json: HashMap<String, String> = serde_json::from_reader(reader).unwrap();
-        ColorMapping::from_hashmap(json)
+    /// Loads a terminal color scheme file, see [`ColorMapping::from_terminal_colors`].
+    pub fn from_terminal_scheme(path: PathBuf) -> Result<ColorMapping> {
+        ColorMapping::from_terminal_colors(&std::fs::read_to_string(path)?)
     }
 
-    pub fn from_css_file(path: PathBuf) -> ColorMapping {
-        let mut mapping = ColorMapping::default();
-        let file = File::open(path).unwrap();
-        let lines = std::io::BufReader::new(file).lines();
-        for line in lines {
-            if let Ok(line) = line {
-                mapping.from_css_line(&line);
+    /// Reads a 16-color terminal scheme, either as Windows Terminal JSON (black, red, …, brightWhite) or as Xresources/kitty-style color0 to color15 lines.
+    /// The eight normal ANSI colors go to their slot, bright black to gray, bright red to orange, bright purple to pink and yellow to brown as well.
+    /// Other entries, such as background and foreground, are available as custom colors.
+    pub fn from_terminal_colors(content: &str) -> Result<ColorMapping> {
+        const ANSI: [&str; 16] = [
+            "black",
+            "red",
+            "green",
+            "yellow",
+            "blue",
+            "purple",
+            "cyan",
+            "white",
+            "brightBlack",
+            "brightRed",
+            "brightGreen",
+            "brightYellow",
+            "brightBlue",
+            "brightPurple",
+            "brightCyan",
+            "brightWhite",
+        ];
+
+        let mut scheme: HashMap<String, String> = match serde_json::from_str(content) {
+            Ok(json) => json,
+            Err(_) => content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.starts_with(['!', '#']))
+                .filter_map(|line| {
+                    let (key, value) = line.split_once(|c: char| c == ':' || c.is_whitespace())?;
+                    let key = key.trim().trim_start_matches('*').trim_start_matches('.');
+                    let key = match key.strip_prefix("color").map(str::parse::<usize>) {
+                        Some(Ok(index)) if index < ANSI.len() => ANSI[index],
+                        _ => key,
+                    };
+                    Some((key.to_string(), value.trim().to_string()))
+                })
+                .collect(),
+        };
+
+        // some schemes call it magenta
+        for (magenta, purple) in [("magenta", "purple"), ("brightMagenta", "brightPurple")] {
+            if let Some(value) = scheme.remove(magenta) {
+                scheme.insert(purple.to_string(), value);
             }
         }
-        mapping
+
+        if let Some(missing) = ANSI[..8].iter().find(|name| !scheme.contains_key(**name)) {
+            return Err(format_err!(
+                "Not a terminal color scheme: {} is missing",
+                missing
+            ));
+        }
+
+        for (slot, ansi) in [
+            ("gray", "brightBlack"),
+            ("orange", "brightRed"),
+            ("pink", "brightPurple"),
+            ("brown", "yellow"),
+        ] {
+            if let Some(value) = scheme.get(ansi).cloned() {
+                scheme.insert(slot.to_string(), value);
+            }
+        }
+        Ok(ColorMapping::from_hashmap(scheme))
+    }
+
+    pub fn from_json_file(path: PathBuf) -> Result<ColorMapping> {
+        let content = std::fs::read_to_string(path)?;
+        let json: HashMap<String, serde_json::Value> = serde_json::from_str(&content)?;
+        if json.contains_key("brightBlack") {
+            return ColorMapping::from_terminal_colors(&content);
+        }
+        Ok(ColorMapping::from_hashmap(serde_json::from_str(&content)?))
+    }
+
+    pub fn from_css_file(path: PathBuf) -> Result<ColorMapping> {
+        let mut mapping = ColorMapping::default();
+        for line in BufReader::new(File::open(path)?).lines() {
+            mapping.from_css_line(&line?);
+        }
+        Ok(mapping)
     }
 
     fn from_css_line(&mut self, line: &str) {
@@ -287,8 +724,117 @@ impl ColorMapping {
                 "cyan" => self.cyan = value,
                 "pink" => self.pink = value,
                 "gray" => self.gray = value,
-                _ => (),
+                // also accept custom properties, e.g. --accent: #ff0
+                "" => (),
+                custom => {
+                    self.extra
+                        .insert(custom.trim_start_matches("--").to_string(), value);
+                }
             }
         }
     }
 }
+
+#[test]
+fn test_base16_scheme() {
+    let mapping = ColorMapping::from_base16(
+        r##"scheme: "Default Dark"
+author: "Chris Kempson (http://chriskempson.com)"
+base00: "181818" # background
+base01: "282828"
+base02: "383838"
+base03: "585858"
+base04: "b8b8b8"
+base05: "d8d8d8"
+base06: "e8e8e8"
+base07: "#f8f8f8"
+base08: "ab4642"
+base09: "dc9656"
+base0A: "f7ca88"
+base0B: "a1b56c"
+base0C: "86c1b9"
+base0D: "7cafc2"
+base0E: "ba8baf"
+base0F: 000000
+"##,
+    )
+    .unwrap();
+
+    assert_eq!(mapping.black, "#181818");
+    assert_eq!(mapping.white, "#f8f8f8");
+    assert_eq!(mapping.red, "#ab4642");
+    assert_eq!(mapping.brown, "#000000");
+    assert_eq!(mapping.extra["base0D"], "#7cafc2");
+    assert!(ColorMapping::from_base16("base00: \"181818\"").is_err());
+}
+
+#[test]
+fn test_terminal_scheme() {
+    let xresources = ColorMapping::from_terminal_colors(
+        "! comment\n*.background: #000000\n*.color0: #111111\n*.color1: #222222\n*.color2: #333333\n*.color3: #444444\n*.color4: #555555\n*.color5: #666666\n*.color6: #777777\n*.color7: #888888\n*.color8: #999999\n",
+    )
+    .unwrap();
+    let kitty = ColorMapping::from_terminal_colors(
+        "# comment\nbackground #000000\ncolor0 #111111\ncolor1 #222222\ncolor2 #333333\ncolor3 #444444\ncolor4 #555555\ncolor5 #666666\ncolor6 #777777\ncolor7 #888888\ncolor8 #999999\n",
+    )
+    .unwrap();
+    let windows_terminal = ColorMapping::from_terminal_colors(
+        r##"{"name": "Test", "background": "#000000", "black": "#111111", "red": "#222222", "green": "#333333", "yellow": "#444444", "blue": "#555555", "magenta": "#666666", "cyan": "#777777", "white": "#888888", "brightBlack": "#999999"}"##,
+    )
+    .unwrap();
+
+    for mapping in [xresources, kitty, windows_terminal] {
+        assert_eq!(mapping.black, "#111111");
+        assert_eq!(mapping.purple, "#666666");
+        assert_eq!(mapping.brown, "#444444");
+        assert_eq!(mapping.gray, "#999999");
+        assert_eq!(mapping.extra["background"], "#000000");
+    }
+    assert!(ColorMapping::from_terminal_colors("color0 #111111").is_err());
+}
+
+#[test]
+fn test_color_names() {
+    let mut mapping = ColorMapping::default();
+    mapping.extra.insert("accent".into(), "#ff00ff".into());
+    assert_eq!(mapping.color("red").unwrap(), Color::Red);
+    assert_eq!(mapping.color("accent").unwrap(), Color::custom("accent"));
+    assert_eq!(
+        mapping.color("#123456").unwrap().render(&mapping),
+        "#123456"
+    );
+    assert!(mapping.color("gren").is_err());
+    assert!(Color::try_from("accent").is_err());
+}
+
+#[test]
+fn test_color_ramp() {
+    let ramp = ColorRamp::new()
+        .with_stop(1.0, "#ffffff")
+        .with_stop(0.0, "#000000");
+    assert_eq!(ramp.at(-1.0), "#000000");
+    assert_eq!(ramp.at(2.0), "#ffffff");
+    assert!(!["#000000", "#ffffff"].contains(&ramp.at(0.5).as_str()));
+    assert_eq!(ColorRamp::new().at(0.5), "");
+
+    let mapping = ColorMapping::default();
+    let ramp = ColorRamp::from_colors(&[Color::Red, Color::Blue], &mapping);
+    assert_eq!(ramp.at(1.0), Color::Blue.render(&mapping));
+    assert_eq!(ramp.color_at(0.5).render(&mapping), ramp.at(0.5));
+}
+
+#[test]
+fn test_split_alpha() {
+    assert_eq!(
+        split_alpha("#ff000080"),
+        ("#ff0000".to_string(), 128.0 / 255.0)
+    );
+    assert_eq!(split_alpha("#f008"), ("#f00".to_string(), 136.0 / 255.0));
+    assert_eq!(split_alpha("#ff0000"), ("#ff0000".to_string(), 1.0));
+    assert_eq!(split_alpha("red"), ("red".to_string(), 1.0));
+    assert_eq!(css_color_declaration("fill", "#ff0000"), "fill: #ff0000;");
+    assert_eq!(
+        css_color_declaration("stroke", "#000000ff"),
+        "stroke: #000000;"
+    );
+}
@@ -1,7 +1,50 @@
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
 use rand::Rng;
 
+use crate::random::rng;
 use crate::*;
 
+/// A built-in scene, listed by `shapemaker examples list`
+#[derive(Debug, Clone, Copy)]
+pub struct Example {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub build: fn() -> Canvas,
+}
+
+static REGISTRY: Lazy<Mutex<Vec<Example>>> = Lazy::new(|| {
+    Mutex::new(vec![
+        Example {
+            name: "dna-analysis-machine",
+            description: "Hatched shapes, splines and a glowing red circle over a 16x9 grid",
+            build: dna_analysis_machine,
+        },
+        Example {
+            name: "title",
+            description: "dna-analysis-machine with shapemaker's name written over it",
+            build: title,
+        },
+    ])
+});
+
+/// Make an example available to `shapemaker examples`. An example with the same name is replaced.
+pub fn register(example: Example) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|registered| registered.name != example.name);
+    registry.push(example);
+}
+
+/// All registered examples, built-in ones first
+pub fn all() -> Vec<Example> {
+    REGISTRY.lock().unwrap().clone()
+}
+
+pub fn by_name(name: &str) -> Option<Example> {
+    all().into_iter().find(|example| example.name == name)
+}
+
 pub fn dna_analysis_machine() -> Canvas {
     let mut canvas = Canvas::new(vec![]);
 
@@ -18,6 +61,7 @@ pub fn dna_analysis_machine() -> Canvas {
         pink: "#e92e76".into(),
         gray: "#81a0a8".into(),
         cyan: "#4fecec".into(),
+        ..ColorMapping::default()
     };
 
     canvas.set_grid_size(16, 9);
@@ -59,7 +103,7 @@ pub fn dna_analysis_machine() -> Canvas {
 
         hatches_layer.add_object(
             point,
-            if rand::thread_rng().gen_bool(0.5) || point == red_circle_at {
+            if rng().gen_bool(0.5) || point == red_circle_at {
                 Object::BigCircle(point)
             } else {
                 Object::Rectangle(point, point)
@@ -71,7 +115,7 @@ pub fn dna_analysis_machine() -> Canvas {
                 } else {
                     Fill::Hatched(
                         Color::White,
-                        Angle(rand::thread_rng().gen_range(0.0..360.0)),
+                        Angle(rng().gen_range(0.0..360.0)),
                         (i + 5) as f32 / 10.0,
                         0.25,
                     )
@@ -121,28 +165,19 @@ pub fn dna_analysis_machine() -> Canvas {
 
 pub fn title() -> Canvas {
     let mut canvas = dna_analysis_machine();
-    let text_zone = Region::from_topleft(Point(8, 2), (3, 3)).unwrap();
-    canvas.remove_all_objects_in(&text_zone);
-    let last_letter_at = &text_zone.bottomright().translated(1, 0);
-    canvas.remove_all_objects_in(&last_letter_at.region());
-
-    let text_layer = canvas.new_layer("title");
-
-    let title = String::from("shapemaker");
+    let title = "shapemaker";
 
-    for (i, point) in text_zone
-        .iter()
-        .chain(last_letter_at.region().iter())
-        .enumerate()
-    {
-        println!("{}: {} '{}'", i, point, &title[i..i + 1]);
-        let character = title[i..i + 1].to_owned();
+    // clear just enough cells for the title to be 40px tall
+    let (width, height) = canvas.measure_text(title, 40.0);
+    let cells = |pixels: f32| (pixels / canvas.cell_size as f32).ceil() as usize;
+    let text_zone = Region::from_topleft(Point(8, 2), (cells(width), cells(height))).unwrap();
+    canvas.remove_all_objects_in(&text_zone);
 
-        text_layer.add_object(
-            &i.to_string(),
-            Object::CenteredText(point, character, 30.0).color(Fill::Solid(Color::White)),
-        );
-    }
+    canvas.new_layer("title").add_object(
+        "title",
+        Object::FittedText(text_zone, title.to_string(), TextAlignment::Center)
+            .color(Fill::Solid(Color::White)),
+    );
 
     canvas
 }
@@ -1,7 +1,21 @@
-use crate::{Color, ColorMapping, RenderCSS};
+use std::hash::{DefaultHasher, Hasher as _};
+
+use itertools::Itertools;
+use strum_macros::EnumIter;
+
+use slug::slugify;
+
+use crate::{
+    color::{css_color_declaration, SetColor},
+    layer::hash_serialized,
+    objects::RenderCSS,
+    point::GridLayout,
+    Color, ColorMapping, ColoredObject, Layer, ObjectSizes,
+};
+use serde::{Deserialize, Serialize};
 
 /// Angle, stored in degrees
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct Angle(pub f32);
 
 impl Angle {
@@ -38,12 +52,116 @@ impl std::fmt::Display for Angle {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Common hatching directions, see `Fill::hatches`
+#[derive(Debug, Clone, Copy, PartialEq, EnumIter)]
+pub enum HatchDirection {
+    Horizontal,
+    Vertical,
+    /// Lines going from the bottom left to the top right
+    BottomUpDiagonal,
+    /// Lines going from the top left to the bottom right
+    TopDownDiagonal,
+    /// Both diagonals at once
+    Crosshatch,
+}
+
+impl HatchDirection {
+    /// Angle to give to `Fill::Hatched` to get hatches in this direction.
+    /// Crosshatches are made of bottom-up diagonals and their perpendiculars.
+    pub fn angle(&self) -> Angle {
+        match self {
+            HatchDirection::Vertical => Angle(0.0),
+            HatchDirection::BottomUpDiagonal | HatchDirection::Crosshatch => Angle(45.0),
+            HatchDirection::Horizontal => Angle(90.0),
+            HatchDirection::TopDownDiagonal => Angle(135.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Fill {
     Solid(Color),
     Translucent(Color, f32),
     Hatched(Color, Angle, f32, f32),
+    /// Like Hatched, but with a second set of hatches perpendicular to the first one
+    CrossHatched(Color, Angle, f32, f32),
     Dotted(Color, f32, f32),
+    /// Color stops (color and offset, from 0.0 to 1.0) and angle of the gradient. An angle of 0 goes from left to right.
+    LinearGradient(Vec<(Color, f32)>, Angle),
+    /// Center color, edge color, and focus: how far from the center (from 0.0 to 1.0) the center color extends before fading to the edge color.
+    RadialGradient(Color, Color, f32),
+    /// Color, grain size (in pixels) and opacity of the grain. Gives a film-grain texture to the color.
+    Noise(Color, f32, f32),
+    /// A custom tile, repeated to fill the shape
+    Pattern(PatternTile),
+    /// A color given directly, without going through the color mapping
+    Rgb(u8, u8, u8),
+    /// A hex color given directly, without going through the color mapping. Can include an alpha channel (#rrggbbaa).
+    Hex(String),
+}
+
+/// A small grid of objects, repeated to fill shapes. See `Fill::Pattern`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatternTile {
+    /// Used, along with a hash of the tile, to identify the pattern in the SVG definitions
+    pub name: String,
+    pub grid_size: (usize, usize),
+    pub cell_size: usize,
+    pub object_sizes: ObjectSizes,
+    pub objects: Vec<ColoredObject>,
+}
+
+impl PatternTile {
+    pub fn new(name: &str, grid_size: (usize, usize), cell_size: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            grid_size,
+            cell_size,
+            object_sizes: ObjectSizes::default(),
+            objects: vec![],
+        }
+    }
+
+    /// Uses all objects of the layer as the tile's contents. The tile's name is the layer's name.
+    pub fn from_layer(layer: &Layer, grid_size: (usize, usize), cell_size: usize) -> Self {
+        Self {
+            name: layer.name.clone(),
+            grid_size,
+            cell_size,
+            object_sizes: layer.object_sizes,
+            objects: layer.objects.values().cloned().collect(),
+        }
+    }
+
+    pub fn with_object(mut self, object: impl Into<ColoredObject>) -> Self {
+        self.objects.push(object.into());
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.grid_size.0 * self.cell_size
+    }
+
+    pub fn height(&self) -> usize {
+        self.grid_size.1 * self.cell_size
+    }
+}
+
+impl Fill {
+    /// Hatches in the given direction. `size` is the size of a single hatch pattern tile, and `thickness_ratio` the proportion of it that is colored.
+    pub fn hatches(
+        color: Color,
+        direction: HatchDirection,
+        size: f32,
+        thickness_ratio: f32,
+    ) -> Self {
+        match direction {
+            HatchDirection::Crosshatch => {
+                Fill::CrossHatched(color, direction.angle(), size, thickness_ratio)
+            }
+            _ => Fill::Hatched(color, direction.angle(), size, thickness_ratio),
+        }
+    }
 }
 
 // Operations that can be applied on fills.
@@ -56,14 +174,14 @@ pub trait FillOperations {
 impl FillOperations for Fill {
     fn opacify(&self, opacity: f32) -> Self {
         match self {
-            Fill::Solid(color) => Fill::Translucent(*color, opacity),
-            Fill::Translucent(color, _) => Fill::Translucent(*color, opacity),
-            _ => *self,
+            Fill::Solid(color) => Fill::Translucent(color.clone(), opacity),
+            Fill::Translucent(color, _) => Fill::Translucent(color.clone(), opacity),
+            _ => self.clone(),
         }
     }
 
     fn bottom_up_hatches(color: Color, thickness: f32, spacing: f32) -> Self {
-        Fill::Hatched(color, Angle(45.0), thickness, spacing)
+        Fill::hatches(color, HatchDirection::BottomUpDiagonal, thickness, spacing)
     }
 }
 
@@ -80,13 +198,24 @@ impl FillOperations for Option<Fill> {
 impl RenderCSS for Fill {
     fn render_fill_css(&self, colormap: &ColorMapping) -> String {
         match self {
-            Fill::Solid(color) => {
-                format!("fill: {};", color.render(colormap))
-            }
+            Fill::Solid(color) => css_color_declaration("fill", &color.render(colormap)),
             Fill::Translucent(color, opacity) => {
-                format!("fill: {}; opacity: {};", color.render(colormap), opacity)
+                format!(
+                    "{} opacity: {};",
+                    css_color_declaration("fill", &color.render(colormap)),
+                    opacity
+                )
             }
-            Fill::Dotted(..) | Fill::Hatched(..) => {
+            Fill::Rgb(..) | Fill::Hex(..) => {
+                css_color_declaration("fill", &self.render_direct_color().unwrap_or_default())
+            }
+            Fill::Dotted(..)
+            | Fill::Hatched(..)
+            | Fill::CrossHatched(..)
+            | Fill::LinearGradient(..)
+            | Fill::RadialGradient(..)
+            | Fill::Noise(..)
+            | Fill::Pattern(..) => {
                 format!("fill: url(#{});", self.pattern_id())
             }
         }
@@ -95,47 +224,175 @@ impl RenderCSS for Fill {
     fn render_stroke_css(&self, colormap: &ColorMapping) -> String {
         match self {
             Fill::Solid(color) => {
-                format!("stroke: {}; fill: transparent;", color.render(colormap))
+                format!(
+                    "{} fill: transparent;",
+                    css_color_declaration("stroke", &color.render(colormap))
+                )
             }
             Fill::Translucent(color, opacity) => {
                 format!(
-                    "stroke: {}; opacity: {}; fill: transparent;",
-                    color.render(colormap),
+                    "{} opacity: {}; fill: transparent;",
+                    css_color_declaration("stroke", &color.render(colormap)),
                     opacity
                 )
             }
-            Fill::Dotted(..) => unimplemented!(),
-            Fill::Hatched(..) => unimplemented!(),
+            Fill::Rgb(..) | Fill::Hex(..) => {
+                format!(
+                    "{} fill: transparent;",
+                    css_color_declaration(
+                        "stroke",
+                        &self.render_direct_color().unwrap_or_default()
+                    )
+                )
+            }
+            Fill::Dotted(..)
+            | Fill::Hatched(..)
+            | Fill::CrossHatched(..)
+            | Fill::LinearGradient(..)
+            | Fill::RadialGradient(..)
+            | Fill::Noise(..)
+            | Fill::Pattern(..) => {
+                format!("stroke: url(#{}); fill: transparent;", self.pattern_id())
+            }
         }
     }
 }
 
 impl Fill {
+    /// CSS color of Rgb and Hex fills, None for other fills
+    pub fn render_direct_color(&self) -> Option<String> {
+        match self {
+            Fill::Rgb(r, g, b) => Some(format!("rgb({}, {}, {})", r, g, b)),
+            Fill::Hex(hex) => Some(format!("#{}", hex.trim_start_matches('#'))),
+            _ => None,
+        }
+    }
+
+    /// Fill between this one (t = 0) and other (t = 1), for smooth crossfades.
+    /// Solid, translucent and direct colors are interpolated (see `Color::lerp`), other fills snap from one to the other halfway through.
+    pub fn lerp(&self, other: &Fill, t: f32, mapping: &ColorMapping) -> Fill {
+        if let (Some((from, from_opacity)), Some((to, to_opacity))) =
+            (self.plain_color(mapping), other.plain_color(mapping))
+        {
+            if let (Ok(mut from), Ok(mut to)) =
+                (csscolorparser::parse(&from), csscolorparser::parse(&to))
+            {
+                // opacity goes in the alpha channel so that it gets interpolated too
+                from.a *= from_opacity as f64;
+                to.a *= to_opacity as f64;
+                return Fill::Hex(
+                    from.interpolate_oklab(&to, t.clamp(0.0, 1.0) as f64)
+                        .to_hex_string(),
+                );
+            }
+        }
+
+        if t < 0.5 {
+            self.clone()
+        } else {
+            other.clone()
+        }
+    }
+
+    /// CSS color and opacity of single-color fills
+    fn plain_color(&self, mapping: &ColorMapping) -> Option<(String, f32)> {
+        match self {
+            Fill::Solid(color) => Some((color.render(mapping), 1.0)),
+            Fill::Translucent(color, opacity) => Some((color.render(mapping), *opacity)),
+            Fill::Rgb(..) | Fill::Hex(..) => self.render_direct_color().map(|color| (color, 1.0)),
+            _ => None,
+        }
+    }
+
     pub fn pattern_id(&self) -> String {
         if let Fill::Hatched(color, angle, thickness, spacing) = self {
             return format!(
                 "pattern-hatched-{}-{}-{}-{}",
                 angle,
-                color.name(),
+                slugify(color.name()),
+                thickness,
+                spacing
+            );
+        }
+        if let Fill::CrossHatched(color, angle, thickness, spacing) = self {
+            return format!(
+                "pattern-crosshatched-{}-{}-{}-{}",
+                angle,
+                slugify(color.name()),
                 thickness,
                 spacing
             );
         }
         if let Fill::Dotted(color, diameter, spacing) = self {
-            return format!("pattern-dotted-{}-{}-{}", color.name(), diameter, spacing);
+            return format!(
+                "pattern-dotted-{}-{}-{}",
+                slugify(color.name()),
+                diameter,
+                spacing
+            );
+        }
+        if let Fill::LinearGradient(stops, angle) = self {
+            return format!(
+                "gradient-linear-{}-{}",
+                angle.degrees(),
+                stops
+                    .iter()
+                    .map(|(color, offset)| format!("{}-{}", slugify(color.name()), offset))
+                    .join("-")
+            )
+            .replace('.', "_");
+        }
+        if let Fill::RadialGradient(center, edge, focus) = self {
+            return format!(
+                "gradient-radial-{}-{}-{}",
+                slugify(center.name()),
+                slugify(edge.name()),
+                focus
+            )
+            .replace('.', "_");
+        }
+        if let Fill::Noise(color, scale, opacity) = self {
+            return format!(
+                "pattern-noise-{}-{}-{}",
+                slugify(color.name()),
+                scale,
+                opacity
+            )
+            .replace('.', "_");
+        }
+        if let Fill::Pattern(tile) = self {
+            // tiles with the same name can still have different contents
+            let mut hasher = DefaultHasher::new();
+            hash_serialized(tile, &mut hasher);
+            return format!("pattern-tile-{}-{:x}", slugify(&tile.name), hasher.finish());
         }
         String::from("")
     }
 
+    /// Whether the fill needs a definition (see `pattern_definition`) to be rendered
+    pub fn needs_definition(&self) -> bool {
+        matches!(
+            self,
+            Fill::Hatched(..)
+                | Fill::CrossHatched(..)
+                | Fill::Dotted(..)
+                | Fill::LinearGradient(..)
+                | Fill::RadialGradient(..)
+                | Fill::Noise(..)
+                | Fill::Pattern(..)
+        )
+    }
+
     pub fn pattern_definition(
         &self,
         colormapping: &ColorMapping,
-    ) -> Option<svg::node::element::Pattern> {
+    ) -> Option<Box<dyn svg::node::Node>> {
         match self {
-            Fill::Hatched(color, angle, size, thickness_ratio) => {
+            Fill::Hatched(color, angle, size, thickness_ratio)
+            | Fill::CrossHatched(color, angle, size, thickness_ratio) => {
                 let thickness = size * (2.0 * thickness_ratio);
 
-                let pattern = svg::node::element::Pattern::new()
+                let mut pattern = svg::node::element::Pattern::new()
                     .set("id", self.pattern_id())
                     .set("patternUnits", "userSpaceOnUse")
                     .set("height", size * 2.0)
@@ -145,33 +402,18 @@ impl Fill {
                         "patternTransform",
                         format!("rotate({})", (*angle - Angle(45.0)).degrees()),
                     )
-                    // https://stackoverflow.com/a/55104220/9943464
-                    .add(
-                        svg::node::element::Polygon::new()
-                            .set(
-                                "points",
-                                format!("0,0 {},0 0,{}", thickness / 2.0, thickness / 2.0),
-                            )
-                            .set("fill", color.render(colormapping)),
-                    )
-                    .add(
-                        svg::node::element::Polygon::new()
-                            .set(
-                                "points",
-                                format!(
-                                    "0,{} {},0 {},{} {},{}",
-                                    size,
-                                    size,
-                                    size,
-                                    thickness / 2.0,
-                                    thickness / 2.0,
-                                    size,
-                                ),
-                            )
-                            .set("fill", color.render(colormapping)),
-                    );
+                    .add(hatch_stripes(color, *size, thickness, colormapping));
+
+                if matches!(self, Fill::CrossHatched(..)) {
+                    // rotating the tile around its center keeps it seamless
+                    pattern =
+                        pattern.add(hatch_stripes(color, *size, thickness, colormapping).set(
+                            "transform",
+                            format!("rotate(90 {} {})", size / 2.0, size / 2.0),
+                        ));
+                }
 
-                Some(pattern)
+                Some(Box::new(pattern))
             }
             Fill::Dotted(color, diameter, spacing) => {
                 let box_size = diameter + 2.0 * spacing;
@@ -186,12 +428,161 @@ impl Fill {
                             .set("cx", box_size / 2.0)
                             .set("cy", box_size / 2.0)
                             .set("r", diameter / 2.0)
-                            .set("fill", color.render(colormapping)),
+                            .set_color("fill", &color.render(colormapping)),
                     );
 
-                Some(pattern)
+                Some(Box::new(pattern))
+            }
+            Fill::LinearGradient(stops, angle) => {
+                // gradient vector goes through the center of the bounding box
+                let (dx, dy) = (angle.radians().cos() / 2.0, angle.radians().sin() / 2.0);
+                let mut gradient = svg::node::element::LinearGradient::new()
+                    .set("id", self.pattern_id())
+                    .set("x1", 0.5 - dx)
+                    .set("y1", 0.5 - dy)
+                    .set("x2", 0.5 + dx)
+                    .set("y2", 0.5 + dy);
+
+                for (color, offset) in stops {
+                    gradient = gradient.add(
+                        svg::node::element::Stop::new()
+                            .set("offset", format!("{}%", offset * 100.0))
+                            .set_color("stop-color", &color.render(colormapping)),
+                    );
+                }
+
+                Some(Box::new(gradient))
+            }
+            Fill::RadialGradient(center, edge, focus) => Some(Box::new(
+                svg::node::element::RadialGradient::new()
+                    .set("id", self.pattern_id())
+                    .add(
+                        svg::node::element::Stop::new()
+                            .set("offset", format!("{}%", focus.clamp(0.0, 1.0) * 100.0))
+                            .set_color("stop-color", &center.render(colormapping)),
+                    )
+                    .add(
+                        svg::node::element::Stop::new()
+                            .set("offset", "100%")
+                            .set_color("stop-color", &edge.render(colormapping)),
+                    ),
+            )),
+            Fill::Noise(color, scale, opacity) => {
+                // stitchTiles makes the noise seamless across pattern tiles
+                let tile_size = 100.0;
+                let filter_id = format!("{}-filter", self.pattern_id());
+
+                Some(Box::new(
+                    svg::node::element::Pattern::new()
+                        .set("id", self.pattern_id())
+                        .set("patternUnits", "userSpaceOnUse")
+                        .set("width", tile_size)
+                        .set("height", tile_size)
+                        .add(
+                            svg::node::element::Filter::new()
+                                .set("id", filter_id.clone())
+                                .set("x", 0)
+                                .set("y", 0)
+                                .set("width", "100%")
+                                .set("height", "100%")
+                                .add(
+                                    svg::node::element::FilterEffectTurbulence::new()
+                                        .set("type", "fractalNoise")
+                                        .set("baseFrequency", 1.0 / scale.max(f32::EPSILON))
+                                        .set("numOctaves", 3)
+                                        .set("stitchTiles", "stitch"),
+                                )
+                                .add(
+                                    svg::node::element::FilterEffectColorMatrix::new()
+                                        .set("type", "saturate")
+                                        .set("values", 0),
+                                ),
+                        )
+                        .add(
+                            svg::node::element::Rectangle::new()
+                                .set("width", tile_size)
+                                .set("height", tile_size)
+                                .set_color("fill", &color.render(colormapping)),
+                        )
+                        .add(
+                            svg::node::element::Rectangle::new()
+                                .set("width", tile_size)
+                                .set("height", tile_size)
+                                .set("filter", format!("url(#{})", filter_id))
+                                .set("opacity", *opacity),
+                        ),
+                ))
+            }
+            Fill::Pattern(tile) => {
+                let mut pattern = svg::node::element::Pattern::new()
+                    .set("id", self.pattern_id())
+                    .set("patternUnits", "userSpaceOnUse")
+                    .set("width", tile.width())
+                    .set("height", tile.height());
+
+                // tiles have their own square grid, whatever the canvas' layout
+                let layout = GridLayout::square(tile.cell_size);
+                for (i, object) in tile.objects.iter().enumerate() {
+                    pattern = pattern.add(object.render(
+                        layout,
+                        tile.object_sizes,
+                        colormapping,
+                        &format!("{}#{}", self.pattern_id(), i),
+                    ));
+                }
+
+                let clips = tile
+                    .objects
+                    .iter()
+                    .filter_map(|object| object.clip.as_ref())
+                    .unique_by(|clip| clip.clip_path_id(layout, tile.object_sizes))
+                    .collect_vec();
+                if !clips.is_empty() {
+                    let mut defs = svg::node::element::Definitions::new();
+                    for clip in clips {
+                        defs = defs.add(clip.clip_path_definition(layout, tile.object_sizes));
+                    }
+                    pattern = pattern.add(defs);
+                }
+
+                Some(Box::new(pattern))
             }
             _ => None,
         }
     }
 }
+
+/// Bottom-up diagonal stripes filling a size×size tile seamlessly
+fn hatch_stripes(
+    color: &Color,
+    size: f32,
+    thickness: f32,
+    colormapping: &ColorMapping,
+) -> svg::node::element::Group {
+    // https://stackoverflow.com/a/55104220/9943464
+    svg::node::element::Group::new()
+        .add(
+            svg::node::element::Polygon::new()
+                .set(
+                    "points",
+                    format!("0,0 {},0 0,{}", thickness / 2.0, thickness / 2.0),
+                )
+                .set_color("fill", &color.render(colormapping)),
+        )
+        .add(
+            svg::node::element::Polygon::new()
+                .set(
+                    "points",
+                    format!(
+                        "0,{} {},0 {},{} {},{}",
+                        size,
+                        size,
+                        size,
+                        thickness / 2.0,
+                        thickness / 2.0,
+                        size,
+                    ),
+                )
+                .set_color("fill", &color.render(colormapping)),
+        )
+}
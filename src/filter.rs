@@ -1,22 +1,37 @@
 use std::hash::Hash;
 
+use itertools::Itertools;
 use wasm_bindgen::prelude::*;
 
-use crate::RenderCSS;
+use crate::objects::RenderCSS;
+use serde::{Deserialize, Serialize};
 
 #[wasm_bindgen]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum FilterType {
     Glow,
     NaturalShadow,
     Saturation,
+    /// Rotates hues by the parameter, in degrees
+    HueRotate,
+    /// Multiplies color channels by the parameter
+    Brightness,
+    /// 1 keeps the original contrast, 0 makes everything gray
+    Contrast,
+    /// Size of the pixels, in SVG units
+    Pixelate,
+    /// Displaces the object along turbulence noise. The parameter is the intensity, in SVG units.
+    /// Change the filter's seed every frame to animate it.
+    Glitch,
 }
 
 #[wasm_bindgen]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Filter {
     pub kind: FilterType,
     pub parameter: f32,
+    /// Seed of the noise, for filters that use some
+    pub seed: u32,
 }
 
 #[wasm_bindgen]
@@ -26,6 +41,11 @@ impl Filter {
             FilterType::Glow => "glow",
             FilterType::NaturalShadow => "natural-shadow-filter",
             FilterType::Saturation => "saturation",
+            FilterType::HueRotate => "hue-rotate",
+            FilterType::Brightness => "brightness",
+            FilterType::Contrast => "contrast",
+            FilterType::Pixelate => "pixelate",
+            FilterType::Glitch => "glitch",
         }
         .to_owned()
     }
@@ -34,103 +54,287 @@ impl Filter {
         Self {
             kind: FilterType::Glow,
             parameter: intensity,
+            seed: 0,
         }
     }
 
+    pub fn hue_rotate(degrees: f32) -> Self {
+        Self {
+            kind: FilterType::HueRotate,
+            parameter: degrees,
+            seed: 0,
+        }
+    }
+
+    pub fn brightness(factor: f32) -> Self {
+        Self {
+            kind: FilterType::Brightness,
+            parameter: factor,
+            seed: 0,
+        }
+    }
+
+    pub fn contrast(factor: f32) -> Self {
+        Self {
+            kind: FilterType::Contrast,
+            parameter: factor,
+            seed: 0,
+        }
+    }
+
+    pub fn pixelate(size: f32) -> Self {
+        Self {
+            kind: FilterType::Pixelate,
+            parameter: size,
+            seed: 0,
+        }
+    }
+
+    pub fn glitch(intensity: f32, seed: u32) -> Self {
+        Self {
+            kind: FilterType::Glitch,
+            parameter: intensity,
+            seed,
+        }
+    }
+
+    pub fn with_seed(self, seed: u32) -> Self {
+        Self { seed, ..self }
+    }
+
     pub fn id(&self) -> String {
-        format!(
+        let id = format!(
             "filter-{}-{}",
             self.name(),
             self.parameter.to_string().replace('.', "_")
-        )
+        );
+
+        match self.kind {
+            FilterType::Glitch => format!("{}-{}", id, self.seed),
+            _ => id,
+        }
     }
 }
 
 impl Filter {
     pub fn definition(&self) -> svg::node::element::Filter {
+        self.primitives("SourceGraphic", "result")
+            .into_iter()
+            .fold(svg::node::element::Filter::new(), |filter, primitive| {
+                filter.add(primitive)
+            })
+            .set("id", self.id())
+            .set("filterUnit", "userSpaceOnUse")
+    }
+
+    /// The filter primitives, reading from input and writing their final result to output.
+    /// Intermediate results are prefixed by output, so that filters can be chained inside a single `<filter>`.
+    pub fn primitives(&self, input: &str, output: &str) -> Vec<Box<dyn svg::node::Node>> {
+        let intermediate = |name: &str| format!("{}-{}", output, name);
+
         match self.kind {
-            FilterType::Glow => {
-                // format!(
-                //     r#"
-                //     <filter id="glow">
-                //         <feGaussianBlur stdDeviation="{}" result="coloredBlur"/>
-                //         <feMerge>
-                //             <feMergeNode in="coloredBlur"/>
-                //             <feMergeNode in="SourceGraphic"/>
-                //         </feMerge>
-                //     </filter>
-                // "#,
-                //     2.5
-                // ) // TODO parameterize stdDeviation
-                svg::node::element::Filter::new()
-                    .add(
-                        // TODO parameterize stdDeviation
-                        svg::node::element::FilterEffectGaussianBlur::new()
-                            .set("stdDeviation", self.parameter)
-                            .set("result", "coloredBlur"),
-                    )
-                    .add(
-                        svg::node::element::FilterEffectMerge::new()
-                            .add(
-                                svg::node::element::FilterEffectMergeNode::new()
-                                    .set("in", "coloredBlur"),
-                            )
-                            .add(
-                                svg::node::element::FilterEffectMergeNode::new()
-                                    .set("in", "SourceGraphic"),
-                            ),
-                    )
-            }
-            FilterType::NaturalShadow => {
-                /*
-                              <filter id="natural-shadow-filter" x="0" y="0" width="2" height="2">
-                  <feOffset in="SourceGraphic" dx="3" dy="3" />
-                  <feGaussianBlur stdDeviation="12" result="blur" />
-                  <feMerge>
-                    <feMergeNode in="blur" />
-                    <feMergeNode in="SourceGraphic" />
-                  </feMerge>
-                </filter>
-                               */
-                svg::node::element::Filter::new()
-                    .add(
-                        svg::node::element::FilterEffectOffset::new()
-                            .set("in", "SourceGraphic")
-                            .set("dx", self.parameter)
-                            .set("dy", self.parameter),
-                    )
-                    .add(
-                        svg::node::element::FilterEffectGaussianBlur::new()
-                            .set("stdDeviation", self.parameter * 4.0)
-                            .set("result", "blur"),
-                    )
-                    .add(
-                        svg::node::element::FilterEffectMerge::new()
-                            .add(svg::node::element::FilterEffectMergeNode::new().set("in", "blur"))
-                            .add(
-                                svg::node::element::FilterEffectMergeNode::new()
-                                    .set("in", "SourceGraphic"),
-                            ),
-                    )
-            }
-            FilterType::Saturation => {
-                /*
-                <filter id="saturation">
-                    <feColorMatrix type="saturate" values="0.5"/>
-                </filter>
-                */
-                svg::node::element::Filter::new().add(
-                    svg::node::element::FilterEffectColorMatrix::new()
-                        .set("type", "saturate")
-                        .set("values", self.parameter),
-                )
+            FilterType::Glow => vec![
+                Box::new(
+                    svg::node::element::FilterEffectGaussianBlur::new()
+                        .set("in", input)
+                        .set("stdDeviation", self.parameter)
+                        .set("result", intermediate("coloredBlur")),
+                ),
+                Box::new(
+                    svg::node::element::FilterEffectMerge::new()
+                        .add(
+                            svg::node::element::FilterEffectMergeNode::new()
+                                .set("in", intermediate("coloredBlur")),
+                        )
+                        .add(svg::node::element::FilterEffectMergeNode::new().set("in", input))
+                        .set("result", output),
+                ),
+            ],
+            FilterType::NaturalShadow => vec![
+                Box::new(
+                    svg::node::element::FilterEffectOffset::new()
+                        .set("in", input)
+                        .set("dx", self.parameter)
+                        .set("dy", self.parameter),
+                ),
+                Box::new(
+                    svg::node::element::FilterEffectGaussianBlur::new()
+                        .set("stdDeviation", self.parameter * 4.0)
+                        .set("result", intermediate("blur")),
+                ),
+                Box::new(
+                    svg::node::element::FilterEffectMerge::new()
+                        .add(
+                            svg::node::element::FilterEffectMergeNode::new()
+                                .set("in", intermediate("blur")),
+                        )
+                        .add(svg::node::element::FilterEffectMergeNode::new().set("in", input))
+                        .set("result", output),
+                ),
+            ],
+            FilterType::Saturation => vec![Box::new(
+                svg::node::element::FilterEffectColorMatrix::new()
+                    .set("in", input)
+                    .set("type", "saturate")
+                    .set("values", self.parameter)
+                    .set("result", output),
+            )],
+            FilterType::HueRotate => vec![Box::new(
+                svg::node::element::FilterEffectColorMatrix::new()
+                    .set("in", input)
+                    .set("type", "hueRotate")
+                    .set("values", self.parameter)
+                    .set("result", output),
+            )],
+            FilterType::Brightness => vec![Box::new(
+                linear_transfer(self.parameter, 0.0)
+                    .set("in", input)
+                    .set("result", output),
+            )],
+            FilterType::Contrast => vec![Box::new(
+                linear_transfer(self.parameter, 0.5 - 0.5 * self.parameter)
+                    .set("in", input)
+                    .set("result", output),
+            )],
+            FilterType::Pixelate => vec![
+                Box::new(
+                    svg::node::element::FilterEffectFlood::new()
+                        .set("x", 0)
+                        .set("y", 0)
+                        .set("width", 1)
+                        .set("height", 1),
+                ),
+                Box::new(
+                    svg::node::element::FilterEffectComposite::new()
+                        .set("width", self.parameter)
+                        .set("height", self.parameter),
+                ),
+                Box::new(
+                    svg::node::element::FilterEffectTile::new().set("result", intermediate("grid")),
+                ),
+                Box::new(
+                    svg::node::element::FilterEffectComposite::new()
+                        .set("in", input)
+                        .set("in2", intermediate("grid"))
+                        .set("operator", "in"),
+                ),
+                Box::new(
+                    svg::node::element::FilterEffectMorphology::new()
+                        .set("operator", "dilate")
+                        .set("radius", self.parameter / 2.0)
+                        .set("result", output),
+                ),
+            ],
+            FilterType::Glitch => vec![
+                Box::new(
+                    svg::node::element::FilterEffectTurbulence::new()
+                        .set("type", "fractalNoise")
+                        // stretched horizontally, so that the object gets sliced into horizontal bands
+                        .set("baseFrequency", "0.001 0.2")
+                        .set("numOctaves", 1)
+                        .set("seed", self.seed)
+                        .set("result", intermediate("noise")),
+                ),
+                Box::new(
+                    svg::node::element::FilterEffectDisplacementMap::new()
+                        .set("in", input)
+                        .set("in2", intermediate("noise"))
+                        .set("scale", self.parameter)
+                        .set("xChannelSelector", "R")
+                        .set("yChannelSelector", "A")
+                        .set("result", output),
+                ),
+            ],
+        }
+    }
+}
+
+/// Several filters applied one after the other, rendered as a single `<filter>` definition.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FilterChain {
+    pub filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn id(&self) -> String {
+        format!(
+            "filter-chain-{}",
+            self.filters
+                .iter()
+                .map(|f| f.id().trim_start_matches("filter-").to_owned())
+                .join("-")
+        )
+    }
+
+    pub fn definition(&self) -> svg::node::element::Filter {
+        let mut definition = svg::node::element::Filter::new();
+        let mut input = "SourceGraphic".to_string();
+
+        for (i, filter) in self.filters.iter().enumerate() {
+            let output = format!("stage{}", i);
+            for primitive in filter.primitives(&input, &output) {
+                definition = definition.add(primitive);
             }
+            input = output;
         }
-        .set("id", self.id())
-        .set("filterUnit", "userSpaceOnUse")
+
+        definition
+            .set("id", self.id())
+            .set("filterUnit", "userSpaceOnUse")
     }
 }
 
+impl From<Vec<Filter>> for FilterChain {
+    fn from(filters: Vec<Filter>) -> Self {
+        Self { filters }
+    }
+}
+
+impl RenderCSS for FilterChain {
+    fn render_fill_css(&self, _colormap: &crate::ColorMapping) -> String {
+        format!("filter: url(#{}); overflow: visible;", self.id())
+    }
+
+    fn render_stroke_css(&self, colormap: &crate::ColorMapping) -> String {
+        self.render_fill_css(colormap)
+    }
+}
+
+/// Applies color * slope + intercept to the red, green and blue channels
+fn linear_transfer(
+    slope: f32,
+    intercept: f32,
+) -> svg::node::element::FilterEffectComponentTransfer {
+    svg::node::element::FilterEffectComponentTransfer::new()
+        .add(
+            svg::node::element::FilterEffectFunctionR::new()
+                .set("type", "linear")
+                .set("slope", slope)
+                .set("intercept", intercept),
+        )
+        .add(
+            svg::node::element::FilterEffectFunctionG::new()
+                .set("type", "linear")
+                .set("slope", slope)
+                .set("intercept", intercept),
+        )
+        .add(
+            svg::node::element::FilterEffectFunctionB::new()
+                .set("type", "linear")
+                .set("slope", slope)
+                .set("intercept", intercept),
+        )
+}
+
 impl RenderCSS for Filter {
     fn render_fill_css(&self, _colormap: &crate::ColorMapping) -> String {
         format!("filter: url(#{}); overflow: visible;", self.id())
@@ -144,7 +348,9 @@ impl RenderCSS for Filter {
 impl PartialEq for Filter {
     fn eq(&self, other: &Self) -> bool {
         // TODO use way less restrictive epsilon
-        self.kind == other.kind && (self.parameter - other.parameter).abs() < f32::EPSILON
+        self.kind == other.kind
+            && (self.parameter - other.parameter).abs() < f32::EPSILON
+            && self.seed == other.seed
     }
 }
 
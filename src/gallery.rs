@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use anyhow::Result;
+use handlebars::Handlebars;
+use itertools::Itertools;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{Canvas, ColorMapping, Fill};
+
+/// One image of a contact sheet, see [`write_gallery`]
+#[derive(Debug, Clone, Serialize)]
+pub struct GalleryItem {
+    pub seed: u64,
+    /// Relative to the gallery's directory
+    pub file: String,
+    pub background: Option<String>,
+    /// Names and CSS values of the colors the image's objects use
+    pub palette: Vec<(String, String)>,
+}
+
+impl GalleryItem {
+    pub fn new(seed: u64, file: &str, canvas: &Canvas) -> Self {
+        Self {
+            seed,
+            file: file.to_string(),
+            background: canvas
+                .background
+                .as_ref()
+                .map(|color| color.render(&canvas.colormap)),
+            palette: canvas
+                .all_layers()
+                .flat_map(|layer| layer.objects.values())
+                .filter_map(|object| object.fill.as_ref())
+                .flat_map(|fill| fill_colors(fill, &canvas.colormap))
+                .unique()
+                .collect(),
+        }
+    }
+}
+
+/// Names and CSS values of the colors making up the fill
+fn fill_colors(fill: &Fill, colormap: &ColorMapping) -> Vec<(String, String)> {
+    let colors = match fill {
+        Fill::Solid(color)
+        | Fill::Translucent(color, _)
+        | Fill::Hatched(color, ..)
+        | Fill::CrossHatched(color, ..)
+        | Fill::Dotted(color, ..)
+        | Fill::Noise(color, ..) => vec![color],
+        Fill::LinearGradient(stops, _) => stops.iter().map(|(color, _)| color).collect(),
+        Fill::RadialGradient(center, edge, _) => vec![center, edge],
+        Fill::Pattern(tile) => {
+            return tile
+                .objects
+                .iter()
+                .filter_map(|object| object.fill.as_ref())
+                .flat_map(|fill| fill_colors(fill, colormap))
+                .collect()
+        }
+        Fill::Rgb(..) | Fill::Hex(..) => {
+            let css = fill.render_direct_color().unwrap_or_default();
+            return vec![(css.clone(), css)];
+        }
+    };
+    colors
+        .into_iter()
+        .map(|color| (color.name(), color.render(colormap)))
+        .collect()
+}
+
+/// Writes an index.html grid of the images into the directory, with their seeds and palettes
+pub fn write_gallery(directory: &Path, items: &[GalleryItem]) -> Result<()> {
+    let template = String::from_utf8_lossy(include_bytes!("../preview/gallery.html.hbs"));
+    let contents = Handlebars::new().render_template(
+        &template,
+        &json!({
+            "count": items.len(),
+            "items": items,
+        }),
+    )?;
+    std::fs::write(directory.join("index.html"), contents)?;
+    Ok(())
+}
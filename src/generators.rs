@@ -0,0 +1,4 @@
+//! Procedural generators that build whole layers from a handful of parameters
+pub mod lsystem;
+pub mod subdivision;
+pub mod wfc;
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use crate::{Layer, Object, PointF, Region};
+
+/// A Lindenmayer system, drawn with turtle graphics:
+/// - `F` and `G` move forward while drawing
+/// - `f` moves forward without drawing
+/// - `+` turns clockwise by the angle, `-` counter-clockwise
+/// - `[` saves the turtle's state, `]` restores it
+///
+/// Other symbols are only used by the rules.
+#[derive(Debug, Clone)]
+pub struct LSystem {
+    pub axiom: String,
+    pub rules: HashMap<char, String>,
+    /// In degrees
+    pub angle: f32,
+    /// Direction the turtle starts in, in degrees. 0 is to the right, -90 is up.
+    pub heading: f32,
+}
+
+impl LSystem {
+    pub fn new(axiom: &str, angle: f32) -> Self {
+        Self {
+            axiom: axiom.to_string(),
+            rules: HashMap::new(),
+            angle,
+            heading: -90.0,
+        }
+    }
+
+    pub fn with_rule(mut self, symbol: char, replacement: &str) -> Self {
+        self.rules.insert(symbol, replacement.to_string());
+        self
+    }
+
+    pub fn with_heading(self, heading: f32) -> Self {
+        Self { heading, ..self }
+    }
+
+    pub fn expand(&self, iterations: usize) -> String {
+        (0..iterations).fold(self.axiom.clone(), |current, _| {
+            current
+                .chars()
+                .map(|symbol| match self.rules.get(&symbol) {
+                    Some(replacement) => replacement.clone(),
+                    None => symbol.to_string(),
+                })
+                .collect()
+        })
+    }
+
+    /// Segments drawn by the turtle after expanding, with a step length of 1
+    pub fn segments(&self, iterations: usize) -> Vec<(PointF, PointF)> {
+        let mut segments = vec![];
+        let mut position = PointF(0.0, 0.0);
+        let mut heading = self.heading;
+        let mut saved = vec![];
+
+        for symbol in self.expand(iterations).chars() {
+            match symbol {
+                'F' | 'G' | 'f' => {
+                    let (sin, cos) = heading.to_radians().sin_cos();
+                    let next = position.translated(cos, sin);
+                    if symbol != 'f' {
+                        segments.push((position, next));
+                    }
+                    position = next;
+                }
+                '+' => heading += self.angle,
+                '-' => heading -= self.angle,
+                '[' => saved.push((position, heading)),
+                ']' => {
+                    if let Some(state) = saved.pop() {
+                        (position, heading) = state;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        segments
+    }
+
+    /// Layer of lines drawing the system, scaled to fit in region while keeping its proportions
+    pub fn layer(&self, name: &str, iterations: usize, region: &Region, line_width: f32) -> Layer {
+        let segments = self.segments(iterations);
+        let mut layer = Layer::new(name);
+
+        let points = segments.iter().flat_map(|(start, end)| [start, end]);
+        let (min_x, min_y, max_x, max_y) = points.fold(
+            (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+            |(min_x, min_y, max_x, max_y), PointF(x, y)| {
+                (min_x.min(*x), min_y.min(*y), max_x.max(*x), max_y.max(*y))
+            },
+        );
+
+        let (width, height) = (region.width() as f32, region.height() as f32);
+        let scale = (width / (max_x - min_x).max(f32::EPSILON))
+            .min(height / (max_y - min_y).max(f32::EPSILON));
+        // center the drawing in the region
+        let offset = PointF(
+            region.start.0 as f32 + (width - (max_x - min_x) * scale) / 2.0,
+            region.start.1 as f32 + (height - (max_y - min_y) * scale) / 2.0,
+        );
+        let fit =
+            |PointF(x, y): &PointF| offset.translated((x - min_x) * scale, (y - min_y) * scale);
+
+        for (i, (start, end)) in segments.iter().enumerate() {
+            layer.add_object(
+                format!("{}#{}", name, i),
+                Object::LineF(fit(start), fit(end), line_width).into(),
+            );
+        }
+
+        layer
+    }
+}
@@ -0,0 +1,94 @@
+use rand::{seq::SliceRandom as _, Rng};
+
+use crate::{
+    random::{chance, rng},
+    Color, Fill, Layer, Object, Region,
+};
+
+/// Recursively splits a region into rectangles, Mondrian-style
+#[derive(Debug, Clone)]
+pub struct Subdivision {
+    /// Probability for a rectangle to stop being split, checked at every step. Clamped to [0, 1].
+    pub stop_probability: f64,
+    /// Empty cells left between the two halves of a split
+    pub gap: usize,
+    /// Rectangles are filled with a random color from this palette
+    pub palette: Vec<Color>,
+}
+
+impl Default for Subdivision {
+    fn default() -> Self {
+        Self {
+            stop_probability: 0.3,
+            gap: 0,
+            palette: vec![Color::White, Color::Red, Color::Blue, Color::Yellow],
+        }
+    }
+}
+
+impl Subdivision {
+    pub fn with_stop_probability(self, stop_probability: f64) -> Self {
+        Self {
+            stop_probability,
+            ..self
+        }
+    }
+
+    pub fn with_gap(self, gap: usize) -> Self {
+        Self { gap, ..self }
+    }
+
+    pub fn with_palette(self, palette: Vec<Color>) -> Self {
+        Self { palette, ..self }
+    }
+
+    /// Rectangles the region gets split into
+    pub fn split(&self, region: &Region) -> Vec<Region> {
+        // each half needs at least one cell
+        let can_split_x = region.width() >= self.gap + 2;
+        let can_split_y = region.height() >= self.gap + 2;
+        if !(can_split_x || can_split_y) || chance(self.stop_probability) {
+            return vec![*region];
+        }
+
+        // prefer cutting across the longer side, so that rectangles don't get too thin
+        let vertical_cut = match (can_split_x, can_split_y) {
+            (true, false) => true,
+            (false, true) => false,
+            _ => rng().gen_bool(region.width() as f64 / (region.width() + region.height()) as f64),
+        };
+
+        let (start, end) = if vertical_cut {
+            (region.start.0, region.end.0)
+        } else {
+            (region.start.1, region.end.1)
+        };
+        // first cell of the second half
+        let cut = rng().gen_range(start + 1 + self.gap..=end);
+
+        let (mut first, mut second) = (*region, *region);
+        if vertical_cut {
+            first.end.0 = cut - 1 - self.gap;
+            second.start.0 = cut;
+        } else {
+            first.end.1 = cut - 1 - self.gap;
+            second.start.1 = cut;
+        }
+
+        let mut rectangles = self.split(&first);
+        rectangles.extend(self.split(&second));
+        rectangles
+    }
+
+    pub fn layer(&self, name: &str, region: &Region) -> Layer {
+        let mut layer = Layer::new(name);
+        for (i, rectangle) in self.split(region).iter().enumerate() {
+            let color = self.palette.choose(&mut rng()).cloned().unwrap_or_default();
+            layer.add_object(
+                format!("{}#{}", name, i),
+                Object::Rectangle(rectangle.start, rectangle.end).color(Fill::Solid(color)),
+            );
+        }
+        layer
+    }
+}
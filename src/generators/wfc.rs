@@ -0,0 +1,274 @@
+use anyhow::{format_err, Result};
+use rand::{distributions::WeightedIndex, prelude::Distribution as _, seq::SliceRandom as _};
+
+use crate::{random::rng, Layer, PointF, Region, Transformation};
+
+/// How many times the solver starts over after running into a contradiction
+const MAX_ATTEMPTS: usize = 100;
+
+/// A square piece of pattern for [`WaveFunctionCollapse`].
+/// Its objects are placed as if the tile was at the origin, and should stay within `tile_size` cells.
+#[derive(Debug, Clone)]
+pub struct Tile {
+    pub name: String,
+    pub layer: Layer,
+    /// Labels of the top, right, bottom and left edges. Two tiles can be side by side if the edges that touch have the same label.
+    pub edges: [String; 4],
+    /// How likely this tile is to be picked, relative to the others
+    pub weight: f32,
+}
+
+impl Tile {
+    pub fn new(name: &str, layer: Layer, edges: [&str; 4]) -> Self {
+        Self {
+            name: name.to_string(),
+            layer,
+            edges: edges.map(String::from),
+            weight: 1.0,
+        }
+    }
+
+    pub fn with_weight(self, weight: f32) -> Self {
+        Self { weight, ..self }
+    }
+
+    /// The tile turned clockwise by a quarter turn `turns` times, for a tile of `tile_size` cells
+    pub fn rotated(&self, turns: usize, tile_size: usize) -> Self {
+        let turns = turns % 4;
+        let mut rotated = self.clone();
+        rotated.name = format!("{}@{}", self.name, turns * 90);
+        rotated.edges.rotate_right(turns);
+
+        if turns > 0 {
+            let center = PointF(tile_size as f32 / 2.0, tile_size as f32 / 2.0);
+            for object in rotated.layer.objects.values_mut() {
+                object
+                    .transformations
+                    .insert(0, Transformation::RotateAround(center, (turns * 90) as f32));
+            }
+            rotated.layer.flush();
+        }
+
+        rotated
+    }
+}
+
+/// Fills a region with tiles so that all adjacent edges match
+#[derive(Debug, Clone)]
+pub struct WaveFunctionCollapse {
+    pub tiles: Vec<Tile>,
+    /// Width and height of every tile, in cells
+    pub tile_size: usize,
+}
+
+/// Offsets to the top, right, bottom and left neighbors, in the same order as [`Tile::edges`]
+const NEIGHBORS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+impl WaveFunctionCollapse {
+    pub fn new(tiles: Vec<Tile>, tile_size: usize) -> Self {
+        Self { tiles, tile_size }
+    }
+
+    /// Also adds the three other orientations of every tile
+    pub fn with_rotations(self) -> Self {
+        let tiles = self
+            .tiles
+            .iter()
+            .flat_map(|tile| (0..4).map(|turns| tile.rotated(turns, self.tile_size)))
+            .collect();
+        Self { tiles, ..self }
+    }
+
+    fn compatible(&self, tile: usize, neighbor: usize, direction: usize) -> bool {
+        self.tiles[tile].edges[direction] == self.tiles[neighbor].edges[(direction + 2) % 4]
+    }
+
+    /// Whether every tile of the solution matches its right and bottom neighbors
+    fn fits(&self, solution: &[usize], columns: usize) -> bool {
+        solution.iter().enumerate().all(|(slot, &tile)| {
+            let fits_right =
+                slot % columns + 1 == columns || self.compatible(tile, solution[slot + 1], 1);
+            let fits_below = slot + columns >= solution.len()
+                || self.compatible(tile, solution[slot + columns], 2);
+            fits_right && fits_below
+        })
+    }
+
+    /// Index of the tile chosen for each slot of a columns × rows grid, in row-major order
+    pub fn solve(&self, columns: usize, rows: usize) -> Result<Vec<usize>> {
+        if self.tiles.is_empty() {
+            return Err(format_err!("No tiles to choose from"));
+        }
+
+        for _ in 0..MAX_ATTEMPTS {
+            if let Some(solution) = self.attempt(columns, rows) {
+                return Ok(solution);
+            }
+        }
+
+        Err(format_err!(
+            "Could not tile a {}×{} grid without contradictions after {} attempts",
+            columns,
+            rows,
+            MAX_ATTEMPTS
+        ))
+    }
+
+    /// One run of the solver, None if it ran into a slot where no tile fits
+    fn attempt(&self, columns: usize, rows: usize) -> Option<Vec<usize>> {
+        let mut rng = rng();
+        let mut possibilities: Vec<Vec<usize>> =
+            vec![(0..self.tiles.len()).collect(); columns * rows];
+
+        loop {
+            // collapse the most constrained slot first
+            let undecided = possibilities
+                .iter()
+                .enumerate()
+                .filter(|(_, tiles)| tiles.len() > 1)
+                .map(|(slot, tiles)| (tiles.len(), slot))
+                .collect::<Vec<_>>();
+            let Some(fewest) = undecided.iter().map(|(count, _)| *count).min() else {
+                let solution: Vec<usize> = possibilities.iter().map(|tiles| tiles[0]).collect();
+                // slots that were decided from the start, such as with a single tile, never went through propagation
+                return self.fits(&solution, columns).then_some(solution);
+            };
+            let &&(_, slot) = undecided
+                .iter()
+                .filter(|(count, _)| *count == fewest)
+                .collect::<Vec<_>>()
+                .choose(&mut rng)?;
+
+            let weights = WeightedIndex::new(
+                possibilities[slot]
+                    .iter()
+                    .map(|&tile| self.tiles[tile].weight),
+            )
+            .ok()?;
+            possibilities[slot] = vec![possibilities[slot][weights.sample(&mut rng)]];
+
+            // remove tiles that don't fit next to their neighbors anymore, until nothing changes
+            let mut to_visit = vec![slot];
+            while let Some(slot) = to_visit.pop() {
+                let (x, y) = ((slot % columns) as i32, (slot / columns) as i32);
+                for (direction, (dx, dy)) in NEIGHBORS.iter().enumerate() {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= columns as i32 || ny >= rows as i32 {
+                        continue;
+                    }
+
+                    let neighbor = ny as usize * columns + nx as usize;
+                    let before = possibilities[neighbor].len();
+                    let current = possibilities[slot].clone();
+                    possibilities[neighbor].retain(|&candidate| {
+                        current
+                            .iter()
+                            .any(|&tile| self.compatible(tile, candidate, direction))
+                    });
+
+                    match possibilities[neighbor].len() {
+                        0 => return None,
+                        len if len < before => to_visit.push(neighbor),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Solves for as many tiles as fit in the region, and puts their objects in a single layer.
+    /// Objects are named "name#column-row/tile object name".
+    pub fn layer(&self, name: &str, region: &Region) -> Result<Layer> {
+        if self.tile_size == 0 {
+            return Err(format_err!("Tiles should be at least one cell wide"));
+        }
+
+        let (columns, rows) = (
+            region.width() / self.tile_size,
+            region.height() / self.tile_size,
+        );
+        let solution = self.solve(columns, rows)?;
+
+        let mut layer = Layer::new(name);
+        for (slot, &tile) in solution.iter().enumerate() {
+            let (column, row) = (slot % columns, slot / columns);
+            let (dx, dy) = (
+                (region.start.0 + column * self.tile_size) as i32,
+                (region.start.1 + row * self.tile_size) as i32,
+            );
+
+            for (object_name, object) in &self.tiles[tile].layer.objects {
+                let mut placed = object.clone();
+                placed.object.translate(dx, dy);
+                // rotations of rotated tiles are around the tile's center, which moved too
+                for transformation in placed.transformations.iter_mut() {
+                    if let Transformation::RotateAround(center, _) = transformation {
+                        center.translate(dx as f32, dy as f32);
+                    }
+                }
+                layer.add_object(
+                    format!("{}#{}-{}/{}", name, column, row, object_name),
+                    placed,
+                );
+            }
+        }
+
+        Ok(layer)
+    }
+}
+
+#[cfg(test)]
+fn tile_set(edges: &[[&str; 4]]) -> WaveFunctionCollapse {
+    let tiles = edges
+        .iter()
+        .enumerate()
+        .map(|(i, edges)| Tile::new(&i.to_string(), Layer::new("tile"), *edges))
+        .collect();
+    WaveFunctionCollapse::new(tiles, 1)
+}
+
+#[test]
+fn test_solvable_tile_set() {
+    let wfc = tile_set(&[
+        ["a", "b", "a", "b"],
+        ["b", "a", "b", "a"],
+        ["a", "a", "a", "a"],
+    ]);
+    let solution = wfc.solve(4, 3).unwrap();
+    assert_eq!(solution.len(), 12);
+    assert!(wfc.fits(&solution, 4));
+}
+
+#[test]
+fn test_unsolvable_tile_set() {
+    // a lone tile whose top edge doesn't match its bottom edge can't be stacked
+    let wfc = tile_set(&[["a", "c", "b", "c"]]);
+    assert!(wfc.solve(1, 2).is_err());
+    assert!(wfc.solve(2, 1).is_ok());
+}
+
+#[test]
+fn test_zero_tile_size() {
+    let wfc = WaveFunctionCollapse {
+        tile_size: 0,
+        ..tile_set(&[["a", "a", "a", "a"]])
+    };
+    assert!(wfc
+        .layer("tiles", &Region::new(0, 0, 3, 3).unwrap())
+        .is_err());
+}
+
+#[test]
+fn test_same_seed_same_tiling() {
+    let _lock = crate::random::TEST_RNG_LOCK.lock().unwrap();
+    let wfc = tile_set(&[
+        ["a", "b", "a", "b"],
+        ["b", "a", "b", "a"],
+        ["a", "a", "a", "a"],
+        ["b", "b", "b", "b"],
+    ]);
+    crate::random::set_seed(42);
+    let first = wfc.solve(8, 8).unwrap();
+    crate::random::set_seed(42);
+    assert_eq!(wfc.solve(8, 8).unwrap(), first);
+}
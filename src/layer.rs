@@ -1,29 +1,139 @@
-use crate::{ColorMapping, ColoredObject, Fill, Filter, ObjectSizes, Region, Toggleable};
-use std::{collections::HashMap, fmt::Display};
+use crate::{
+    point::GridLayout, Axis, Body, ColorMapping, ColoredObject, Containable, Fill, Filter, Object,
+    ObjectSizes, PointF, Region, Toggleable, Transformation,
+};
+use indexmap::IndexMap;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use slug::slugify;
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    hash::{DefaultHasher, Hash as _, Hasher},
+    io,
+};
 
-#[derive(Debug, Clone, Default)]
+/// How a layer is composited with what is below it, see CSS' mix-blend-mode
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    Difference,
+}
+
+impl BlendMode {
+    pub fn css_name(&self) -> &'static str {
+        match self {
+            BlendMode::Normal => "normal",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Overlay => "overlay",
+            BlendMode::Darken => "darken",
+            BlendMode::Lighten => "lighten",
+            BlendMode::Difference => "difference",
+        }
+    }
+}
+
+/// A layer used to hold other layers, see `Layer::group`
+pub type LayerGroup = Layer;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 // #[wasm_bindgen(getter_with_clone)]
 pub struct Layer {
     pub object_sizes: ObjectSizes,
-    pub objects: HashMap<String, ColoredObject>,
+    /// In insertion order, which is also the order they are rendered in (later objects are drawn on top)
+    pub objects: IndexMap<String, ColoredObject>,
     pub name: String,
     pub hidden: bool,
-    pub _render_cache: Option<svg::node::element::Group>,
+    /// Only the part of the layer inside this region is visible
+    pub clip: Option<Region>,
+    /// Physics bodies moving objects around, by object name. See `Canvas::step_physics`.
+    pub bodies: HashMap<String, Body>,
+    /// Opacity of the whole layer, from 0 to 1. Can be animated with `Context::animate_layer` or `Context::fade_layer`.
+    pub opacity: f32,
+    /// Applied to the whole layer, around the center of the canvas
+    pub transformations: Vec<Transformation>,
+    pub blend_mode: BlendMode,
+    /// Layers nested in this one, rendered above its own objects. Like the canvas' layers, the first child is on top.
+    /// Children get their parent's opacity, transformations and blend mode on top of their own.
+    pub children: Vec<Layer>,
+    /// Last render, with the content hash it was rendered from (see `Layer::content_hash`)
+    #[serde(skip)]
+    pub _render_cache: Option<(u64, svg::node::element::Group)>,
 }
 
-static DISABLE_CACHE: bool = true;
+impl Default for Layer {
+    fn default() -> Self {
+        Layer::new("")
+    }
+}
 
 impl Layer {
     pub fn new(name: &str) -> Self {
         Layer {
             object_sizes: ObjectSizes::default(),
-            objects: HashMap::new(),
+            objects: IndexMap::new(),
             name: name.to_string(),
             _render_cache: None,
             hidden: false,
+            clip: None,
+            bodies: HashMap::new(),
+            opacity: 1.0,
+            transformations: vec![],
+            blend_mode: BlendMode::Normal,
+            children: vec![],
+        }
+    }
+
+    /// A layer with no objects of its own, holding the given layers (first one on top)
+    pub fn group(name: &str, children: Vec<Layer>) -> LayerGroup {
+        Layer {
+            children,
+            ..Layer::new(name)
         }
     }
 
+    pub fn add_child(&mut self, layer: Layer) {
+        self.children.push(layer);
+        self.flush();
+    }
+
+    /// Layer with the given name among this one and its descendants, depth-first
+    pub fn find(&mut self, name: &str) -> Option<&mut Layer> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter_mut().find_map(|child| child.find(name))
+    }
+
+    /// This layer and all of its descendants, depth-first
+    pub fn descendants(&self) -> Vec<&Layer> {
+        std::iter::once(self)
+            .chain(self.children.iter().flat_map(|child| child.descendants()))
+            .collect()
+    }
+
+    /// Whether this layer is selected by the given patterns. See [`layer_name_matches`].
+    pub fn matches(&self, patterns: &[&str]) -> bool {
+        layer_name_matches(&self.name, patterns)
+    }
+
+    pub fn clip_to(&mut self, region: Region) {
+        self.clip = Some(region);
+        self.flush();
+    }
+
+    pub fn unclip(&mut self) {
+        self.clip = None;
+        self.flush();
+    }
+
     pub fn hide(&mut self) {
         self.hidden = true;
     }
@@ -36,15 +146,61 @@ impl Layer {
         self.hidden.toggle();
     }
 
+    /// Transforms the whole layer at once, which is much cheaper than transforming every object
+    pub fn transform(&mut self, transformation: Transformation) {
+        self.transformations.push(transformation);
+    }
+
+    pub fn clear_transformations(&mut self) {
+        self.transformations.clear();
+    }
+
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+        self.flush();
+    }
+
+    /// ID of the layer's rendered group, the same from one frame to the next.
+    /// Names that aren't already slugs get a hash of the name appended, so that e.g. "Drums" and "drums" don't share an ID.
+    pub fn group_id(&self) -> String {
+        let slug = slugify(&self.name);
+        if slug == self.name {
+            return format!("layer-{}", slug);
+        }
+        let mut hasher = DefaultHasher::new();
+        self.name.hash(&mut hasher);
+        format!("layer-{}-{:x}", slug, hasher.finish() as u32)
+    }
+
+    /// Override the dot radius, line width, etc. used by this layer's objects. Layers start with the canvas' sizes.
+    pub fn set_object_sizes(&mut self, object_sizes: ObjectSizes) {
+        self.object_sizes = object_sizes;
+        self.flush();
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self.flush();
+    }
+
     pub fn object(&mut self, name: &str) -> &mut ColoredObject {
         self.safe_object(name).unwrap()
     }
 
+    pub fn objects_tagged<'a>(
+        &'a mut self,
+        tag: &'a str,
+    ) -> impl Iterator<Item = &'a mut ColoredObject> + 'a {
+        // objects get modified through the returned iterator, so the cached render becomes stale
+        self.flush();
+        self.objects.values_mut().filter(move |o| o.has_tag(tag))
+    }
+
     pub fn safe_object(&mut self, name: &str) -> Option<&mut ColoredObject> {
         self.objects.get_mut(name)
     }
 
-    // Flush the render cache.
+    // Flush the render cache. Not needed to see changes, since the cache is keyed by the layer's contents, but frees the memory.
     pub fn flush(&mut self) {
         self._render_cache = None;
     }
@@ -54,14 +210,16 @@ impl Layer {
         self.flush();
     }
 
-    pub fn remove_all_objects_in(&mut self, region: &Region) {
+    /// Removes objects entirely inside area, which can be a Region or a Mask
+    pub fn remove_all_objects_in<C: Containable<Region>>(&mut self, area: &C) {
         self.objects
-            .retain(|_, ColoredObject { object, .. }| !object.region().within(region))
+            .retain(|_, ColoredObject { object, .. }| !area.contains(&object.region()));
+        self.flush();
     }
 
     pub fn paint_all_objects(&mut self, fill: Fill) {
         for (_id, obj) in &mut self.objects {
-            obj.fill = Some(fill);
+            obj.fill = Some(fill.clone());
         }
         self.flush();
     }
@@ -80,6 +238,98 @@ impl Layer {
         self.flush();
     }
 
+    /// Adds count objects made by factory at evenly spaced points along path (see [`Object::points_along`]).
+    /// factory gets the point and its index. Objects are named "layer name#n", skipping names that are already taken.
+    pub fn place_along(
+        &mut self,
+        path: &Object,
+        count: usize,
+        factory: impl Fn(PointF, usize) -> ColoredObject,
+    ) {
+        let mut n = self.objects.len();
+        for (i, point) in path.points_along(count).into_iter().enumerate() {
+            while self.objects.contains_key(&format!("{}#{}", self.name, n)) {
+                n += 1;
+            }
+            self.add_object(format!("{}#{}", self.name, n), factory(point, i));
+        }
+    }
+
+    /// Deep copy of this layer (children included) named new_name, with every object moved by offset cells.
+    /// Object names starting with the layer's name and a # (e.g. "layer#3") are renamed to start with new_name instead.
+    /// Children are renamed to new_name/child, so that their names stay unique on the canvas.
+    pub fn duplicated_as(&self, new_name: &str, offset: (i32, i32)) -> Layer {
+        let objects_prefix = format!("{}#", self.name);
+        let rename = |key: &String| match key.strip_prefix(&objects_prefix) {
+            Some(rest) => format!("{}#{}", new_name, rest),
+            None => key.clone(),
+        };
+
+        let mut duplicate = self.clone();
+        duplicate.name = new_name.to_string();
+        duplicate.objects = self
+            .objects
+            .iter()
+            .map(|(key, object)| (rename(key), object.clone()))
+            .collect();
+        duplicate.bodies = self
+            .bodies
+            .iter()
+            .map(|(key, body)| (rename(key), *body))
+            .collect();
+
+        let children_prefix = format!("{}/", self.name);
+        duplicate.children = self
+            .children
+            .iter()
+            .map(|child| {
+                let name = child
+                    .name
+                    .strip_prefix(&children_prefix)
+                    .unwrap_or(&child.name);
+                child.duplicated_as(&format!("{}/{}", new_name, name), offset)
+            })
+            .collect();
+
+        if offset != (0, 0) {
+            let (dx, dy) = offset;
+            duplicate.move_all_objects(dx, dy);
+            if let Some(clip) = duplicate.clip.as_mut() {
+                clip.translate(dx, dy);
+            }
+        }
+
+        duplicate.flush();
+        duplicate
+    }
+
+    /// Copy of this layer where every object also gets reflected across the midline(s) of `world_region`.
+    /// Reflections are named after their original, e.g. `name.mirrored-vertical`.
+    pub fn mirrored(&self, axis: Axis, world_region: &Region) -> Layer {
+        let reflections: &[Axis] = match axis {
+            Axis::Both => &[Axis::Vertical, Axis::Horizontal, Axis::Both],
+            _ => &[axis],
+        };
+
+        let mut layer = self.clone();
+        for (name, object) in &self.objects {
+            for reflection in reflections {
+                layer.set_object(
+                    format!(
+                        "{}.mirrored-{}",
+                        name,
+                        format!("{:?}", reflection).to_lowercase()
+                    ),
+                    ColoredObject {
+                        object: object.object.mirrored(*reflection, world_region),
+                        ..object.clone()
+                    },
+                );
+            }
+        }
+        layer
+    }
+
     pub fn add_object<'a, N: Display>(&mut self, name: N, object: ColoredObject) {
         let name_str = format!("{}", name);
 
@@ -109,37 +359,255 @@ impl Layer {
     }
 
     pub fn remove_object(&mut self, name: &str) {
-        self.objects.remove(name);
+        self.objects.shift_remove(name);
+        self.bodies.remove(name);
+        self.flush();
+    }
+
+    /// Makes the object move according to physics, see [`Body`]
+    pub fn attach_body(&mut self, name: &str, body: Body) {
+        self.bodies.insert(name.to_string(), body);
         self.flush();
     }
 
+    pub fn detach_body(&mut self, name: &str) {
+        self.bodies.remove(name);
+        self.flush();
+    }
+
+    pub fn body(&mut self, name: &str) -> Option<&mut Body> {
+        self.bodies.get_mut(name)
+    }
+
     pub fn replace_object(&mut self, name: &str, object: ColoredObject) {
         self.remove_object(name);
         self.add_object(name, object);
     }
 
-    /// Render the layer to a SVG group element.
+    /// Moves objects that have a physics body attached, in this layer and its children. See [`Body::step`].
+    pub fn step_physics(&mut self, ms: usize, world: &Region) {
+        if !self.bodies.is_empty() {
+            for (name, body) in self.bodies.iter_mut() {
+                if let Some(object) = self.objects.get(name) {
+                    body.step(ms, &object.object.region(), world);
+                }
+            }
+            self.flush();
+        }
+
+        for child in self.children.iter_mut() {
+            child.step_physics(ms, world);
+        }
+    }
+
+    /// Hash of everything that goes into the layer's render, so that the render cache never gets stale
+    fn content_hash(
+        &self,
+        colormap: &ColorMapping,
+        layout: GridLayout,
+        object_sizes: ObjectSizes,
+        origin: (f32, f32),
+    ) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hash_serialized(
+            &(
+                &self.name,
+                &self.objects,
+                &self.bodies,
+                &self.clip,
+                self.opacity,
+                &self.transformations,
+                self.blend_mode,
+                colormap,
+                object_sizes,
+                origin,
+            ),
+            &mut hasher,
+        );
+        layout.hash(&mut hasher);
+
+        for child in self.children.iter().filter(|child| !child.hidden) {
+            child
+                .content_hash(colormap, layout, child.object_sizes, origin)
+                .hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Render the layer and its children to a SVG group element.
+    /// origin is where the layer's transformations are applied around, in SVG coordinates. Usually the center of the canvas.
     pub fn render(
         &mut self,
         colormap: ColorMapping,
-        cell_size: usize,
+        layout: GridLayout,
         object_sizes: ObjectSizes,
+        origin: (f32, f32),
     ) -> svg::node::element::Group {
-        if !DISABLE_CACHE {
-            if let Some(cached_svg) = &self._render_cache {
+        let hash = self.content_hash(&colormap, layout, object_sizes, origin);
+        if let Some((cached_hash, cached_svg)) = &self._render_cache {
+            if *cached_hash == hash {
                 return cached_svg.clone();
             }
         }
 
         let mut layer_group = svg::node::element::Group::new()
             .set("class", "layer")
+            .set("id", self.group_id())
             .set("data-layer", self.name.clone());
 
+        if self.opacity < 1.0 {
+            layer_group = layer_group.set("opacity", self.opacity);
+        }
+
+        if !self.transformations.is_empty() {
+            layer_group = layer_group.set(
+                "transform",
+                self.transformations
+                    .iter()
+                    .map(|t| t.render_around(origin, layout))
+                    .join(" "),
+            );
+        }
+
+        if self.blend_mode != BlendMode::Normal {
+            layer_group = layer_group.set(
+                "style",
+                format!("mix-blend-mode: {};", self.blend_mode.css_name()),
+            );
+        }
+
+        if let Some(region) = self.clip {
+            let clip = Object::Rectangle(region.start, region.end);
+            layer_group = layer_group.set(
+                "clip-path",
+                format!("url(#{})", clip.clip_path_id(layout, object_sizes)),
+            );
+        }
+
         for (id, obj) in &self.objects {
-            layer_group = layer_group.add(obj.render(cell_size, object_sizes, &colormap, id));
+            let rendered = obj.render(layout, object_sizes, &colormap, id);
+            layer_group = match self.bodies.get(id) {
+                Some(body) => layer_group.add(
+                    svg::node::element::Group::new()
+                        .set(
+                            "transform",
+                            format!(
+                                "translate({} {})",
+                                body.position.0 * layout.cell_size as f32,
+                                body.position.1 * layout.cell_size as f32
+                            ),
+                        )
+                        .add(rendered),
+                ),
+                None => layer_group.add(rendered),
+            };
+        }
+
+        for child in self.children.iter_mut().filter(|child| !child.hidden).rev() {
+            let child_object_sizes = child.object_sizes;
+            layer_group =
+                layer_group.add(child.render(colormap.clone(), layout, child_object_sizes, origin));
         }
 
-        self._render_cache = Some(layer_group.clone());
+        self._render_cache = Some((hash, layer_group.clone()));
         layer_group
     }
 }
+
+/// Feeds everything written to it to a hasher, to hash values holding floats through their serialization
+pub(crate) struct HashWriter<'a, H: Hasher>(pub &'a mut H);
+
+/// Hashes values that hold floats, which don't implement Hash, through their binary CBOR encoding.
+/// Values that can't be serialized, such as [`Object::RawSVG`], are hashed through their debug representation instead.
+pub(crate) fn hash_serialized<T: Serialize + Debug>(value: &T, hasher: &mut impl Hasher) {
+    if serde_cbor::to_writer(HashWriter(hasher), value).is_err() {
+        format!("{:?}", value).hash(hasher);
+    }
+}
+
+impl<H: Hasher> io::Write for HashWriter<'_, H> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.write(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Whether a layer name is selected by a list of patterns.
+/// Patterns support `*` wildcards ("drums*") and are negated with a leading `!` ("!debug*").
+/// A name is selected if it matches any positive pattern (or if there are none) and no negated pattern.
+pub fn layer_name_matches(name: &str, patterns: &[&str]) -> bool {
+    let (excluded, included): (Vec<&str>, Vec<&str>) = patterns
+        .iter()
+        .copied()
+        .partition(|pattern| pattern.starts_with('!'));
+
+    (included.is_empty() || included.iter().any(|pattern| glob_matches(pattern, name)))
+        && !excluded
+            .iter()
+            .any(|pattern| glob_matches(&pattern[1..], name))
+}
+
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            name.starts_with(prefix)
+                && (prefix.len()..=name.len())
+                    .filter(|&i| name.is_char_boundary(i))
+                    .any(|i| glob_matches(rest, &name[i..]))
+        }
+    }
+}
+
+#[test]
+fn test_duplicated_as() {
+    use crate::Point;
+
+    let mut child = Layer::new("layer1/child");
+    child.add_object("layer1/child#0", Object::Dot(Point(0, 0)).into());
+    let mut layer = Layer::new("layer1");
+    layer.add_object("layer1#0", Object::Dot(Point(0, 0)).into());
+    layer.add_object("layer10#0", Object::Dot(Point(0, 0)).into());
+    layer.add_child(child);
+
+    let duplicate = layer.duplicated_as("echo", (1, 0));
+    assert_eq!(
+        duplicate.objects.keys().collect::<Vec<_>>(),
+        vec!["echo#0", "layer10#0"]
+    );
+    assert_eq!(duplicate.children[0].name, "echo/child");
+    assert!(matches!(
+        duplicate.children[0].objects["echo/child#0"].object,
+        Object::Dot(Point(1, 0))
+    ));
+}
+
+#[test]
+fn test_group_id() {
+    assert_eq!(Layer::new("drums").group_id(), "layer-drums");
+    assert_ne!(
+        Layer::new("Drums").group_id(),
+        Layer::new("drums").group_id()
+    );
+    assert_ne!(Layer::new("a b").group_id(), Layer::new("a-b").group_id());
+    assert_eq!(Layer::new("a b").group_id(), Layer::new("a b").group_id());
+}
+
+#[test]
+fn test_objects_order_survives_serde() {
+    use crate::Point;
+
+    let mut layer = Layer::new("layer");
+    for name in ["b", "c", "a"] {
+        layer.add_object(name, Object::Dot(Point(0, 0)).into());
+    }
+    let layer: Layer = serde_json::from_str(&serde_json::to_string(&layer).unwrap()).unwrap();
+    assert_eq!(
+        layer.objects.keys().collect::<Vec<_>>(),
+        vec!["b", "c", "a"]
+    );
+}
@@ -8,39 +8,43 @@ pub mod color;
 pub mod examples;
 pub mod fill;
 pub mod filter;
+pub mod gallery;
+pub mod generators;
 pub mod layer;
+pub mod mask;
 pub mod midi;
+pub mod morph;
+pub mod noise;
 pub mod objects;
+pub mod palettes;
+pub mod physics;
+#[cfg(feature = "plugins")]
+pub mod plugins;
 pub mod point;
+pub mod prelude;
 pub mod preview;
+pub mod random;
 pub mod region;
+pub mod scene;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod stroke;
 pub mod sync;
+pub mod text;
 pub mod transform;
 pub mod ui;
 pub mod video;
 pub mod web;
-pub use animation::*;
+pub mod web_audio;
 use anyhow::Result;
-pub use audio::*;
-pub use canvas::*;
-pub use color::*;
-pub use fill::*;
-pub use filter::*;
 use itertools::Itertools;
-pub use layer::*;
-pub use midi::MidiSynchronizer;
-pub use objects::*;
-pub use point::*;
-pub use region::*;
-pub use sync::Syncable;
-pub use transform::*;
-pub use video::*;
-pub use web::log;
+pub use prelude::*;
 
 use nanoid::nanoid;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs::{self};
 use std::path::PathBuf;
-use sync::SyncData;
 
 pub struct Context<'a, AdditionalContext = ()> {
     pub frame: usize,
@@ -52,12 +56,16 @@ pub struct Context<'a, AdditionalContext = ()> {
     pub syncdata: &'a SyncData,
     pub audiofile: PathBuf,
     pub later_hooks: Vec<LaterHook<AdditionalContext>>,
+    /// Current values of the video's parameters, see `Video::parameter`
+    pub params: HashMap<String, f32>,
+    /// Audio analysed as the video plays, by stem name. Takes precedence over `syncdata`'s stems.
+    pub live_stems: HashMap<String, LiveStem>,
     pub extra: AdditionalContext,
     pub duration_override: Option<usize>,
 }
 
 impl<'a, C> Context<'a, C> {
-    pub fn stem(&self, name: &str) -> StemAtInstant {
+    fn syncdata_stem(&self, name: &str) -> &Stem {
         let stems = &self.syncdata.stems;
         if !stems.contains_key(name) {
             panic!(
@@ -69,21 +77,68 @@ impl<'a, C> Context<'a, C> {
                     .fold(String::new(), |acc, k| format!("{acc}\n\t{k}"))
             );
         }
+        &stems[name]
+    }
+
+    /// Value of a parameter declared with `Video::parameter`
+    pub fn param(&self, name: &str) -> f32 {
+        match self.params.get(name) {
+            Some(value) => *value,
+            None => panic!(
+                "No parameter named {:?} found. Available parameters:\n{}\n",
+                name,
+                self.params
+                    .keys()
+                    .sorted()
+                    .fold(String::new(), |acc, k| format!("{acc}\n\t{k}"))
+            ),
+        }
+    }
+
+    pub fn stem(&self, name: &str) -> StemAtInstant {
+        if let Some(stem) = self.live_stems.get(name) {
+            return stem.into();
+        }
+
+        let stem = self.syncdata_stem(name);
         StemAtInstant {
-            amplitude: *stems[name].amplitude_db.get(self.ms).unwrap_or(&0.0),
-            amplitude_max: stems[name].amplitude_max,
-            velocity_max: stems[name]
+            amplitude: *stem.amplitude_db.get(self.ms).unwrap_or(&0.0),
+            amplitude_max: stem.amplitude_max,
+            velocity_max: stem
                 .notes
                 .get(&self.ms)
                 .iter()
                 .map(|notes| notes.iter().map(|note| note.velocity).max().unwrap_or(0))
                 .max()
                 .unwrap_or(0),
-            duration: stems[name].duration_ms,
-            notes: stems[name].notes.get(&self.ms).cloned().unwrap_or(vec![]),
+            duration: stem.duration_ms,
+            notes: stem.notes.get(&self.ms).cloned().unwrap_or(vec![]),
+        }
+    }
+
+    /// Current spectrum of a live stem, see `LiveStem::spectrum`
+    pub fn spectrum(&self, name: &str) -> &[f32] {
+        match self.live_stems.get(name) {
+            Some(stem) => &stem.spectrum,
+            None => panic!(
+                "No live stem named {:?} found. Available live stems:\n{}\n",
+                name,
+                self.live_stems
+                    .keys()
+                    .sorted()
+                    .fold(String::new(), |acc, k| format!("{acc}\n\t{k}"))
+            ),
         }
     }
 
+    /// Amplitude statistics of the stem over the last `past_ms` milliseconds, current instant included.
+    pub fn stem_window(&self, name: &str, past_ms: usize) -> StemWindow {
+        let stem = self.syncdata_stem(name);
+        let end = (self.ms + 1).min(stem.amplitude_db.len());
+        let start = self.ms.saturating_sub(past_ms).min(end);
+        StemWindow::from_amplitudes(&stem.amplitude_db[start..end], stem.amplitude_max)
+    }
+
     pub fn dump_syncdata(&self, to: PathBuf) -> Result<()> {
         Ok(serde_cbor::to_writer(fs::File::create(to)?, self.syncdata)?)
     }
@@ -175,6 +230,41 @@ impl<'a, C> Context<'a, C> {
         );
     }
 
+    /// Morphs an object into target over duration ms (see [`morph`]). The object is replaced by target once done.
+    pub fn morph_object(&mut self, layer: &str, name: &str, target: Object, duration: usize) {
+        let (layer, name) = (layer.to_string(), name.to_string());
+        // the starting shape is whatever the object looks like when the animation starts
+        let source: RefCell<Option<Object>> = RefCell::new(None);
+
+        let (morphing_layer, morphing_name, morphing_target) =
+            (layer.clone(), name.clone(), target.clone());
+        self.start_animation(
+            duration,
+            Animation {
+                name: format!("morph {}/{}", layer, name),
+                update: Box::new(move |t, canvas, _| {
+                    let object = canvas.layer(&morphing_layer).object(&morphing_name);
+                    let mut source = source.borrow_mut();
+                    let from = source.get_or_insert_with(|| object.object.clone());
+                    object.object = morph(from, &morphing_target, t);
+                    canvas.layer(&morphing_layer).flush();
+                    Ok(())
+                }),
+            },
+        );
+
+        let end_ms = self.ms + duration;
+        self.later_hooks.push(LaterHook {
+            once: true,
+            when: Box::new(move |_, context, _| context.ms >= end_ms),
+            render_function: Box::new(move |canvas, _| {
+                canvas.layer(&layer).object(&name).object = target.clone();
+                canvas.layer(&layer).flush();
+                Ok(())
+            }),
+        });
+    }
+
     pub fn animate_layer(
         &mut self,
         layer: &'static str,
@@ -192,6 +282,20 @@ impl<'a, C> Context<'a, C> {
 
         self.start_animation(duration, animation);
     }
+
+    /// Animates the layer's opacity from `from` to `to` over duration ms
+    pub fn fade_layer(&mut self, layer: &'static str, duration: usize, from: f32, to: f32) {
+        self.start_animation(
+            duration,
+            Animation {
+                name: format!("fade {}", layer),
+                update: Box::new(move |progress, canvas, _| {
+                    canvas.layer(layer).set_opacity(from + (to - from) * progress);
+                    Ok(())
+                }),
+            },
+        );
+    }
 }
 
 trait Toggleable {
@@ -1,48 +1,235 @@
-use std::env;
+use std::{env, io::Write as _, path::PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use itertools::Itertools;
 use rand::Rng;
 use shapemaker::{
-    cli::{canvas_from_cli, cli_args},
-    *,
+    cli::{self, canvas_from_cli, cli_args},
+    examples,
+    gallery::{write_gallery, GalleryItem},
+    scene::Scene,
+    prelude::*,
+    set_seed,
 };
 
 pub fn main() -> Result<()> {
-    run(cli_args())
+    run(cli_args()?)
 }
 
 pub fn run(args: cli::Args) -> Result<()> {
-    let mut canvas = canvas_from_cli(&args);
+    if let Some(seed) = args.flag_seed {
+        set_seed(seed);
+    }
+    let mut canvas = canvas_from_cli(&args)?;
+
+    if args.cmd_examples && args.cmd_list {
+        for example in examples::all() {
+            println!("{:<24} {}", example.name, example.description);
+        }
+        return Ok(());
+    }
+
+    if args.cmd_examples && args.cmd_render {
+        let example = examples::by_name(&args.arg_name).ok_or_else(|| {
+            anyhow::format_err!(
+                "Unknown example: {}. Available examples: {}",
+                args.arg_name,
+                examples::all().iter().map(|example| example.name).join(", ")
+            )
+        })?;
+        let mut canvas = (example.build)();
+        cli::set_layer_filters_from_args(&args, &mut canvas);
+        cli::draw_grid_from_args(&args, &mut canvas)?;
+        return save_image(&mut canvas, &args);
+    }
+
+    if args.cmd_gallery {
+        return render_gallery(&args);
+    }
+
+    if args.cmd_image && args.flag_count.is_some() {
+        return render_batch(&args);
+    }
 
     if args.cmd_image && !args.cmd_video {
         canvas = examples::title();
+        cli::set_layer_filters_from_args(&args, &mut canvas);
+        cli::draw_grid_from_args(&args, &mut canvas)?;
+        return save_image(&mut canvas, &args);
+    }
 
-        let rendered = canvas.render(true)?;
-        if args.arg_file.ends_with(".svg") {
-            std::fs::write(args.arg_file, rendered).unwrap();
-        } else {
-            match Canvas::save_as(
-                &args.arg_file,
-                canvas.aspect_ratio(),
-                args.flag_resolution.unwrap_or(1000),
-                rendered,
-            ) {
-                Ok(_) => println!("Image saved to {}", args.arg_file),
-                Err(e) => println!("Error saving image: {}", e),
+    #[cfg(feature = "plugins")]
+    if let Some(directory) = &args.flag_plugins {
+        shapemaker::plugins::load(std::path::Path::new(directory))?;
+    }
+    let video = video_from_args(canvas, &args)?;
+
+    if let Some(composition) = &args.flag_composition {
+        let compositions = cli::load_compositions(composition)?;
+        video.render_compositions_in(
+            &compositions,
+            args.arg_file.clone(),
+            args.flag_workers.unwrap_or(8),
+        )
+    } else if args.flag_preview && args.flag_watch {
+        video.preview_watch(8888, args.watched_files(), || {
+            // same seed, so that only what changed in the files changes in the video
+            if let Some(seed) = args.flag_seed {
+                set_seed(seed);
             }
-        }
-        return Ok(());
+            video_from_args(canvas_from_cli(&args)?, &args)
+        })
+    } else if args.flag_preview {
+        video.preview_on(8888)
+    } else {
+        video.render_to(args.arg_file, args.flag_workers.unwrap_or(8), false)
     }
+}
 
+fn video_from_args(canvas: Canvas, args: &cli::Args) -> Result<Video<()>> {
     let mut video = Video::<()>::new(canvas);
     video.duration_override = args.flag_duration.map(|seconds| seconds * 1000);
     video.start_rendering_at = args.flag_start.unwrap_or_default() * 1000;
     video.fps = args.flag_fps.unwrap_or(30);
+    video.onion_skin_frames = args.flag_onion_skin.unwrap_or_default();
+    video.preview_comparison = args.flag_compare.as_ref().map(PathBuf::from);
+    if let Some(scene) = &args.flag_scene {
+        video = Scene::from_file(scene)?.apply(video)?;
+    }
+    cli::draw_grid_from_args(args, &mut video.initial_canvas)?;
+    Ok(video.with_layers(&cli::layers_from_args(args)))
+}
 
-    if args.flag_preview {
-        video.preview_on(8888)
+fn save_image(canvas: &mut Canvas, args: &cli::Args) -> Result<()> {
+    let rendered = canvas.render_layers(&cli::layers_from_args(args), true)?;
+    write_image(
+        &args.arg_file,
+        canvas.aspect_ratio(),
+        args.flag_resolution.unwrap_or(1000),
+        rendered,
+    )
+}
+
+fn write_image(path: &str, aspect_ratio: f32, resolution: usize, rendered: String) -> Result<()> {
+    if path.ends_with(".svg") {
+        std::fs::write(path, rendered).with_context(|| format!("Could not write {path}"))?;
     } else {
-        video.render_to(args.arg_file, args.flag_workers.unwrap_or(8), false)
+        Canvas::save_as(path, aspect_ratio, resolution, rendered)
+            .map_err(|e| anyhow::format_err!("Could not save {path}: {e}"))?;
+        println!("Image saved to {}", path);
+    }
+    Ok(())
+}
+
+/// Renders --count images, each with its own seed, to the --out template.
+/// Canvases are generated one after the other, since they share the seeded generator, but rasterized in parallel.
+fn render_batch(args: &cli::Args) -> Result<()> {
+    let count = args.flag_count.unwrap_or(1);
+    if count == 0 {
+        return Ok(());
+    }
+    let template = args.flag_out.clone().unwrap_or(args.arg_file.clone());
+    if count > 1 && !template.contains("{seed}") && !template.contains("{index}") {
+        return Err(anyhow::format_err!(
+            "{template} should contain {{seed}} or {{index}}, or all images would be saved to the same file"
+        ));
+    }
+
+    let mut images = vec![];
+    for (index, (seed, mut canvas)) in seeded_canvases(args, count)?.into_iter().enumerate() {
+        let path = template
+            .replace("{seed}", &seed.to_string())
+            .replace("{index}", &index.to_string());
+        let rendered = canvas.render_layers(&cli::layers_from_args(args), true)?;
+        images.push((path, seed, canvas.aspect_ratio(), rendered));
+    }
+
+    for (path, _, _, _) in &images {
+        if let Some(directory) = PathBuf::from(path).parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+    }
+    let seeds_file = PathBuf::from(&images[0].0).with_file_name("seeds.txt");
+    let mut seeds = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&seeds_file)?;
+    for (path, seed, _, _) in &images {
+        writeln!(seeds, "{path} {seed}")?;
     }
+
+    let resolution = args.flag_resolution.unwrap_or(1000);
+    let chunk_size = count.div_ceil(args.flag_workers.unwrap_or(8).max(1));
+    let failures: Vec<anyhow::Error> = std::thread::scope(|scope| {
+        let workers = images
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .filter_map(|(path, _, aspect_ratio, rendered)| {
+                            write_image(path, *aspect_ratio, resolution, rendered.clone()).err()
+                        })
+                        .collect_vec()
+                })
+            })
+            .collect_vec();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("image writer panicked"))
+            .collect()
+    });
+    println!("Seeds recorded in {}", seeds_file.display());
+
+    for failure in &failures {
+        eprintln!("{failure:#}");
+    }
+    if !failures.is_empty() {
+        return Err(anyhow::format_err!(
+            "{} of {count} images could not be saved",
+            failures.len()
+        ));
+    }
+    Ok(())
+}
+
+/// `count` canvases with a random background and a random layer of shapes, following the command line's settings (grid size, colors, objects count, shapes, etc.).
+/// Each is generated right after seeding with its own seed, starting from --seed.
+fn seeded_canvases(args: &cli::Args, count: usize) -> Result<Vec<(u64, Canvas)>> {
+    let first_seed = args.flag_seed.unwrap_or_else(rand::random);
+    (0..count)
+        .map(|index| {
+            let seed = first_seed.wrapping_add(index as u64);
+            set_seed(seed);
+            let mut canvas = canvas_from_cli(args)?;
+            canvas.set_background(canvas.random_color());
+            let shapes = canvas.random_layer("shapes");
+            canvas.add_or_replace_layer(shapes);
+            cli::draw_grid_from_args(args, &mut canvas)?;
+            Ok((seed, canvas))
+        })
+        .collect()
+}
+
+/// Renders --count canvases (see [`seeded_canvases`]) as SVGs into the <file> directory, along with an index.html contact sheet
+fn render_gallery(args: &cli::Args) -> Result<()> {
+    if args.arg_file.is_empty() {
+        return Err(anyhow::format_err!(
+            "gallery needs a directory to save the images and index.html to: pass it as <file>"
+        ));
+    }
+    let directory = PathBuf::from(&args.arg_file);
+    std::fs::create_dir_all(&directory)?;
+
+    let mut items = vec![];
+    for (seed, mut canvas) in seeded_canvases(args, args.flag_count.unwrap_or(24))? {
+        let file = format!("{seed}.svg");
+        let rendered = canvas.render_layers(&cli::layers_from_args(args), true)?;
+        std::fs::write(directory.join(&file), rendered)?;
+        items.push(GalleryItem::new(seed, &file, &canvas));
+    }
+
+    write_gallery(&directory, &items)?;
+    println!("Gallery saved to {}", directory.join("index.html").display());
+    Ok(())
 }
@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use rand::seq::IteratorRandom;
+
+use crate::{random::rng, Containable, MultiRegion, Object, Point, Region};
+
+/// An arbitrary set of cells, for placement areas that are not rectangular (circles, L-shapes, etc.)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mask {
+    pub cells: HashSet<Point>,
+}
+
+impl Mask {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// cells of the region for which the predicate is true
+    pub fn from_predicate(within: &Region, predicate: impl Fn(&Point) -> bool) -> Self {
+        Self {
+            cells: within.iter().filter(|point| predicate(point)).collect(),
+        }
+    }
+
+    /// cells at most radius cells away from center
+    pub fn circle(center: Point, radius: f32) -> Self {
+        let reach = radius.ceil() as usize;
+        let bounds = Region {
+            start: Point(
+                center.0.saturating_sub(reach),
+                center.1.saturating_sub(reach),
+            ),
+            end: Point(center.0 + reach, center.1 + reach),
+        };
+
+        Self::from_predicate(&bounds, |Point(x, y)| {
+            let dx = *x as f32 - center.0 as f32;
+            let dy = *y as f32 - center.1 as f32;
+            dx * dx + dy * dy <= radius * radius
+        })
+    }
+
+    pub fn union(&self, other: &Mask) -> Mask {
+        Self {
+            cells: self.cells.union(&other.cells).copied().collect(),
+        }
+    }
+
+    pub fn intersection(&self, other: &Mask) -> Mask {
+        Self {
+            cells: self.cells.intersection(&other.cells).copied().collect(),
+        }
+    }
+
+    pub fn difference(&self, other: &Mask) -> Mask {
+        Self {
+            cells: self.cells.difference(&other.cells).copied().collect(),
+        }
+    }
+
+    /// iterates over every cell, in row-major order
+    pub fn iter(&self) -> impl Iterator<Item = Point> + '_ {
+        self.cells
+            .iter()
+            .copied()
+            .sorted_by_key(|Point(x, y)| (*y, *x))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// smallest region containing every cell of the mask
+    pub fn bounding_region(&self) -> Option<Region> {
+        let (min_x, max_x) = self.cells.iter().map(|p| p.0).minmax().into_option()?;
+        let (min_y, max_y) = self.cells.iter().map(|p| p.1).minmax().into_option()?;
+        Some(Region {
+            start: Point(min_x, min_y),
+            end: Point(max_x, max_y),
+        })
+    }
+
+    pub fn random_point(&self) -> Option<Point> {
+        self.cells.iter().copied().choose(&mut rng())
+    }
+
+    /// whether every cell covered by the object is in the mask
+    pub fn contains_object(&self, object: &Object) -> bool {
+        self.contains(&object.region())
+    }
+}
+
+impl From<Region> for Mask {
+    fn from(region: Region) -> Self {
+        Self {
+            cells: region.iter().collect(),
+        }
+    }
+}
+
+impl From<MultiRegion> for Mask {
+    fn from(regions: MultiRegion) -> Self {
+        Self {
+            cells: regions.iter().collect(),
+        }
+    }
+}
+
+impl Containable<Point> for Mask {
+    fn contains(&self, value: &Point) -> bool {
+        self.cells.contains(value)
+    }
+}
+
+impl Containable<Region> for Mask {
+    fn contains(&self, value: &Region) -> bool {
+        value.iter().all(|point| self.cells.contains(&point))
+    }
+}
@@ -3,10 +3,47 @@ use itertools::Itertools;
 use midly::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind};
 use std::{collections::HashMap, fmt::Debug, path::PathBuf};
 
-use crate::{audio, sync::SyncData, ui::Log as _, ui::MaybeProgressBar as _, Stem, Syncable};
+use crate::{
+    audio, sync::SyncData, ui::Log as _, ui::MaybeProgressBar as _, Stem, Syncable, VelocityCurve,
+};
 
 pub struct MidiSynchronizer {
     pub midi_path: PathBuf,
+    /// Velocity curves to apply to each stem, by stem name. Use "*" to apply a curve to every stem that doesn't have its own.
+    pub velocity_curves: HashMap<String, VelocityCurve>,
+    /// Whether to synthesize a "metronome" stem from the tempo map, with a note on every beat and accented downbeats.
+    pub metronome: bool,
+}
+
+pub const METRONOME_STEM_NAME: &str = "metronome";
+const METRONOME_DOWNBEAT_PITCH: u8 = 76;
+const METRONOME_BEAT_PITCH: u8 = 77;
+const METRONOME_DOWNBEAT_VELOCITY: u8 = 127;
+const METRONOME_BEAT_VELOCITY: u8 = 90;
+
+impl MidiSynchronizer {
+    pub fn with_velocity_curve(mut self, stem: &str, curve: VelocityCurve) -> Self {
+        self.velocity_curves.insert(stem.to_string(), curve);
+        self
+    }
+
+    pub fn with_metronome(mut self) -> Self {
+        self.metronome = true;
+        self
+    }
+
+    pub fn with_velocity_curves(mut self, curves: HashMap<String, VelocityCurve>) -> Self {
+        self.velocity_curves.extend(curves);
+        self
+    }
+
+    fn velocity_curve_of(&self, stem: &str) -> VelocityCurve {
+        self.velocity_curves
+            .get(stem)
+            .or(self.velocity_curves.get("*"))
+            .copied()
+            .unwrap_or_default()
+    }
 }
 
 trait Averageable {
@@ -23,15 +60,18 @@ impl Syncable for MidiSynchronizer {
     fn new(path: &str) -> Self {
         Self {
             midi_path: PathBuf::from(path),
+            velocity_curves: HashMap::new(),
+            metronome: false,
         }
     }
 
     fn load(&self, progressbar: Option<&ProgressBar>) -> SyncData {
-        let (now, notes_per_instrument) = load_notes(&self.midi_path, progressbar);
+        let (now, tempo_map, notes_per_instrument) = load_notes(&self.midi_path, progressbar);
 
-        SyncData {
+        let mut syncdata = SyncData {
             bpm: tempo_to_bpm(now.tempo),
             stems: HashMap::from_iter(notes_per_instrument.iter().map(|(name, notes)| {
+                let velocity_curve = self.velocity_curve_of(name);
                 let mut notes_per_ms = HashMap::<usize, Vec<audio::Note>>::new();
 
                 if let Some(pb) = progressbar {
@@ -47,7 +87,7 @@ impl Syncable for MidiSynchronizer {
                         .push(audio::Note {
                             pitch: note.key,
                             tick: note.tick,
-                            velocity: note.vel,
+                            velocity: velocity_curve.apply(note.vel),
                         });
                     progressbar.inc(1);
                 }
@@ -77,7 +117,11 @@ impl Syncable for MidiSynchronizer {
                 (
                     name.clone(),
                     Stem {
-                        amplitude_max: notes.iter().map(|n| n.vel).max().unwrap_or(0) as f32,
+                        amplitude_max: notes
+                            .iter()
+                            .map(|n| velocity_curve.apply(n.vel))
+                            .max()
+                            .unwrap_or(0) as f32,
                         amplitude_db: amplitudes,
                         duration_ms,
                         notes: notes_per_ms,
@@ -86,7 +130,77 @@ impl Syncable for MidiSynchronizer {
                 )
             })),
             markers: HashMap::new(),
+            beats: vec![],
+        };
+
+        let duration_ms = syncdata
+            .stems
+            .values()
+            .map(|stem| stem.duration_ms)
+            .max()
+            .unwrap_or(0);
+        syncdata.beats = tempo_map.beats_until(duration_ms);
+
+        if self.metronome {
+            progressbar.log(
+                "Synthesized",
+                &format!(
+                    "{METRONOME_STEM_NAME} stem with {} beats per bar",
+                    tempo_map.beats_per_bar
+                ),
+            );
+
+            syncdata.stems.insert(
+                METRONOME_STEM_NAME.to_string(),
+                metronome_stem(&tempo_map, duration_ms),
+            );
         }
+
+        syncdata
+    }
+}
+
+/// Creates a stem with a note on every beat of the tempo map, the first beat of every bar being accented.
+/// Notes last for half a beat, or 100ms, whichever is shorter.
+fn metronome_stem(tempo_map: &TempoMap, duration_ms: usize) -> Stem {
+    let mut notes = HashMap::<usize, Vec<audio::Note>>::new();
+    let mut amplitudes = vec![0.0; duration_ms];
+
+    for (beat, start_ms) in tempo_map.beats_until(duration_ms).into_iter().enumerate() {
+        let beat = beat as u32;
+        let tick = beat * tempo_map.ticks_per_beat as u32;
+        let next_beat_ms = tempo_map.tick_to_ms(tick + tempo_map.ticks_per_beat as u32);
+        let end_ms = (start_ms + ((next_beat_ms - start_ms) / 2).min(100)).min(duration_ms);
+
+        let downbeat = beat.is_multiple_of(tempo_map.beats_per_bar as u32);
+        let (pitch, velocity) = if downbeat {
+            (METRONOME_DOWNBEAT_PITCH, METRONOME_DOWNBEAT_VELOCITY)
+        } else {
+            (METRONOME_BEAT_PITCH, METRONOME_BEAT_VELOCITY)
+        };
+
+        notes.entry(start_ms).or_default().push(audio::Note {
+            pitch,
+            velocity,
+            tick,
+        });
+        notes.entry(end_ms).or_default().push(audio::Note {
+            pitch,
+            velocity: 0,
+            tick,
+        });
+
+        for amplitude in &mut amplitudes[start_ms..end_ms] {
+            *amplitude = velocity as f32;
+        }
+    }
+
+    Stem {
+        amplitude_db: amplitudes,
+        amplitude_max: METRONOME_DOWNBEAT_VELOCITY as f32,
+        duration_ms,
+        notes,
+        name: METRONOME_STEM_NAME.to_string(),
     }
 }
 
@@ -104,6 +218,45 @@ struct Now {
     ticks_per_beat: u16,
 }
 
+/// Tempo changes of a MIDI file, used to convert ticks to milliseconds.
+struct TempoMap {
+    ticks_per_beat: u16,
+    /// (absolute tick, tempo in µs per beat), sorted by tick
+    changes: Vec<(u32, usize)>,
+    beats_per_bar: u8,
+}
+
+impl TempoMap {
+    fn tick_to_ms(&self, tick: u32) -> usize {
+        let mut ms = 0;
+        let mut last_tick = 0;
+        // MIDI files play at 120 bpm until their first tempo change
+        let mut tempo = 500_000;
+
+        for (change_tick, change_tempo) in &self.changes {
+            if *change_tick >= tick {
+                break;
+            }
+            ms += midi_tick_to_ms(change_tick - last_tick, tempo, self.ticks_per_beat as usize);
+            last_tick = *change_tick;
+            tempo = *change_tempo;
+        }
+
+        ms + midi_tick_to_ms(tick - last_tick, tempo, self.ticks_per_beat as usize)
+    }
+
+    /// Start of each beat before duration_ms
+    fn beats_until(&self, duration_ms: usize) -> Vec<usize> {
+        if self.ticks_per_beat == 0 {
+            return vec![];
+        }
+        (0..)
+            .map(|beat| self.tick_to_ms(beat * self.ticks_per_beat as u32))
+            .take_while(|ms| *ms < duration_ms)
+            .collect()
+    }
+}
+
 type Timeline<'a> = HashMap<u32, HashMap<String, TrackEvent<'a>>>;
 
 type StemNotes = HashMap<u32, HashMap<String, Note>>;
@@ -142,7 +295,7 @@ impl Debug for Note {
 fn load_notes<'a>(
     source: &PathBuf,
     progressbar: Option<&ProgressBar>,
-) -> (Now, HashMap<String, Vec<Note>>) {
+) -> (Now, TempoMap, HashMap<String, Vec<Note>>) {
     // Read midi file using midly
     if let Some(pb) = progressbar {
         pb.set_length(1);
@@ -166,7 +319,14 @@ fn load_notes<'a>(
         },
     };
 
-    // Get track names and (initial) BPM
+    let mut tempo_map = TempoMap {
+        ticks_per_beat: now.ticks_per_beat,
+        changes: vec![],
+        beats_per_bar: 4,
+    };
+    let mut time_signature_found = false;
+
+    // Get track names, (initial) BPM and time signature
     let mut track_no = 0;
     let mut track_names = HashMap::<usize, String>::new();
     for track in midifile.tracks.iter() {
@@ -182,6 +342,12 @@ fn load_notes<'a>(
                         now.tempo = tempo.as_int() as usize;
                     }
                 }
+                TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, ..))
+                    if !time_signature_found && numerator > 0 =>
+                {
+                    tempo_map.beats_per_bar = numerator;
+                    time_signature_found = true;
+                }
                 _ => {}
             }
         }
@@ -227,6 +393,7 @@ fn load_notes<'a>(
             match event.kind {
                 TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
                     now.tempo = tempo.as_int() as usize;
+                    tempo_map.changes.push((*tick, now.tempo));
                 }
                 _ => {}
             }
@@ -290,10 +457,22 @@ fn load_notes<'a>(
         }
     }
 
-    (now, result)
+    (now, tempo_map, result)
 }
 
 fn midi_tick_to_ms(tick: u32, tempo: usize, ppq: usize) -> usize {
     let with_floats = (tempo as f32 / 1e3) / ppq as f32 * tick as f32;
     with_floats.round() as usize
 }
+
+#[test]
+fn test_tick_to_ms() {
+    let tempo_map = TempoMap {
+        ticks_per_beat: 480,
+        changes: vec![(960, 250_000)],
+        beats_per_bar: 4,
+    };
+    assert_eq!(tempo_map.tick_to_ms(480), 500);
+    assert_eq!(tempo_map.tick_to_ms(960), 1000);
+    assert_eq!(tempo_map.tick_to_ms(1440), 1250);
+}
@@ -0,0 +1,113 @@
+use crate::{LineSegment, Object, PointF, Region};
+
+/// How many points outlines are resampled to before being interpolated
+const MORPH_RESOLUTION: usize = 64;
+
+/// Shape between from (t = 0) and to (t = 1), as a [`Object::PolygonF`].
+/// Both outlines are resampled to the same number of points, then matched up so that the shape twists as little as possible.
+pub fn morph(from: &Object, to: &Object, t: f32) -> Object {
+    let from = resample(&outline(from), MORPH_RESOLUTION, true);
+    let to = resample(&outline(to), MORPH_RESOLUTION, true);
+
+    // start from the point of `to` that makes corresponding points closest overall
+    let offset = (0..MORPH_RESOLUTION)
+        .min_by(|&a, &b| {
+            let cost = |offset: usize| -> f32 {
+                from.iter()
+                    .enumerate()
+                    .map(|(i, p)| {
+                        let q = to[(i + offset) % MORPH_RESOLUTION];
+                        (p.0 - q.0).powi(2) + (p.1 - q.1).powi(2)
+                    })
+                    .sum()
+            };
+            cost(a).total_cmp(&cost(b))
+        })
+        .unwrap_or(0);
+
+    Object::PolygonF(
+        from.iter()
+            .enumerate()
+            .map(|(i, p)| p.lerp(&to[(i + offset) % MORPH_RESOLUTION], t))
+            .collect(),
+    )
+}
+
+/// Vertices of the object's outline as a closed polygon, in cells.
+/// Lines go there and back, small circles and dots are single points, and objects without a clear outline use their region.
+pub fn outline(object: &Object) -> Vec<PointF> {
+    let circle = |PointF(x, y): PointF, radius: f32| -> Vec<PointF> {
+        (0..MORPH_RESOLUTION)
+            .map(|i| {
+                let angle = i as f32 / MORPH_RESOLUTION as f32 * std::f32::consts::TAU;
+                PointF(x + radius * angle.cos(), y + radius * angle.sin())
+            })
+            .collect()
+    };
+    let rectangle = |region: &Region| -> Vec<PointF> {
+        let (start, end) = (
+            PointF::from(region.start),
+            PointF::from(region.end).translated(1.0, 1.0),
+        );
+        vec![start, PointF(end.0, start.1), end, PointF(start.0, end.1)]
+    };
+
+    match object {
+        Object::Polygon(start, lines) => std::iter::once(*start)
+            .chain(lines.iter().map(|line| match line {
+                LineSegment::Straight(end)
+                | LineSegment::InwardCurve(end)
+                | LineSegment::OutwardCurve(end) => *end,
+            }))
+            .map(PointF::from)
+            .collect(),
+        Object::PolygonF(vertices) => vertices.clone(),
+        Object::Line(start, end, _)
+        | Object::CurveInward(start, end, _)
+        | Object::CurveOutward(start, end, _) => vec![(*start).into(), (*end).into()],
+        Object::LineF(start, end, _) => vec![*start, *end],
+        Object::BigCircle(topleft) => circle(PointF::from(*topleft).translated(0.5, 0.5), 0.5),
+        Object::BigCircleF(topleft) => circle(topleft.translated(0.5, 0.5), 0.5),
+        Object::SmallCircle(center) | Object::Dot(center) => vec![(*center).into()],
+        Object::SmallCircleF(center) | Object::DotF(center) => vec![*center],
+        Object::TextOnPath(path, ..) => outline(path),
+        Object::Rectangle(start, end) => rectangle(&Region::from((start, end))),
+        _ => rectangle(&object.region()),
+    }
+}
+
+/// count points evenly spaced along the polygon. Open polylines get points on both ends.
+pub(crate) fn resample(polygon: &[PointF], count: usize, closed: bool) -> Vec<PointF> {
+    let edges: Vec<(PointF, PointF)> = match closed {
+        true => polygon
+            .iter()
+            .zip(polygon.iter().cycle().skip(1))
+            .map(|(a, b)| (*a, *b))
+            .collect(),
+        false => polygon.windows(2).map(|pair| (pair[0], pair[1])).collect(),
+    };
+    let length = |(a, b): &(PointF, PointF)| ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+    let perimeter: f32 = edges.iter().map(length).sum();
+
+    if perimeter == 0.0 || edges.is_empty() {
+        return vec![polygon.first().copied().unwrap_or_default(); count];
+    }
+
+    let mut points = vec![];
+    let mut edge = 0;
+    let mut walked = 0.0;
+    for i in 0..count {
+        let target = match closed {
+            true => i as f32 / count as f32 * perimeter,
+            false => i as f32 / (count.max(2) - 1) as f32 * perimeter,
+        };
+        while edge < edges.len() - 1 && walked + length(&edges[edge]) < target {
+            walked += length(&edges[edge]);
+            edge += 1;
+        }
+        let (a, b) = edges[edge];
+        let t = ((target - walked) / length(&edges[edge]).max(f32::EPSILON)).min(1.0);
+        points.push(a.lerp(&b, t));
+    }
+    points
+}
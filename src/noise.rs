@@ -0,0 +1,118 @@
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+/// Seeded Perlin noise, to vary things smoothly over the canvas and over time instead of re-rolling them.
+/// Sample it with grid coordinates and a timestamp using [`Noise::at`].
+#[derive(Debug, Clone)]
+pub struct Noise {
+    permutation: Vec<usize>,
+    /// How fast the noise varies across the canvas, in features per cell
+    pub frequency: f32,
+    /// How long the noise takes to move by one feature, in milliseconds
+    pub period_ms: f32,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        let mut permutation: Vec<usize> = (0..256).collect();
+        permutation.shuffle(&mut StdRng::seed_from_u64(seed));
+        // repeat the table so that lookups of hash + 1 don't need to wrap around
+        permutation.extend_from_within(..);
+
+        Self {
+            permutation,
+            frequency: 0.25,
+            period_ms: 1000.0,
+        }
+    }
+
+    pub fn with_frequency(self, frequency: f32) -> Self {
+        Self { frequency, ..self }
+    }
+
+    pub fn with_period(self, period_ms: f32) -> Self {
+        Self { period_ms, ..self }
+    }
+
+    /// Noise at a grid position and time, between 0 and 1
+    pub fn at(&self, x: f32, y: f32, ms: usize) -> f32 {
+        let value = self.get3(
+            x * self.frequency,
+            y * self.frequency,
+            ms as f32 / self.period_ms,
+        );
+        ((value + 1.0) / 2.0).clamp(0.0, 1.0)
+    }
+
+    /// Noise at a grid position and time, mapped between min and max
+    pub fn between(&self, min: f32, max: f32, x: f32, y: f32, ms: usize) -> f32 {
+        min + (max - min) * self.at(x, y, ms)
+    }
+
+    /// Raw 2D noise, between -1 and 1
+    pub fn get2(&self, x: f32, y: f32) -> f32 {
+        self.get3(x, y, 0.0)
+    }
+
+    /// Raw 3D noise, between -1 and 1 (Ken Perlin's improved noise)
+    pub fn get3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let p = &self.permutation;
+        let (xi, yi, zi) = (
+            x.floor() as i64 as usize & 255,
+            y.floor() as i64 as usize & 255,
+            z.floor() as i64 as usize & 255,
+        );
+        let (x, y, z) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let a = p[xi] + yi;
+        let (aa, ab) = (p[a] + zi, p[a + 1] + zi);
+        let b = p[xi + 1] + yi;
+        let (ba, bb) = (p[b] + zi, p[b + 1] + zi);
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad(p[aa], x, y, z), grad(p[ba], x - 1.0, y, z)),
+                lerp(
+                    u,
+                    grad(p[ab], x, y - 1.0, z),
+                    grad(p[bb], x - 1.0, y - 1.0, z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(p[aa + 1], x, y, z - 1.0),
+                    grad(p[ba + 1], x - 1.0, y, z - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(p[ab + 1], x, y - 1.0, z - 1.0),
+                    grad(p[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+/// Dot product of (x, y, z) with one of 12 gradient directions, picked by hash
+fn grad(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = match h {
+        0..=3 => y,
+        12 | 14 => x,
+        _ => z,
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
@@ -1,35 +1,133 @@
-use std::collections::HashMap;
-
-use crate::{ColorMapping, Fill, Filter, Point, Region, Transformation};
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash as _, Hasher as _},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    color::{all_colors, css_color_declaration, split_alpha},
+    layer::hash_serialized,
+    morph::{outline, resample},
+    point::GridLayout,
+    text::{fitting_font_size, wrap_text, FONT_FAMILY, LINE_HEIGHT_EM},
+    ui::format_log_msg,
+    Color, ColorMapping, Fill, Filter, FilterChain, ParagraphStyle, Point, PointF, Region, Stroke,
+    TextAlignment, Transformation,
+};
+use anyhow::{format_err, Result};
+use base64::Engine as _;
 use itertools::Itertools;
+use serde::{Deserialize, Deserializer, Serialize};
+use slug::slugify;
 use wasm_bindgen::prelude::*;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LineSegment {
     Straight(Point),
     InwardCurve(Point),
     OutwardCurve(Point),
 }
 
-#[derive(Debug, Clone)]
+/// Line across which objects are mirrored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    /// Left becomes right
+    Vertical,
+    /// Top becomes bottom
+    Horizontal,
+    /// Both at once, which is the same as a half-turn around the center
+    Both,
+}
+
+/// How many straight segments curves are approximated with when walking along them
+const CURVE_SAMPLES: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Object {
     Polygon(Point, Vec<LineSegment>),
+    /// Closed polygon through fractional grid positions, e.g. the result of [`crate::morph`]
+    PolygonF(Vec<PointF>),
     Line(Point, Point, f32),
+    /// Line between fractional grid positions
+    LineF(PointF, PointF, f32),
     CurveOutward(Point, Point, f32),
     CurveInward(Point, Point, f32),
     SmallCircle(Point),
     Dot(Point),
     BigCircle(Point),
+    /// SmallCircle centered on a fractional grid position
+    SmallCircleF(PointF),
+    /// Dot centered on a fractional grid position
+    DotF(PointF),
+    /// BigCircle whose bounding cell starts at a fractional grid position
+    BigCircleF(PointF),
     Text(Point, String, f32),
     CenteredText(Point, String, f32),
-    // FittedText(Region, String),
+    /// Single line of text, sized to fill the region
+    FittedText(Region, String, TextAlignment),
+    /// Text wrapped to the region's width. Font size is in pixels. Lines that overflow the region are dropped.
+    Paragraph(Region, String, f32, ParagraphStyle),
+    /// Text flowing along the outline of another object (see [`Object::path_data`]). Font size is in pixels.
+    TextOnPath(Box<Object>, String, f32),
     Rectangle(Point, Point),
-    Image(Region, String),
+    /// Unit cube standing on the cell, meant to be used with an isometric projection (see `Canvas::set_projection`)
+    IsoCube(Point),
+    /// A PNG or JPEG file, inlined as base64 and scaled to fit the region
+    Image(Region, PathBuf),
+    /// Not serializable: saving a canvas that contains one fails
+    #[serde(skip)]
     RawSVG(Box<dyn svg::Node>),
+    /// The contents of an external SVG file, rescaled to fit the region. See `Object::from_svg_file`.
+    #[serde(deserialize_with = "deserialize_imported_svg")]
+    ImportedSVG(Region, String),
     // Tiling(Region, Box<Object>),
 }
 
 impl Object {
+    /// Loads an external SVG file, to be rescaled so that its viewBox fits in `region`.
+    /// If a colormap is given, color names used in fill and stroke attributes (eg. `fill="red"`) are replaced by the colormap's values.
+    pub fn from_svg_file<P: AsRef<Path>>(
+        path: P,
+        region: Region,
+        recolor_with: Option<&ColorMapping>,
+    ) -> Result<Object> {
+        let mut contents = std::fs::read_to_string(path.as_ref())?;
+
+        // make sure the file is valid SVG before doing anything with it
+        roxmltree::Document::parse(&contents)
+            .map_err(|e| format_err!("Could not parse SVG file {:?}: {}", path.as_ref(), e))?;
+
+        if let Some(colormap) = recolor_with {
+            for color in all_colors() {
+                for property in ["fill", "stroke"] {
+                    contents = contents
+                        .replace(
+                            &format!(r#"{}="{}""#, property, color.name()),
+                            &match split_alpha(&color.render(colormap)) {
+                                (rendered, alpha) if alpha >= 1.0 => {
+                                    format!(r#"{}="{}""#, property, rendered)
+                                }
+                                (rendered, alpha) => format!(
+                                    r#"{}="{}" {}-opacity="{}""#,
+                                    property, rendered, property, alpha
+                                ),
+                            },
+                        )
+                        .replace(
+                            &format!("{}:{};", property, color.name()),
+                            &css_color_declaration(property, &color.render(colormap)),
+                        )
+                        .replace(
+                            &format!("{}: {};", property, color.name()),
+                            &css_color_declaration(property, &color.render(colormap)),
+                        );
+                }
+            }
+        }
+
+        Ok(Object::ImportedSVG(region, contents))
+    }
+
     pub fn color(self, fill: Fill) -> ColoredObject {
         ColoredObject::from((self, Some(fill)))
     }
@@ -43,20 +141,80 @@ impl Object {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ColoredObject {
     pub object: Object,
     pub fill: Option<Fill>,
     pub filters: Vec<Filter>,
     pub transformations: Vec<Transformation>,
+    pub stroke: Option<Stroke>,
+    /// Outline color, rendered alongside the fill
+    pub stroke_color: Option<Color>,
+    /// Only the part of the object inside this shape is visible
+    pub clip: Option<Object>,
+    /// Free-form labels, to query related objects across layers
+    pub tags: Vec<String>,
 }
 
 impl ColoredObject {
+    pub fn with_stroke(self, stroke: Stroke) -> Self {
+        Self {
+            stroke: Some(stroke),
+            ..self
+        }
+    }
+
+    /// Adds an outline on top of the fill
+    pub fn stroked(self, color: Color, width: f32) -> Self {
+        Self {
+            stroke_color: Some(color),
+            stroke: Some(Stroke {
+                width,
+                ..self.stroke.unwrap_or_default()
+            }),
+            ..self
+        }
+    }
+
+    pub fn tag(mut self, tag: &str) -> Self {
+        if !self.has_tag(tag) {
+            self.tags.push(tag.to_string());
+        }
+        self
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// See [`Object::intersects`]
+    pub fn intersects(
+        &self,
+        other: &ColoredObject,
+        object_sizes: ObjectSizes,
+        cell_size: usize,
+    ) -> bool {
+        self.object
+            .intersects(&other.object, object_sizes, cell_size)
+    }
+
+    pub fn clip(self, to: Object) -> Self {
+        Self {
+            clip: Some(to),
+            ..self
+        }
+    }
+
     pub fn filter(mut self, filter: Filter) -> Self {
         self.filters.push(filter);
         self
     }
 
+    pub fn filter_chain(mut self, chain: FilterChain) -> Self {
+        self.filters.extend(chain.filters);
+        self
+    }
+
     pub fn transform(mut self, transformation: Transformation) -> Self {
         self.transformations.push(transformation);
         self
@@ -68,50 +226,67 @@ impl ColoredObject {
 
     pub fn render(
         &self,
-        cell_size: usize,
+        layout: GridLayout,
         object_sizes: ObjectSizes,
         colormap: &ColorMapping,
         id: &str,
     ) -> svg::node::element::Group {
-        let mut group = self.object.render(cell_size, object_sizes, id);
-
-        for (key, value) in self
-            .transformations
-            .render_attributes(colormap, !self.object.fillable())
-        {
-            group = group.set(key, value);
+        let mut group = self.object.render(layout, object_sizes, id);
+
+        if !self.transformations.is_empty() {
+            let origin = self.object.center_coords(layout);
+            group = group.set(
+                "transform",
+                self.transformations
+                    .iter()
+                    .map(|t| t.render_around(origin, layout))
+                    .join(" "),
+            );
         }
 
-        let start = self.object.region().start.coords(cell_size);
-        let (w, h) = (
-            self.object.region().width() * cell_size,
-            self.object.region().height() * cell_size,
-        );
-
-        group = group.set(
-            "transform-origin",
-            format!(
-                "{} {}",
-                start.0 + (w as f32 / 2.0),
-                start.1 + (h as f32 / 2.0)
-            ),
-        );
-
         let mut css = String::new();
-        if !matches!(self.object, Object::RawSVG(..)) {
+        if !matches!(self.object, Object::RawSVG(..) | Object::ImportedSVG(..)) {
             css = self.fill.render_css(colormap, !self.object.fillable());
         }
 
-        css += "transform-box: fill-box;";
+        if !self.object.fillable() {
+            css += &self.stroke.map(|s| s.render_css()).unwrap_or_default();
+        } else if self.object.outlinable() && (self.fill.is_none() || self.stroke_color.is_some()) {
+            if self.fill.is_none() {
+                css += "fill: transparent;";
+            }
+            css += &format!(
+                "{}{}",
+                css_color_declaration(
+                    "stroke",
+                    &self.stroke_color.clone().unwrap_or_default().render(colormap)
+                ),
+                self.stroke
+                    .unwrap_or(object_sizes.empty_shape_stroke)
+                    .render_css()
+            );
+        }
 
-        css += self
-            .filters
-            .iter()
-            .map(|f| f.render_fill_css(colormap))
-            .join(" ")
-            .as_ref();
+        // CSS only keeps the last filter property, so multiple filters need to be combined into a single definition
+        css += &match self.filters.len() {
+            0 => String::new(),
+            1 => self.filters[0].render_fill_css(colormap),
+            _ => FilterChain::from(self.filters.clone()).render_fill_css(colormap),
+        };
 
-        group.set("style", css)
+        group = group.set("style", css);
+
+        // Wrap the object so that the clip region is not affected by the object's transformations.
+        // The <clipPath> itself is defined once for the whole canvas, see `Canvas::unique_clips`
+        match &self.clip {
+            Some(clip) => svg::node::element::Group::new()
+                .set(
+                    "clip-path",
+                    format!("url(#{})", clip.clip_path_id(layout, object_sizes)),
+                )
+                .add(group),
+            None => group,
+        }
     }
 }
 
@@ -122,10 +297,14 @@ impl std::fmt::Display for ColoredObject {
             fill,
             filters,
             transformations,
+            stroke,
+            stroke_color,
+            clip,
+            tags,
         } = self;
 
-        if fill.is_some() {
-            write!(f, "{:?} {:?}", fill.unwrap(), object)?;
+        if let Some(fill) = fill {
+            write!(f, "{:?} {:?}", fill, object)?;
         } else {
             write!(f, "transparent {:?}", object)?;
         }
@@ -138,6 +317,22 @@ impl std::fmt::Display for ColoredObject {
             write!(f, " with transformations {:?}", transformations)?;
         }
 
+        if let Some(stroke) = stroke {
+            write!(f, " with stroke {:?}", stroke)?;
+        }
+
+        if let Some(stroke_color) = stroke_color {
+            write!(f, " outlined in {:?}", stroke_color)?;
+        }
+
+        if let Some(clip) = clip {
+            write!(f, " clipped to {:?}", clip)?;
+        }
+
+        if !tags.is_empty() {
+            write!(f, " tagged {}", tags.join(", "))?;
+        }
+
         Ok(())
     }
 }
@@ -149,6 +344,10 @@ impl From<Object> for ColoredObject {
             fill: None,
             filters: vec![],
             transformations: vec![],
+            stroke: None,
+            stroke_color: None,
+            clip: None,
+            tags: vec![],
         }
     }
 }
@@ -160,14 +359,19 @@ impl From<(Object, Option<Fill>)> for ColoredObject {
             fill,
             filters: vec![],
             transformations: vec![],
+            stroke: None,
+            stroke_color: None,
+            clip: None,
+            tags: vec![],
         }
     }
 }
 
 #[wasm_bindgen]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct ObjectSizes {
-    pub empty_shape_stroke_width: f32,
+    #[wasm_bindgen(skip)]
+    pub empty_shape_stroke: Stroke,
     pub small_circle_radius: f32,
     pub dot_radius: f32,
     pub default_line_width: f32,
@@ -176,7 +380,7 @@ pub struct ObjectSizes {
 impl Default for ObjectSizes {
     fn default() -> Self {
         Self {
-            empty_shape_stroke_width: 0.5,
+            empty_shape_stroke: Stroke::solid(0.5),
             small_circle_radius: 5.0,
             dot_radius: 2.0,
             default_line_width: 2.0,
@@ -184,6 +388,20 @@ impl Default for ObjectSizes {
     }
 }
 
+#[wasm_bindgen]
+impl ObjectSizes {
+    /// Width of empty_shape_stroke, under the name JavaScript used before strokes had styles
+    #[wasm_bindgen(getter)]
+    pub fn empty_shape_stroke_width(&self) -> f32 {
+        self.empty_shape_stroke.width
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_empty_shape_stroke_width(&mut self, width: f32) {
+        self.empty_shape_stroke.width = width;
+    }
+}
+
 pub trait RenderAttributes {
     const MULTIPLE_VALUES_JOIN_BY: &'static str = ", ";
 
@@ -286,9 +504,24 @@ impl Object {
             Object::Text(anchor, _, _)
             | Object::CenteredText(anchor, ..)
             | Object::Dot(anchor)
-            | Object::SmallCircle(anchor) => anchor.translate(dx, dy),
+            | Object::SmallCircle(anchor)
+            | Object::IsoCube(anchor) => anchor.translate(dx, dy),
             Object::BigCircle(center) => center.translate(dx, dy),
-            Object::Image(region, ..) => region.translate(dx, dy),
+            Object::LineF(start, end, _) => {
+                start.translate(dx as f32, dy as f32);
+                end.translate(dx as f32, dy as f32);
+            }
+            Object::SmallCircleF(anchor) | Object::DotF(anchor) | Object::BigCircleF(anchor) => {
+                anchor.translate(dx as f32, dy as f32)
+            }
+            Object::PolygonF(vertices) => vertices
+                .iter_mut()
+                .for_each(|vertex| vertex.translate(dx as f32, dy as f32)),
+            Object::Image(region, ..)
+            | Object::ImportedSVG(region, ..)
+            | Object::FittedText(region, ..)
+            | Object::Paragraph(region, ..) => region.translate(dx, dy),
+            Object::TextOnPath(path, ..) => path.translate(dx, dy),
             Object::RawSVG(_) => {
                 unimplemented!()
             }
@@ -338,9 +571,28 @@ impl Object {
             Object::Text(anchor, _, _)
             | Object::CenteredText(anchor, ..)
             | Object::Dot(anchor)
-            | Object::SmallCircle(anchor) => anchor.region(),
+            | Object::SmallCircle(anchor)
+            | Object::IsoCube(anchor) => anchor.region(),
             Object::BigCircle(center) => center.region(),
-            Object::Image(region, ..) => *region,
+            Object::LineF(start, end, _) => (&start.floored(), &end.floored()).into(),
+            Object::SmallCircleF(anchor) | Object::DotF(anchor) | Object::BigCircleF(anchor) => {
+                anchor.region()
+            }
+            Object::PolygonF(vertices) => {
+                let (xs, ys): (Vec<f32>, Vec<f32>) = vertices.iter().map(|p| (p.0, p.1)).unzip();
+                let (min_x, max_x) = xs.into_iter().minmax().into_option().unwrap_or_default();
+                let (min_y, max_y) = ys.into_iter().minmax().into_option().unwrap_or_default();
+                (
+                    &PointF(min_x, min_y).floored(),
+                    &PointF(max_x, max_y).floored(),
+                )
+                    .into()
+            }
+            Object::Image(region, ..)
+            | Object::ImportedSVG(region, ..)
+            | Object::FittedText(region, ..)
+            | Object::Paragraph(region, ..) => *region,
+            Object::TextOnPath(path, ..) => path.region(),
             Object::RawSVG(_) => {
                 unimplemented!()
             }
@@ -349,51 +601,364 @@ impl Object {
 }
 
 impl Object {
+    /// Reflection of the object across the midline of `world_region`. RawSVG objects are returned unchanged.
+    pub fn mirrored(&self, axis: Axis, world_region: &Region) -> Object {
+        if axis == Axis::Both {
+            return self
+                .mirrored(Axis::Vertical, world_region)
+                .mirrored(Axis::Horizontal, world_region);
+        }
+
+        let vertical = axis == Axis::Vertical;
+        let (lo, hi) = if vertical {
+            (world_region.start.0, world_region.end.0)
+        } else {
+            (world_region.start.1, world_region.end.1)
+        };
+        // the region spans cells lo..=hi, so anchors go from lo to hi + 1
+        let flip_anchor = |v: usize| ((lo + hi + 1) as i32 - v as i32).max(0) as usize;
+        let flip_cell = |v: usize| ((lo + hi) as i32 - v as i32).max(0) as usize;
+
+        let anchor = |Point(x, y): &Point| match vertical {
+            true => Point(flip_anchor(*x), *y),
+            false => Point(*x, flip_anchor(*y)),
+        };
+        let cell = |Point(x, y): &Point| match vertical {
+            true => Point(flip_cell(*x), *y),
+            false => Point(*x, flip_cell(*y)),
+        };
+        let anchor_f = |PointF(x, y): &PointF| match vertical {
+            true => PointF((lo + hi + 1) as f32 - x, *y),
+            false => PointF(*x, (lo + hi + 1) as f32 - y),
+        };
+        let cell_f = |PointF(x, y): &PointF| match vertical {
+            true => PointF((lo + hi) as f32 - x, *y),
+            false => PointF(*x, (lo + hi) as f32 - y),
+        };
+        let region = |r: &Region| {
+            let (a, b) = (cell(&r.start), cell(&r.end));
+            Region {
+                start: Point(a.0.min(b.0), a.1.min(b.1)),
+                end: Point(a.0.max(b.0), a.1.max(b.1)),
+            }
+        };
+        // curves bulge towards a fixed side of the canvas (see curve_path_data), so some reflections swap them
+        let curve_flips = |start: &Point, end: &Point| (start.0 == end.0) == vertical;
+
+        match self {
+            Object::Polygon(start, lines) => Object::Polygon(
+                anchor(start),
+                lines
+                    .iter()
+                    .map(|line| match line {
+                        LineSegment::Straight(p) => LineSegment::Straight(anchor(p)),
+                        LineSegment::InwardCurve(p) => LineSegment::InwardCurve(anchor(p)),
+                        LineSegment::OutwardCurve(p) => LineSegment::OutwardCurve(anchor(p)),
+                    })
+                    .collect(),
+            ),
+            Object::Line(start, end, width) => Object::Line(anchor(start), anchor(end), *width),
+            Object::LineF(start, end, width) => {
+                Object::LineF(anchor_f(start), anchor_f(end), *width)
+            }
+            Object::CurveInward(start, end, width) if curve_flips(start, end) => {
+                Object::CurveOutward(anchor(start), anchor(end), *width)
+            }
+            Object::CurveOutward(start, end, width) if curve_flips(start, end) => {
+                Object::CurveInward(anchor(start), anchor(end), *width)
+            }
+            Object::CurveInward(start, end, width) => {
+                Object::CurveInward(anchor(start), anchor(end), *width)
+            }
+            Object::CurveOutward(start, end, width) => {
+                Object::CurveOutward(anchor(start), anchor(end), *width)
+            }
+            Object::SmallCircle(p) => Object::SmallCircle(anchor(p)),
+            Object::Dot(p) => Object::Dot(anchor(p)),
+            Object::BigCircle(p) => Object::BigCircle(cell(p)),
+            Object::PolygonF(vertices) => Object::PolygonF(vertices.iter().map(anchor_f).collect()),
+            Object::SmallCircleF(p) => Object::SmallCircleF(anchor_f(p)),
+            Object::DotF(p) => Object::DotF(anchor_f(p)),
+            Object::BigCircleF(p) => Object::BigCircleF(cell_f(p)),
+            Object::IsoCube(p) => Object::IsoCube(cell(p)),
+            Object::Text(p, content, size) => Object::Text(anchor(p), content.clone(), *size),
+            Object::CenteredText(p, content, size) => {
+                Object::CenteredText(cell(p), content.clone(), *size)
+            }
+            Object::Rectangle(start, end) => {
+                let mirrored = region(&Region {
+                    start: *start,
+                    end: *end,
+                });
+                Object::Rectangle(mirrored.start, mirrored.end)
+            }
+            Object::FittedText(r, content, alignment) => {
+                Object::FittedText(region(r), content.clone(), *alignment)
+            }
+            Object::Paragraph(r, content, size, style) => {
+                Object::Paragraph(region(r), content.clone(), *size, *style)
+            }
+            Object::Image(r, path) => Object::Image(region(r), path.clone()),
+            Object::ImportedSVG(r, contents) => Object::ImportedSVG(region(r), contents.clone()),
+            Object::TextOnPath(path, content, size) => Object::TextOnPath(
+                Box::new(path.mirrored(axis, world_region)),
+                content.clone(),
+                *size,
+            ),
+            // arbitrary SVG can't be reflected without knowing its geometry: keep it as is
+            Object::RawSVG(svg) => Object::RawSVG(svg.clone()),
+        }
+    }
+
+    /// SVG coordinates of the object's visual center, used as the origin of its transformations
+    pub fn center_coords(&self, layout: GridLayout) -> (f32, f32) {
+        let midpoint =
+            |(x1, y1): (f32, f32), (x2, y2): (f32, f32)| ((x1 + x2) / 2.0, (y1 + y2) / 2.0);
+        let region_center = |region: &Region| {
+            let (x, y) = region.start.coords(layout);
+            (
+                x + (region.width() * layout.cell_size) as f32 / 2.0,
+                y + (region.height() * layout.cell_size) as f32 / 2.0,
+            )
+        };
+
+        match self {
+            Object::Polygon(start, lines) => {
+                let (xs, ys): (Vec<f32>, Vec<f32>) = std::iter::once(start)
+                    .chain(lines.iter().map(|line| match line {
+                        LineSegment::Straight(end)
+                        | LineSegment::InwardCurve(end)
+                        | LineSegment::OutwardCurve(end) => end,
+                    }))
+                    .map(|point| point.coords(layout))
+                    .unzip();
+                let (min_x, max_x) = xs.into_iter().minmax().into_option().unwrap();
+                let (min_y, max_y) = ys.into_iter().minmax().into_option().unwrap();
+                midpoint((min_x, min_y), (max_x, max_y))
+            }
+            Object::Line(start, end, _)
+            | Object::CurveInward(start, end, _)
+            | Object::CurveOutward(start, end, _) => {
+                midpoint(start.coords(layout), end.coords(layout))
+            }
+            Object::LineF(start, end, _) => {
+                midpoint(start.coords(layout), end.coords(layout))
+            }
+            Object::Rectangle(start, end) => {
+                let (x, y) = start.coords(layout);
+                let (w, h) = start.distances(end);
+                (
+                    x + (w * layout.cell_size) as f32 / 2.0,
+                    y + (h * layout.cell_size) as f32 / 2.0,
+                )
+            }
+            Object::Text(anchor, ..) | Object::SmallCircle(anchor) | Object::Dot(anchor) => {
+                anchor.coords(layout)
+            }
+            Object::CenteredText(anchor, ..) | Object::BigCircle(anchor) => {
+                anchor.center_coords(layout)
+            }
+            Object::SmallCircleF(anchor) | Object::DotF(anchor) => anchor.coords(layout),
+            Object::PolygonF(vertices) => {
+                let (xs, ys): (Vec<f32>, Vec<f32>) = vertices
+                    .iter()
+                    .map(|vertex| vertex.coords(layout))
+                    .unzip();
+                let (min_x, max_x) = xs.into_iter().minmax().into_option().unwrap_or_default();
+                let (min_y, max_y) = ys.into_iter().minmax().into_option().unwrap_or_default();
+                midpoint((min_x, min_y), (max_x, max_y))
+            }
+            Object::IsoCube(anchor) => {
+                let (x, y) = anchor.center_coords(layout);
+                (x, y - layout.cell_size as f32 / 2.0)
+            }
+            Object::BigCircleF(anchor) => {
+                let (x, y) = anchor.coords(layout);
+                (x + layout.cell_size as f32 / 2.0, y + layout.cell_size as f32 / 2.0)
+            }
+            Object::Image(region, ..)
+            | Object::ImportedSVG(region, ..)
+            | Object::FittedText(region, ..)
+            | Object::Paragraph(region, ..) => region_center(region),
+            Object::TextOnPath(path, ..) => path.center_coords(layout),
+            Object::RawSVG(_) => (0.0, 0.0),
+        }
+    }
+
+    /// count evenly spaced points along the object, in cells. Lines and curves go from start to end, other shapes around their outline (see [`crate::outline`]).
+    pub fn points_along(&self, count: usize) -> Vec<PointF> {
+        match self {
+            Object::Line(start, end, _) => {
+                resample(&[(*start).into(), (*end).into()], count, false)
+            }
+            Object::LineF(start, end, _) => resample(&[*start, *end], count, false),
+            Object::CurveInward(start, end, _) | Object::CurveOutward(start, end, _) => {
+                // with a square layout and a cell size of 1, SVG coordinates are grid coordinates
+                let (cx, cy) = self.curve_control_point(GridLayout::square(1));
+                let (start, control, end) =
+                    (PointF::from(*start), PointF(cx, cy), PointF::from(*end));
+                let curve: Vec<PointF> = (0..=CURVE_SAMPLES)
+                    .map(|i| {
+                        let t = i as f32 / CURVE_SAMPLES as f32;
+                        start.lerp(&control, t).lerp(&control.lerp(&end, t), t)
+                    })
+                    .collect();
+                resample(&curve, count, false)
+            }
+            Object::TextOnPath(path, ..) => path.points_along(count),
+            _ => resample(&outline(self), count, true),
+        }
+    }
+
+    /// Center and radius of circle-like objects, in cells. Small circles and dots get their radius from object_sizes.
+    fn circle(&self, object_sizes: ObjectSizes, cell_size: usize) -> Option<(PointF, f32)> {
+        let cells = |pixels: f32| pixels / cell_size as f32;
+        match self {
+            Object::BigCircle(topleft) => Some((PointF::from(*topleft).translated(0.5, 0.5), 0.5)),
+            Object::BigCircleF(topleft) => Some((topleft.translated(0.5, 0.5), 0.5)),
+            Object::SmallCircle(center) => {
+                Some(((*center).into(), cells(object_sizes.small_circle_radius)))
+            }
+            Object::SmallCircleF(center) => {
+                Some((*center, cells(object_sizes.small_circle_radius)))
+            }
+            Object::Dot(center) => Some(((*center).into(), cells(object_sizes.dot_radius))),
+            Object::DotF(center) => Some((*center, cells(object_sizes.dot_radius))),
+            _ => None,
+        }
+    }
+
+    /// Whether the object touches the region. Circles are checked against their actual shape instead of their bounding cell,
+    /// with small circles and dots sized according to object_sizes and cell_size (in pixels).
+    pub fn intersects_region(
+        &self,
+        region: &Region,
+        object_sizes: ObjectSizes,
+        cell_size: usize,
+    ) -> bool {
+        match self.circle(object_sizes, cell_size) {
+            Some((PointF(x, y), radius)) => {
+                // distance from the center to the closest point of the region's area
+                let dx = x - x.clamp(region.start.0 as f32, (region.end.0 + 1) as f32);
+                let dy = y - y.clamp(region.start.1 as f32, (region.end.1 + 1) as f32);
+                (dx * dx + dy * dy).sqrt() <= radius
+            }
+            None => self.region().overlaps(region),
+        }
+    }
+
+    /// Whether the two objects touch, based on their regions. Circles are checked against their actual shape, see [`Object::intersects_region`].
+    pub fn intersects(&self, other: &Object, object_sizes: ObjectSizes, cell_size: usize) -> bool {
+        match (
+            self.circle(object_sizes, cell_size),
+            other.circle(object_sizes, cell_size),
+        ) {
+            (Some((a, radius_a)), Some((b, radius_b))) => {
+                let (dx, dy) = (a.0 - b.0, a.1 - b.1);
+                (dx * dx + dy * dy).sqrt() <= radius_a + radius_b
+            }
+            (Some(_), None) => self.intersects_region(&other.region(), object_sizes, cell_size),
+            (None, _) => other.intersects_region(&self.region(), object_sizes, cell_size),
+        }
+    }
+
     pub fn fillable(&self) -> bool {
         !matches!(
             self,
-            Object::Line(..) | Object::CurveInward(..) | Object::CurveOutward(..)
+            Object::Line(..)
+                | Object::LineF(..)
+                | Object::CurveInward(..)
+                | Object::CurveOutward(..)
+        )
+    }
+
+    pub fn outlinable(&self) -> bool {
+        matches!(
+            self,
+            Object::Polygon(..)
+                | Object::PolygonF(..)
+                | Object::Rectangle(..)
+                | Object::SmallCircle(..)
+                | Object::BigCircle(..)
+                | Object::Dot(..)
+                | Object::SmallCircleF(..)
+                | Object::BigCircleF(..)
+                | Object::DotF(..)
+                | Object::IsoCube(..)
         )
     }
 
     pub fn hatchable(&self) -> bool {
-        self.fillable() && !matches!(self, Object::Dot(..))
+        self.fillable() && !matches!(self, Object::Dot(..) | Object::DotF(..))
     }
 
     pub fn render(
         &self,
-        cell_size: usize,
+        layout: GridLayout,
         object_sizes: ObjectSizes,
         id: &str,
     ) -> svg::node::element::Group {
-        let group = svg::node::element::Group::new();
-
-        let rendered = match self {
-            Object::Text(..) | Object::CenteredText(..) => self.render_text(cell_size),
-            Object::Rectangle(..) => self.render_rectangle(cell_size),
-            Object::Polygon(..) => self.render_polygon(cell_size),
-            Object::Line(..) => self.render_line(cell_size),
-            Object::CurveInward(..) | Object::CurveOutward(..) => self.render_curve(cell_size),
-            Object::SmallCircle(..) => self.render_small_circle(cell_size, object_sizes),
-            Object::Dot(..) => self.render_dot(cell_size, object_sizes),
-            Object::BigCircle(..) => self.render_big_circle(cell_size),
-            Object::Image(..) => self.render_image(cell_size),
+        svg::node::element::Group::new()
+            .set("data-object", id)
+            .add(self.render_shape(layout, object_sizes))
+    }
+
+    /// Render the object's SVG element alone, without the wrapping group
+    pub fn render_shape(
+        &self,
+        layout: GridLayout,
+        object_sizes: ObjectSizes,
+    ) -> Box<dyn svg::node::Node> {
+        match self {
+            Object::Text(..) | Object::CenteredText(..) => self.render_text(layout),
+            Object::FittedText(..) => self.render_fitted_text(layout),
+            Object::Paragraph(..) => self.render_paragraph(layout),
+            Object::TextOnPath(..) => self.render_text_on_path(layout, object_sizes),
+            Object::Rectangle(..) => self.render_rectangle(layout),
+            Object::Polygon(..) | Object::PolygonF(..) => self.render_polygon(layout),
+            Object::IsoCube(..) => self.render_iso_cube(layout),
+            Object::Line(..) | Object::LineF(..) => self.render_line(layout),
+            Object::CurveInward(..) | Object::CurveOutward(..) => self.render_curve(layout),
+            Object::SmallCircle(..) | Object::SmallCircleF(..) => {
+                self.render_small_circle(layout, object_sizes)
+            }
+            Object::Dot(..) | Object::DotF(..) => self.render_dot(layout, object_sizes),
+            Object::BigCircle(..) | Object::BigCircleF(..) => self.render_big_circle(layout),
+            Object::Image(..) => self.render_image(layout),
             Object::RawSVG(..) => self.render_raw_svg(),
-        };
+            Object::ImportedSVG(..) => self.render_imported_svg(layout),
+        }
+    }
 
-        group.set("data-object", id).add(rendered)
+    /// ID of the `<clipPath>` made from this object, which depends on everything that changes its rendered shape
+    pub fn clip_path_id(&self, layout: GridLayout, object_sizes: ObjectSizes) -> String {
+        let mut hasher = DefaultHasher::new();
+        hash_serialized(&(self, object_sizes), &mut hasher);
+        layout.hash(&mut hasher);
+        format!("clip-{:x}", hasher.finish())
     }
 
-    fn render_image(&self, cell_size: usize) -> Box<dyn svg::node::Node> {
+    /// A `<clipPath>` definition using this object as the clipping shape, referenced by [`Object::clip_path_id`]
+    pub fn clip_path_definition(
+        &self,
+        layout: GridLayout,
+        object_sizes: ObjectSizes,
+    ) -> svg::node::element::ClipPath {
+        svg::node::element::ClipPath::new()
+            .set("id", self.clip_path_id(layout, object_sizes))
+            .add(self.render_shape(layout, object_sizes))
+    }
+
+    fn render_image(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
         if let Object::Image(region, path) = self {
-            let (x, y) = region.start.coords(cell_size);
+            let (x, y) = region.start.coords(layout);
             return Box::new(
                 svg::node::element::Image::new()
                     .set("x", x)
                     .set("y", y)
-                    .set("width", region.width() * cell_size)
-                    .set("height", region.height() * cell_size)
-                    .set("href", path.clone()),
+                    .set("width", region.width() * layout.cell_size)
+                    .set("height", region.height() * layout.cell_size)
+                    .set("href", image_data_uri(path)),
             );
         }
 
@@ -408,23 +973,66 @@ impl Object {
         panic!("Expected RawSVG, got {:?}", self);
     }
 
-    fn render_text(&self, cell_size: usize) -> Box<dyn svg::node::Node> {
+    fn render_imported_svg(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
+        if let Object::ImportedSVG(region, contents) = self {
+            let Ok(document) = roxmltree::Document::parse(contents) else {
+                // reported by ensure_renderable
+                return Box::new(svg::node::element::Group::new());
+            };
+            let root = document.root_element();
+
+            let view_box = root
+                .attribute("viewBox")
+                .map(String::from)
+                .unwrap_or_else(|| {
+                    format!(
+                        "0 0 {} {}",
+                        root.attribute("width")
+                            .unwrap_or("100")
+                            .trim_end_matches("px"),
+                        root.attribute("height")
+                            .unwrap_or("100")
+                            .trim_end_matches("px"),
+                    )
+                });
+
+            let inner = match (root.first_child(), root.last_child()) {
+                (Some(first), Some(last)) => &contents[first.range().start..last.range().end],
+                _ => "",
+            };
+
+            let (x, y) = region.start.coords(layout);
+            return Box::new(
+                svg::node::element::SVG::new()
+                    .set("x", x)
+                    .set("y", y)
+                    .set("width", region.width() * layout.cell_size)
+                    .set("height", region.height() * layout.cell_size)
+                    .set("viewBox", view_box)
+                    .add(svg::node::Blob::new(inner)),
+            );
+        }
+
+        panic!("Expected ImportedSVG, got {:?}", self);
+    }
+
+    fn render_text(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
         if let Object::Text(position, content, font_size)
         | Object::CenteredText(position, content, font_size) = self
         {
             let centered = matches!(self, Object::CenteredText(..));
 
             let coords = if centered {
-                position.center_coords(cell_size)
+                position.center_coords(layout)
             } else {
-                position.coords(cell_size)
+                position.coords(layout)
             };
 
             let mut node = svg::node::element::Text::new(content.clone())
                 .set("x", coords.0)
                 .set("y", coords.1)
                 .set("font-size", format!("{}pt", font_size))
-                .set("font-family", "Inconsolata");
+                .set("font-family", FONT_FAMILY);
 
             if centered {
                 node = node
@@ -443,48 +1051,143 @@ impl Object {
         panic!("Expected Text, got {:?}", self);
     }
 
-    // fn render_fitted_text(&self, cell_size: usize) -> Box<dyn svg:node::Node> {
-    //     if let Object::FittedText(region, content) = self {
-    //         let (x, y) = region.start.coords(cell_size);
-    //         let width = region.width() * cell_size as f32;
-    //         let height = region.height() * cell_size as f32;
+    fn render_fitted_text(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
+        if let Object::FittedText(region, content, alignment) = self {
+            let (x, y) = region.start.coords(layout);
+            let width = (region.width() * layout.cell_size) as f32;
+            let height = (region.height() * layout.cell_size) as f32;
 
-    //         return Box::new(
-    //             svg::node::element::Text::new(content.clone())
-    //                 .set("x", x)
-    //                 .set("y", y)
-    //                 .set("")
-    //                 .set("font-size", format!("{}pt", 10.0))
-    //                 .set("font-family", "sans-serif"),
-    //         );
-    //     }
+            let x = match alignment {
+                TextAlignment::Start => x,
+                TextAlignment::Center => x + width / 2.0,
+                TextAlignment::End => x + width,
+            };
 
-    //     panic!("Expected FittedText, got {:?}", self);
-    // }
+            return Box::new(
+                svg::node::element::Text::new(content.clone())
+                    .set("x", x)
+                    .set("y", y + height / 2.0)
+                    .set("font-size", fitting_font_size(content, width, height))
+                    .set("font-family", FONT_FAMILY)
+                    .set("text-anchor", alignment.text_anchor())
+                    // FIXME does not work with imagemagick
+                    .set("dominant-baseline", "middle"),
+            );
+        }
+
+        panic!("Expected FittedText, got {:?}", self);
+    }
 
-    fn render_rectangle(&self, cell_size: usize) -> Box<dyn svg::node::Node> {
+    fn render_paragraph(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
+        if let Object::Paragraph(region, content, font_size, style) = self {
+            let (x, y) = region.start.coords(layout);
+            let width = (region.width() * layout.cell_size) as f32;
+            let height = (region.height() * layout.cell_size) as f32;
+
+            let x = match style.alignment {
+                TextAlignment::Start => x,
+                TextAlignment::Center => x + width / 2.0,
+                TextAlignment::End => x + width,
+            };
+
+            let max_lines = ((height - font_size * LINE_HEIGHT_EM)
+                / (font_size * style.line_height))
+                .floor()
+                .max(0.0) as usize
+                + 1;
+
+            let mut node = svg::node::element::Text::new("")
+                .set("x", x)
+                .set("y", y)
+                .set("font-size", *font_size)
+                .set("font-family", FONT_FAMILY)
+                .set("text-anchor", style.alignment.text_anchor())
+                // FIXME does not work with imagemagick
+                .set("dominant-baseline", "hanging");
+
+            for (i, line) in wrap_text(content, *font_size, width)
+                .into_iter()
+                .take(max_lines)
+                .enumerate()
+            {
+                node = node.add(svg::node::element::TSpan::new(line).set("x", x).set(
+                    "dy",
+                    if i == 0 {
+                        0.0
+                    } else {
+                        font_size * style.line_height
+                    },
+                ));
+            }
+
+            return Box::new(node);
+        }
+
+        panic!("Expected Paragraph, got {:?}", self);
+    }
+
+    fn render_text_on_path(
+        &self,
+        layout: GridLayout,
+        object_sizes: ObjectSizes,
+    ) -> Box<dyn svg::node::Node> {
+        if let Object::TextOnPath(path, content, font_size) = self {
+            let path_id = format!("textpath-{}", slugify(format!("{:?}", path)));
+            // canvases refuse to render text on objects without a path, see `ensure_renderable`
+            let path_data = path.path_data(layout, object_sizes).unwrap_or_default();
+
+            return Box::new(
+                svg::node::element::Group::new()
+                    .add(
+                        svg::node::element::Definitions::new().add(
+                            svg::node::element::Path::new()
+                                .set("id", path_id.clone())
+                                .set("d", path_data),
+                        ),
+                    )
+                    .add(
+                        svg::node::element::Text::new("")
+                            .set("font-size", *font_size)
+                            .set("font-family", FONT_FAMILY)
+                            .add(
+                                svg::node::element::TextPath::new(content.clone())
+                                    .set("href", format!("#{}", path_id)),
+                            ),
+                    ),
+            );
+        }
+
+        panic!("Expected TextOnPath, got {:?}", self);
+    }
+
+    fn render_rectangle(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
         if let Object::Rectangle(start, end) = self {
             return Box::new(
                 svg::node::element::Rectangle::new()
-                    .set("x", start.coords(cell_size).0)
-                    .set("y", start.coords(cell_size).1)
-                    .set("width", start.distances(end).0 * cell_size)
-                    .set("height", start.distances(end).1 * cell_size),
+                    .set("x", start.coords(layout).0)
+                    .set("y", start.coords(layout).1)
+                    .set("width", start.distances(end).0 * layout.cell_size)
+                    .set("height", start.distances(end).1 * layout.cell_size),
             );
         }
 
         panic!("Expected Rectangle, got {:?}", self);
     }
 
-    fn render_polygon(&self, cell_size: usize) -> Box<dyn svg::node::Node> {
+    fn render_polygon(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
+        if let Object::PolygonF(..) = self {
+            let path = self.path_data(layout, ObjectSizes::default());
+            return Box::new(svg::node::element::Path::new().set("d", path.unwrap()));
+        }
+
         if let Object::Polygon(start, lines) = self {
             let mut path = svg::node::element::path::Data::new();
-            path = path.move_to(start.coords(cell_size));
+            path = path.move_to(start.coords(layout));
             for line in lines {
                 path = match line {
                     LineSegment::Straight(end)
                     | LineSegment::InwardCurve(end)
-                    | LineSegment::OutwardCurve(end) => path.line_to(end.coords(cell_size)),
+                    | LineSegment::OutwardCurve(end) => path.line_to(end.coords(layout)),
                 };
             }
             path = path.close();
@@ -494,27 +1197,201 @@ impl Object {
         panic!("Expected Polygon, got {:?}", self);
     }
 
-    fn render_line(&self, cell_size: usize) -> Box<dyn svg::node::Node> {
-        if let Object::Line(start, end, width) = self {
+    /// Top face and the two front faces, the latter shaded darker so that the cube reads as lit from above
+    fn render_iso_cube(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
+        if let Object::IsoCube(anchor) = self {
+            let height = layout.cell_size as f32;
+            let [back, right, front, left] = [(0, 0), (1, 0), (1, 1), (0, 1)]
+                .map(|(dx, dy)| anchor.translated(dx, dy).coords(layout));
+            let lifted = |(x, y): (f32, f32)| (x, y - height);
+
+            let face = |corners: [(f32, f32); 4]| {
+                svg::node::element::Path::new().set(
+                    "d",
+                    corners
+                        .iter()
+                        .skip(1)
+                        .fold(
+                            svg::node::element::path::Data::new().move_to(corners[0]),
+                            |path, &corner| path.line_to(corner),
+                        )
+                        .close(),
+                )
+            };
+
+            let left_face = [left, front, lifted(front), lifted(left)];
+            let right_face = [front, right, lifted(right), lifted(front)];
+
             return Box::new(
-                svg::node::element::Line::new()
-                    .set("x1", start.coords(cell_size).0)
-                    .set("y1", start.coords(cell_size).1)
-                    .set("x2", end.coords(cell_size).0)
-                    .set("y2", end.coords(cell_size).1)
+                svg::node::element::Group::new()
+                    .add(face([back, right, front, left].map(lifted)))
+                    .add(face(left_face))
+                    .add(face(right_face))
+                    .add(
+                        face(left_face)
+                            .set("style", "fill: black; fill-opacity: 0.2; stroke: none;"),
+                    )
+                    .add(
+                        face(right_face)
+                            .set("style", "fill: black; fill-opacity: 0.4; stroke: none;"),
+                    ),
+            );
+        }
+
+        panic!("Expected IsoCube, got {:?}", self);
+    }
+
+    /// The object's outline as SVG path data, for objects that can be used as a path (e.g. by TextOnPath).
+    /// Circles start at their leftmost point and go clockwise.
+    pub fn path_data(
+        &self,
+        layout: GridLayout,
+        object_sizes: ObjectSizes,
+    ) -> Option<svg::node::element::path::Data> {
+        use svg::node::element::path::Data;
+
+        let circle = |(cx, cy): (f32, f32), r: f32| {
+            Data::new()
+                .move_to((cx - r, cy))
+                .elliptical_arc_to((r, r, 0, 1, 1, cx + r, cy))
+                .elliptical_arc_to((r, r, 0, 1, 1, cx - r, cy))
+        };
+
+        match self {
+            Object::Line(start, end, _) => Some(
+                Data::new()
+                    .move_to(start.coords(layout))
+                    .line_to(end.coords(layout)),
+            ),
+            Object::LineF(start, end, _) => Some(
+                Data::new()
+                    .move_to(start.coords(layout))
+                    .line_to(end.coords(layout)),
+            ),
+            Object::CurveInward(..) | Object::CurveOutward(..) => {
+                Some(self.curve_path_data(layout))
+            }
+            Object::Polygon(start, lines) => Some(
+                lines
+                    .iter()
+                    .fold(
+                        Data::new().move_to(start.coords(layout)),
+                        |path, line| match line {
+                            LineSegment::Straight(end)
+                            | LineSegment::InwardCurve(end)
+                            | LineSegment::OutwardCurve(end) => path.line_to(end.coords(layout)),
+                        },
+                    )
+                    .close(),
+            ),
+            Object::PolygonF(vertices) => Some(
+                vertices
+                    .iter()
+                    .skip(1)
+                    .fold(
+                        Data::new().move_to(
+                            vertices
+                                .first()
+                                .copied()
+                                .unwrap_or_default()
+                                .coords(layout),
+                        ),
+                        |path, vertex| path.line_to(vertex.coords(layout)),
+                    )
+                    .close(),
+            ),
+            Object::Rectangle(start, end) => {
+                let (x, y) = start.coords(layout);
+                let (w, h) = start.distances(end);
+                let (w, h) = ((w * layout.cell_size) as f32, (h * layout.cell_size) as f32);
+                Some(
+                    Data::new()
+                        .move_to((x, y))
+                        .horizontal_line_by(w)
+                        .vertical_line_by(h)
+                        .horizontal_line_by(-w)
+                        .close(),
+                )
+            }
+            Object::SmallCircle(..) | Object::SmallCircleF(..) => Some(circle(
+                self.center_coords(layout),
+                object_sizes.small_circle_radius,
+            )),
+            Object::BigCircle(..) | Object::BigCircleF(..) => Some(circle(
+                self.center_coords(layout),
+                layout.cell_size as f32 / 2.0,
+            )),
+            Object::Dot(..) | Object::DotF(..) => Some(circle(
+                self.center_coords(layout),
+                object_sizes.dot_radius,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Errors if the object cannot be rendered, like text on an object that has no path to follow (see [`Object::path_data`])
+    pub fn ensure_renderable(&self) -> Result<()> {
+        match self {
+            Object::TextOnPath(path, ..) if path.path_data(GridLayout::square(1), ObjectSizes::default()).is_none() => {
+                Err(format_err!("Cannot put text on a {:?}", path))
+            }
+            Object::ImportedSVG(_, contents) => roxmltree::Document::parse(contents)
+                .map(|_| ())
+                .map_err(|e| format_err!("Imported SVG is not valid: {}", e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn render_line(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
+        let (start, end, width) = match self {
+            Object::Line(start, end, width) => {
+                (start.coords(layout), end.coords(layout), width)
+            }
+            Object::LineF(start, end, width) => {
+                (start.coords(layout), end.coords(layout), width)
+            }
+            _ => panic!("Expected Line, got {:?}", self),
+        };
+
+        Box::new(
+            svg::node::element::Line::new()
+                .set("x1", start.0)
+                .set("y1", start.1)
+                .set("x2", end.0)
+                .set("y2", end.1)
+                .set("stroke-width", *width),
+        )
+    }
+
+    fn render_curve(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
+        if let Object::CurveOutward(_, _, width) | Object::CurveInward(_, _, width) = self {
+            return Box::new(
+                svg::node::element::Path::new()
+                    .set("d", self.curve_path_data(layout))
                     .set("stroke-width", *width),
             );
         }
 
-        panic!("Expected Line, got {:?}", self);
+        panic!("Expected Curve, got {:?}", self);
+    }
+
+    fn curve_path_data(&self, layout: GridLayout) -> svg::node::element::path::Data {
+        if let Object::CurveOutward(start, end, _) | Object::CurveInward(start, end, _) = self {
+            return svg::node::element::path::Data::new()
+                .move_to(start.coords(layout))
+                .quadratic_curve_to((self.curve_control_point(layout), end.coords(layout)));
+        }
+
+        panic!("Expected Curve, got {:?}", self);
     }
 
-    fn render_curve(&self, cell_size: usize) -> Box<dyn svg::node::Node> {
+    /// Control point of the curve's quadratic Bézier, in SVG coordinates
+    fn curve_control_point(&self, layout: GridLayout) -> (f32, f32) {
         if let Object::CurveOutward(start, end, _) | Object::CurveInward(start, end, _) = self {
             let inward = matches!(self, Object::CurveInward(..));
 
-            let (start_x, start_y) = start.coords(cell_size);
-            let (end_x, end_y) = end.coords(cell_size);
+            let (start_x, start_y) = start.coords(layout);
+            let (end_x, end_y) = end.coords(layout);
 
             let midpoint = ((start_x + end_x) / 2.0, (start_y + end_y) / 2.0);
             let start_from_midpoint = (start_x - midpoint.0, start_y - midpoint.1);
@@ -568,14 +1445,7 @@ impl Object {
                 }
             };
 
-            return Box::new(
-                svg::node::element::Path::new().set(
-                    "d",
-                    svg::node::element::path::Data::new()
-                        .move_to(start.coords(cell_size))
-                        .quadratic_curve_to((control, end.coords(cell_size))),
-                ),
-            );
+            return control;
         }
 
         panic!("Expected Curve, got {:?}", self);
@@ -583,49 +1453,124 @@ impl Object {
 
     fn render_small_circle(
         &self,
-        cell_size: usize,
+        layout: GridLayout,
         object_sizes: ObjectSizes,
     ) -> Box<dyn svg::node::Node> {
-        if let Object::SmallCircle(center) = self {
-            return Box::new(
-                svg::node::element::Circle::new()
-                    .set("cx", center.coords(cell_size).0)
-                    .set("cy", center.coords(cell_size).1)
-                    .set("r", object_sizes.small_circle_radius),
-            );
-        }
+        let center = match self {
+            Object::SmallCircle(center) => center.coords(layout),
+            Object::SmallCircleF(center) => center.coords(layout),
+            _ => panic!("Expected SmallCircle, got {:?}", self),
+        };
 
-        panic!("Expected SmallCircle, got {:?}", self);
+        Box::new(
+            svg::node::element::Circle::new()
+                .set("cx", center.0)
+                .set("cy", center.1)
+                .set("r", object_sizes.small_circle_radius),
+        )
     }
 
-    fn render_dot(&self, cell_size: usize, object_sizes: ObjectSizes) -> Box<dyn svg::node::Node> {
-        if let Object::Dot(center) = self {
-            return Box::new(
-                svg::node::element::Circle::new()
-                    .set("cx", center.coords(cell_size).0)
-                    .set("cy", center.coords(cell_size).1)
-                    .set("r", object_sizes.dot_radius),
-            );
-        }
+    fn render_dot(&self, layout: GridLayout, object_sizes: ObjectSizes) -> Box<dyn svg::node::Node> {
+        let center = match self {
+            Object::Dot(center) => center.coords(layout),
+            Object::DotF(center) => center.coords(layout),
+            _ => panic!("Expected Dot, got {:?}", self),
+        };
 
-        panic!("Expected Dot, got {:?}", self);
+        Box::new(
+            svg::node::element::Circle::new()
+                .set("cx", center.0)
+                .set("cy", center.1)
+                .set("r", object_sizes.dot_radius),
+        )
     }
 
-    fn render_big_circle(&self, cell_size: usize) -> Box<dyn svg::node::Node> {
-        if let Object::BigCircle(topleft) = self {
-            let (cx, cy) = {
-                let (x, y) = topleft.coords(cell_size);
-                (x + cell_size as f32 / 2.0, y + cell_size as f32 / 2.0)
-            };
+    fn render_big_circle(&self, layout: GridLayout) -> Box<dyn svg::node::Node> {
+        let (x, y) = match self {
+            Object::BigCircle(topleft) => topleft.coords(layout),
+            Object::BigCircleF(topleft) => topleft.coords(layout),
+            _ => panic!("Expected BigCircle, got {:?}", self),
+        };
 
-            return Box::new(
-                svg::node::element::Circle::new()
-                    .set("cx", cx)
-                    .set("cy", cy)
-                    .set("r", cell_size / 2),
+        Box::new(
+            svg::node::element::Circle::new()
+                .set("cx", x + layout.cell_size as f32 / 2.0)
+                .set("cy", y + layout.cell_size as f32 / 2.0)
+                .set("r", layout.cell_size / 2),
+        )
+    }
+}
+
+/// Returns a base64 data URI with the contents of the image file at `path`.
+/// Falls back to referencing the file by its path if it can't be read.
+fn image_data_uri(path: &Path) -> String {
+    let mime_type = match path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .as_deref()
+    {
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "image/png",
+    };
+
+    match std::fs::read(path) {
+        Ok(contents) => format!(
+            "data:{};base64,{}",
+            mime_type,
+            base64::engine::general_purpose::STANDARD.encode(contents)
+        ),
+        Err(e) => {
+            eprintln!(
+                "{}",
+                format_log_msg(
+                    "Warning",
+                    &format!(
+                        "could not read image {:?}, linking to it instead: {}",
+                        path, e
+                    )
+                )
             );
+            path.to_string_lossy().to_string()
         }
-
-        panic!("Expected BigCircle, got {:?}", self);
     }
 }
+
+/// Canvases loaded from files skip `Object::from_svg_file`, so imported SVG is checked again there
+fn deserialize_imported_svg<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(Region, String), D::Error> {
+    let (region, contents) = <(Region, String)>::deserialize(deserializer)?;
+    roxmltree::Document::parse(&contents).map_err(|e| {
+        serde::de::Error::custom(format!("imported SVG is not valid: {}", e))
+    })?;
+    Ok((region, contents))
+}
+
+#[test]
+fn test_small_circle_intersects_region() {
+    let cell = Region::new(0, 0, 0, 0).unwrap();
+    let sizes = ObjectSizes::default();
+    // 5px radius with 50px cells: the circle reaches 0.1 cells past its center
+    assert!(Object::SmallCircleF(PointF(1.05, 0.5)).intersects_region(&cell, sizes, 50));
+    assert!(!Object::SmallCircleF(PointF(1.2, 0.5)).intersects_region(&cell, sizes, 50));
+}
+
+#[test]
+fn test_imported_svg_is_validated() {
+    let region = Region::new(0, 0, 2, 2).unwrap();
+    let valid = Object::ImportedSVG(
+        region,
+        r#"<svg viewBox="0 0 10 10"><circle r="5"/></svg>"#.into(),
+    );
+    let json = serde_json::to_string(&valid).unwrap();
+    assert!(serde_json::from_str::<Object>(&json).is_ok());
+
+    let invalid = Object::ImportedSVG(region, "<svg>".into());
+    let json = serde_json::to_string(&invalid).unwrap();
+    assert!(serde_json::from_str::<Object>(&json).is_err());
+    assert!(invalid.ensure_renderable().is_err());
+    invalid.render_shape(GridLayout::square(1), ObjectSizes::default());
+}
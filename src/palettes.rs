@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use crate::ColorMapping;
+
+/// A named set of twelve colors, one for each of shapemaker's color names
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub name: &'static str,
+    /// In the order of [`SLOTS`]
+    pub colors: [&'static str; 12],
+}
+
+/// Color names, in the order palette colors are given
+pub const SLOTS: [&str; 12] = [
+    "black", "white", "red", "green", "blue", "yellow", "orange", "purple", "brown", "cyan",
+    "pink", "gray",
+];
+
+impl Palette {
+    pub fn colormap(&self) -> ColorMapping {
+        ColorMapping::from_hashmap(
+            SLOTS
+                .iter()
+                .zip(self.colors)
+                .map(|(slot, color)| (slot.to_string(), color.to_string()))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+}
+
+impl From<Palette> for ColorMapping {
+    fn from(palette: Palette) -> Self {
+        palette.colormap()
+    }
+}
+
+/// Twelve samples of matplotlib's perceptually uniform viridis scale.
+/// The darkest one is black, the lightest one white, and the others go to the remaining slots in order, so the names don't match the hues.
+pub const VIRIDIS_12: Palette = Palette {
+    name: "viridis",
+    colors: [
+        "#440154", "#fde725", "#482173", "#433e85", "#38588c", "#2d708e", "#25858e", "#1e9b8a",
+        "#2ab07f", "#52c569", "#86d549", "#c2df23",
+    ],
+};
+
+/// Ethan Schoonover's Solarized, with base03 as black and base3 as white
+pub const SOLARIZED: Palette = Palette {
+    name: "solarized",
+    colors: [
+        "#002b36", "#fdf6e3", "#dc322f", "#859900", "#268bd2", "#b58900", "#cb4b16", "#6c71c4",
+        "#8a6a10", "#2aa198", "#d33682", "#839496",
+    ],
+};
+
+/// Okabe and Ito's colorblind-safe palette. It only has eight colors, so brown is a darker orange, pink is the same as purple and gray is neutral.
+pub const OKABE_ITO: Palette = Palette {
+    name: "okabe-ito",
+    colors: [
+        "#000000", "#ffffff", "#d55e00", "#009e73", "#0072b2", "#f0e442", "#e69f00", "#cc79a7",
+        "#8f5a00", "#56b4e9", "#cc79a7", "#999999",
+    ],
+};
+
+pub const ALL: [Palette; 3] = [VIRIDIS_12, SOLARIZED, OKABE_ITO];
+
+/// Preset with the given name, see `ALL`
+pub fn by_name(name: &str) -> Option<Palette> {
+    ALL.iter().find(|palette| palette.name == name).copied()
+}
@@ -0,0 +1,94 @@
+use crate::{PointF, Region};
+use serde::{Deserialize, Serialize};
+
+/// Simple point-mass physics for an object, attached with `Layer::attach_body`.
+/// The body moves the object away from where it was placed, without changing the object itself.
+/// Units are cells and seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Body {
+    /// Offset from the object's position
+    pub position: PointF,
+    pub velocity: PointF,
+    /// Constant acceleration, e.g. gravity
+    pub acceleration: PointF,
+    /// Fraction of the velocity lost every second
+    pub drag: f32,
+    /// Fraction of the velocity kept when bouncing off the world region's edges
+    pub bounciness: f32,
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self {
+            position: PointF(0.0, 0.0),
+            velocity: PointF(0.0, 0.0),
+            acceleration: PointF(0.0, 0.0),
+            drag: 0.0,
+            bounciness: 0.8,
+        }
+    }
+}
+
+impl Body {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_velocity(self, dx: f32, dy: f32) -> Self {
+        Self {
+            velocity: PointF(dx, dy),
+            ..self
+        }
+    }
+
+    pub fn with_gravity(self, gravity: f32) -> Self {
+        Self {
+            acceleration: PointF(self.acceleration.0, gravity),
+            ..self
+        }
+    }
+
+    pub fn with_drag(self, drag: f32) -> Self {
+        Self { drag, ..self }
+    }
+
+    pub fn with_bounciness(self, bounciness: f32) -> Self {
+        Self { bounciness, ..self }
+    }
+
+    /// Gives the body a kick, e.g. on a beat
+    pub fn push(&mut self, dx: f32, dy: f32) {
+        self.velocity.translate(dx, dy);
+    }
+
+    /// Advances the simulation by `ms` milliseconds, keeping an object occupying `region` inside `world`
+    pub fn step(&mut self, ms: usize, region: &Region, world: &Region) {
+        let dt = ms as f32 / 1000.0;
+
+        self.velocity
+            .translate(self.acceleration.0 * dt, self.acceleration.1 * dt);
+        let kept = (1.0 - self.drag * dt).max(0.0);
+        self.velocity = PointF(self.velocity.0 * kept, self.velocity.1 * kept);
+        self.position
+            .translate(self.velocity.0 * dt, self.velocity.1 * dt);
+
+        // offsets that keep the object's region within the world region
+        let min = PointF(
+            world.start.0 as f32 - region.start.0 as f32,
+            world.start.1 as f32 - region.start.1 as f32,
+        );
+        let max = PointF(
+            world.end.0 as f32 - region.end.0 as f32,
+            world.end.1 as f32 - region.end.1 as f32,
+        );
+
+        if self.position.0 < min.0 || self.position.0 > max.0 {
+            self.position.0 = self.position.0.clamp(min.0, max.0.max(min.0));
+            self.velocity.0 *= -self.bounciness;
+        }
+        if self.position.1 < min.1 || self.position.1 > max.1 {
+            self.position.1 = self.position.1.clamp(min.1, max.1.max(min.1));
+            self.velocity.1 *= -self.bounciness;
+        }
+    }
+}
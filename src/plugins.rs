@@ -0,0 +1,297 @@
+//! Third-party object generators, fills and hooks, compiled to WebAssembly and loaded from a plugins directory.
+//! Only available with the `plugins` feature.
+//!
+//! `shapemaker video --plugins <directory>` loads the plugins of the directory (see [`load`]), whose generators and hooks can then be used by scene files:
+//! `generator: { plugin: <name> }` for layers, and `- plugin: <name>` for hook actions.
+//!
+//! # Plugin ABI
+//!
+//! Data goes in and out of plugins as JSON strings in the plugin's memory.
+//! A string is returned as an i64 packing its pointer (high 32 bits) and length in bytes (low 32 bits).
+//!
+//! A plugin module exports:
+//! - `memory`
+//! - `shapemaker_alloc(len: i32) -> i32`, returning where the host can write a string of `len` bytes
+//! - `shapemaker_free(ptr: i32, len: i32)`, called by the host once it is done with a string, be it one it wrote or one the plugin returned
+//! - `shapemaker_manifest() -> i64`, returning a [`Manifest`]
+//! - for every generator, `generate_<name>(ptr: i32, len: i32) -> i64`, taking a [`GeneratorInput`] and returning the objects of the layer, by object name
+//! - for every fill, `fill_<name>(ptr: i32, len: i32) -> i64`, taking the [`Object`] to fill and returning a [`Fill`]
+//! - for every hook, `hook_<name>(ptr: i32, len: i32) -> i64`, taking a [`HookInput`] and returning the new [`Canvas`]
+//!
+//! Objects, fills and canvases use the same JSON representation as `Canvas::save`.
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use anyhow::{format_err, Context as _, Result};
+use indexmap::IndexMap;
+use once_cell::sync::Lazy;
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use wasmtime::{Engine, Instance, Memory, Module, Store, TypedFunc};
+
+use crate::{
+    random::rng, Canvas, ColoredObject, Context, Fill, Layer, Object, Region, RenderFunction,
+};
+
+/// What a plugin provides, as returned by its `shapemaker_manifest` export
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Manifest {
+    pub name: String,
+    pub generators: Vec<String>,
+    pub fills: Vec<String>,
+    pub hooks: Vec<String>,
+}
+
+/// Given to a plugin's generators
+#[derive(Debug, Serialize)]
+pub struct GeneratorInput<'a> {
+    /// Name of the generated layer, objects should be named after it to avoid clashes
+    pub layer: &'a str,
+    pub region: Region,
+    pub world_region: Region,
+    /// Number of objects to generate
+    pub objects: usize,
+    /// Plugins don't have access to shapemaker's random number generator, so they are given a seed instead
+    pub seed: u64,
+}
+
+/// Given to a plugin's hooks
+#[derive(Debug, Serialize)]
+pub struct HookInput<'a> {
+    pub canvas: &'a Canvas,
+    pub ms: usize,
+    pub frame: usize,
+    pub beat: usize,
+    pub beat_fractional: f32,
+    pub bpm: usize,
+    pub marker: String,
+    /// Relative amplitude (from 0 to 1) of each stem
+    pub amplitudes: HashMap<String, f32>,
+    pub params: &'a HashMap<String, f32>,
+}
+
+pub struct Plugin {
+    pub manifest: Manifest,
+    instance: Instance,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    free: TypedFunc<(i32, i32), ()>,
+    store: Mutex<Store<()>>,
+}
+
+impl Plugin {
+    pub fn load(engine: &Engine, path: &Path) -> Result<Self> {
+        let module = Module::from_file(engine, path)?;
+        let mut store = Store::new(engine, ());
+        let instance = Instance::new(&mut store, &module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| format_err!("plugin does not export its memory"))?;
+        let alloc = instance.get_typed_func(&mut store, "shapemaker_alloc")?;
+        let free = instance.get_typed_func(&mut store, "shapemaker_free")?;
+        let manifest_fn = instance.get_typed_func::<(), i64>(&mut store, "shapemaker_manifest")?;
+
+        let packed = manifest_fn.call(&mut store, ())?;
+        let manifest = read_string(&memory, &store, packed)?;
+        let (ptr, len) = unpack(packed);
+        free.call(&mut store, (ptr, len))?;
+        Ok(Self {
+            manifest: serde_json::from_slice(&manifest)?,
+            instance,
+            memory,
+            alloc,
+            free,
+            store: Mutex::new(store),
+        })
+    }
+
+    /// Calls one of the plugin's `(ptr, len) -> i64` exports, passing and returning JSON
+    fn call<I: Serialize + ?Sized, O: DeserializeOwned>(
+        &self,
+        export: &str,
+        input: &I,
+    ) -> Result<O> {
+        let input = serde_json::to_vec(input)?;
+        let mut store = self.store.lock().unwrap();
+        let function = self
+            .instance
+            .get_typed_func::<(i32, i32), i64>(&mut *store, export)?;
+
+        // both strings are freed even if the call fails, hooks run on every frame and would exhaust the plugin's memory otherwise
+        let ptr = self.alloc.call(&mut *store, input.len() as i32)?;
+        let output = self
+            .memory
+            .write(&mut *store, ptr as usize, &input)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| function.call(&mut *store, (ptr, input.len() as i32)));
+        self.free.call(&mut *store, (ptr, input.len() as i32))?;
+
+        let packed = output?;
+        let output = read_string(&self.memory, &*store, packed);
+        self.free.call(&mut *store, unpack(packed))?;
+        serde_json::from_slice(&output?)
+            .with_context(|| format!("{} returned invalid JSON", export))
+    }
+}
+
+/// Pointer and length of a string returned by a plugin
+fn unpack(packed: i64) -> (i32, i32) {
+    (
+        (packed as u64 >> 32) as i32,
+        (packed as u64 & 0xffff_ffff) as i32,
+    )
+}
+
+fn read_string(memory: &Memory, store: impl wasmtime::AsContext, packed: i64) -> Result<Vec<u8>> {
+    let (ptr, len) = unpack(packed);
+    let mut buffer = vec![0; len as u32 as usize];
+    memory.read(store, ptr as u32 as usize, &mut buffer)?;
+    Ok(buffer)
+}
+
+static LOADED: Lazy<RwLock<Arc<Plugins>>> = Lazy::new(Default::default);
+
+/// Loads the plugins of the directory (see [`Plugins::discover`]), replacing the ones loaded before. Scene files can then use them.
+pub fn load(directory: &Path) -> Result<()> {
+    let plugins = Plugins::discover(directory)?;
+    for plugin in &plugins.plugins {
+        println!(
+            "Loaded plugin {}: generators {:?}, fills {:?}, hooks {:?}",
+            plugin.manifest.name,
+            plugin.manifest.generators,
+            plugin.manifest.fills,
+            plugin.manifest.hooks
+        );
+    }
+    *LOADED.write().unwrap() = Arc::new(plugins);
+    Ok(())
+}
+
+/// Plugins loaded with [`load`]
+pub fn loaded() -> Arc<Plugins> {
+    LOADED.read().unwrap().clone()
+}
+
+/// All loaded plugins. Generators, fills and hooks are looked up by name across all of them.
+#[derive(Default)]
+pub struct Plugins {
+    pub plugins: Vec<Arc<Plugin>>,
+}
+
+impl Plugins {
+    /// Loads every .wasm file in the directory. A missing directory means no plugins.
+    pub fn discover(directory: &Path) -> Result<Self> {
+        if !directory.exists() {
+            return Ok(Self::default());
+        }
+        let engine = Engine::default();
+        let mut plugins = vec![];
+        for entry in std::fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "wasm") {
+                let plugin = Plugin::load(&engine, &path)
+                    .with_context(|| format!("Could not load plugin {}", path.display()))?;
+                plugins.push(Arc::new(plugin));
+            }
+        }
+        Ok(Self { plugins })
+    }
+
+    pub fn provides_generator(&self, name: &str) -> bool {
+        self.providing(|manifest| &manifest.generators, name)
+            .is_ok()
+    }
+
+    pub fn provides_hook(&self, name: &str) -> bool {
+        self.providing(|manifest| &manifest.hooks, name).is_ok()
+    }
+
+    fn providing(
+        &self,
+        what: impl Fn(&Manifest) -> &Vec<String>,
+        name: &str,
+    ) -> Result<Arc<Plugin>> {
+        self.plugins
+            .iter()
+            .find(|plugin| {
+                what(&plugin.manifest)
+                    .iter()
+                    .any(|provided| provided == name)
+            })
+            .cloned()
+            .ok_or_else(|| format_err!("No plugin provides {name}"))
+    }
+
+    /// Layer of `objects` objects made by the plugin generator `generator`, within `region`
+    pub fn generate(
+        &self,
+        canvas: &Canvas,
+        generator: &str,
+        layer: &str,
+        region: &Region,
+        objects: usize,
+    ) -> Result<Layer> {
+        let plugin = self.providing(|manifest| &manifest.generators, generator)?;
+        let objects: IndexMap<String, ColoredObject> = plugin.call(
+            &format!("generate_{generator}"),
+            &GeneratorInput {
+                layer,
+                region: *region,
+                world_region: canvas.world_region,
+                objects,
+                seed: rng().gen(),
+            },
+        )?;
+
+        let mut generated = Layer::new(layer);
+        generated.object_sizes = canvas.object_sizes;
+        generated.objects = objects;
+        Ok(generated)
+    }
+
+    /// Fill chosen by the plugin fill `fill` for the object
+    pub fn fill(&self, fill: &str, object: &Object) -> Result<Fill> {
+        self.providing(|manifest| &manifest.fills, fill)?
+            .call(&format!("fill_{fill}"), object)
+    }
+
+    /// Hook handler `hook`, to pass to `Video::each_frame`, `Video::on`, etc.
+    pub fn hook<C>(self: &Arc<Self>, hook: &str) -> Result<&'static RenderFunction<C>> {
+        self.providing(|manifest| &manifest.hooks, hook)?;
+        let (plugins, hook) = (self.clone(), hook.to_string());
+        Ok(Box::leak(Box::new(
+            move |canvas: &mut Canvas, context: &mut Context<C>| {
+                plugins.run_hook(&hook, canvas, context)
+            },
+        )))
+    }
+
+    /// Runs the plugin hook `hook` on the canvas
+    pub fn run_hook<C>(&self, hook: &str, canvas: &mut Canvas, context: &Context<C>) -> Result<()> {
+        let plugin = self.providing(|manifest| &manifest.hooks, hook)?;
+        let stem_names = context
+            .syncdata
+            .stems
+            .keys()
+            .chain(context.live_stems.keys());
+        let input = HookInput {
+            canvas,
+            ms: context.ms,
+            frame: context.frame,
+            beat: context.beat,
+            beat_fractional: context.beat_fractional,
+            bpm: context.bpm,
+            marker: context.marker(),
+            amplitudes: stem_names
+                .map(|name| (name.clone(), context.stem(name).amplitude_relative()))
+                .collect(),
+            params: &context.params,
+        };
+        *canvas = plugin.call(&format!("hook_{hook}"), &input)?;
+        Ok(())
+    }
+}
@@ -1,11 +1,102 @@
 use wasm_bindgen::prelude::*;
 
 use crate::Region;
+use serde::{Deserialize, Serialize};
+
+/// How anchor points are laid out on the canvas
+#[derive(Debug, Clone, Copy, Default, PartialEq, Hash, Serialize, Deserialize)]
+pub enum GridTopology {
+    #[default]
+    Square,
+    /// Points are axial hex coordinates: the second coordinate's axis is tilted 60° from the first one's,
+    /// so that every point has six equidistant neighbors
+    Hexagonal,
+}
+
+impl GridTopology {
+    /// Position of grid coordinates on the canvas, in cells
+    pub fn project(&self, x: f32, y: f32) -> (f32, f32) {
+        match self {
+            GridTopology::Square => (x, y),
+            GridTopology::Hexagonal => (x + y / 2.0, y * 3.0_f32.sqrt() / 2.0),
+        }
+    }
+
+    /// Whether going from a point by (dx, dy) follows one of the grid's straight axes (diagonals on square grids)
+    pub fn is_axis_aligned(&self, dx: i32, dy: i32) -> bool {
+        match self {
+            GridTopology::Square => dx.abs() == dy.abs(),
+            GridTopology::Hexagonal => dx == 0 || dy == 0 || dx == -dy,
+        }
+    }
+}
+
+/// How the laid out grid is viewed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Projection {
+    #[default]
+    Orthographic,
+    /// The grid is seen as a floor, tilted by 30° on both axes. Pairs well with Object::IsoCube
+    Isometric,
+}
+
+impl Projection {
+    /// Position of (laid out) grid coordinates on the canvas, in cells
+    pub fn project(&self, x: f32, y: f32) -> (f32, f32) {
+        match self {
+            Projection::Orthographic => (x, y),
+            Projection::Isometric => (
+                (x - y) * 30.0_f32.to_radians().cos(),
+                (x + y) * 30.0_f32.to_radians().sin(),
+            ),
+        }
+    }
+}
+
+/// How grid coordinates become SVG coordinates: cells of `cell_size` pixels, laid out according to a topology and seen through a projection.
+/// Passed down when rendering, see [`crate::Canvas::grid_layout`].
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Serialize, Deserialize)]
+pub struct GridLayout {
+    pub cell_size: usize,
+    pub topology: GridTopology,
+    pub projection: Projection,
+}
+
+impl GridLayout {
+    /// A square grid seen from above, where SVG coordinates are grid coordinates times `cell_size`
+    pub fn square(cell_size: usize) -> Self {
+        Self {
+            cell_size,
+            topology: GridTopology::Square,
+            projection: Projection::Orthographic,
+        }
+    }
+
+    /// Position of grid coordinates on the canvas, in SVG units
+    pub fn project(&self, x: f32, y: f32) -> (f32, f32) {
+        let (x, y) = layout(self.topology, self.projection, x, y);
+        (x * self.cell_size as f32, y * self.cell_size as f32)
+    }
+}
+
+/// Position of grid coordinates on the canvas, in cells
+pub(crate) fn layout(topology: GridTopology, projection: Projection, x: f32, y: f32) -> (f32, f32) {
+    let (x, y) = topology.project(x, y);
+    projection.project(x, y)
+}
 
 #[wasm_bindgen]
-#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Point(pub usize, pub usize);
 
+#[wasm_bindgen]
+impl Point {
+    #[wasm_bindgen(constructor)]
+    pub fn new(x: usize, y: usize) -> Self {
+        Self(x, y)
+    }
+}
+
 impl Point {
     pub fn translated(&self, dx: i32, dy: i32) -> Self {
         Self((self.0 as i32 + dx) as usize, (self.1 as i32 + dy) as usize)
@@ -27,14 +118,13 @@ impl Point {
         self.1 = (self.1 as i32 + dy) as usize;
     }
 
-    pub fn coords(&self, cell_size: usize) -> (f32, f32) {
-        ((self.0 * cell_size) as f32, (self.1 * cell_size) as f32)
+    pub fn coords(&self, layout: GridLayout) -> (f32, f32) {
+        layout.project(self.0 as f32, self.1 as f32)
     }
 
     /// get SVG coordinates of the cell's center instead of its origin (top-left)
-    pub fn center_coords(&self, cell_size: usize) -> (f32, f32) {
-        let (x, y) = self.coords(cell_size);
-        (x + cell_size as f32 / 2.0, y + cell_size as f32 / 2.0)
+    pub fn center_coords(&self, layout: GridLayout) -> (f32, f32) {
+        layout.project(self.0 as f32 + 0.5, self.1 as f32 + 0.5)
     }
 
     pub fn distances(&self, other: &Point) -> (usize, usize) {
@@ -67,3 +157,78 @@ impl std::fmt::Display for Point {
         write!(f, "({}, {})", self.0, self.1)
     }
 }
+
+/// A point with fractional grid coordinates, for positions between cell corners.
+/// Coordinates are in cells, like [`Point`]: `PointF(0.5, 0.0)` is halfway between the first two anchors.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PointF(pub f32, pub f32);
+
+#[wasm_bindgen]
+impl PointF {
+    #[wasm_bindgen(constructor)]
+    pub fn new(x: f32, y: f32) -> Self {
+        Self(x, y)
+    }
+}
+
+impl PointF {
+    pub fn translated(&self, dx: f32, dy: f32) -> Self {
+        Self(self.0 + dx, self.1 + dy)
+    }
+
+    pub fn translate(&mut self, dx: f32, dy: f32) {
+        self.0 += dx;
+        self.1 += dy;
+    }
+
+    pub fn coords(&self, layout: GridLayout) -> (f32, f32) {
+        layout.project(self.0, self.1)
+    }
+
+    /// Linear interpolation between this point (t = 0) and the other one (t = 1)
+    pub fn lerp(&self, other: &PointF, t: f32) -> Self {
+        Self(
+            self.0 + (other.0 - self.0) * t,
+            self.1 + (other.1 - self.1) * t,
+        )
+    }
+
+    /// The anchor point at the top-left of the cell this point is in
+    pub fn floored(&self) -> Point {
+        Point(
+            self.0.max(0.0).floor() as usize,
+            self.1.max(0.0).floor() as usize,
+        )
+    }
+
+    /// The closest anchor point
+    pub fn rounded(&self) -> Point {
+        Point(
+            self.0.max(0.0).round() as usize,
+            self.1.max(0.0).round() as usize,
+        )
+    }
+
+    pub fn region(&self) -> Region {
+        self.floored().region()
+    }
+}
+
+impl From<Point> for PointF {
+    fn from(value: Point) -> Self {
+        Self(value.0 as f32, value.1 as f32)
+    }
+}
+
+impl From<(f32, f32)> for PointF {
+    fn from(value: (f32, f32)) -> Self {
+        Self(value.0, value.1)
+    }
+}
+
+impl std::fmt::Display for PointF {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "({}, {})", self.0, self.1)
+    }
+}
@@ -0,0 +1,39 @@
+//! The stable public API of shapemaker, which is also what the crate root re-exports: `use shapemaker::prelude::*;`.
+//! Helpers outside of it are reached through their module, e.g. `shapemaker::color::random_color`.
+
+pub use crate::{
+    animation::{Animation, AnimationUpdateFunction, LayerAnimationUpdateFunction},
+    audio::{
+        LiveStem, MusicalDurationUnit, Note, Stem, StemAtInstant, StemWindow, VelocityCurve,
+    },
+    canvas::{Canvas, ObjectKind, RandomLayerOptions, TruchetTiles},
+    color::{Color, ColorMapping, ColorRamp},
+    fill::{Angle, Fill, FillOperations, HatchDirection, PatternTile},
+    filter::{Filter, FilterChain, FilterType},
+    generators::{
+        lsystem::LSystem,
+        subdivision::Subdivision,
+        wfc::{Tile, WaveFunctionCollapse},
+    },
+    layer::{layer_name_matches, BlendMode, Layer, LayerGroup},
+    mask::Mask,
+    midi::MidiSynchronizer,
+    morph::morph,
+    noise::Noise,
+    objects::{Axis, ColoredObject, LineSegment, Object, ObjectSizes},
+    palettes::{self, Palette},
+    physics::Body,
+    point::{GridLayout, GridTopology, Point, PointF, Projection},
+    random::set_seed,
+    region::{Containable, MultiRegion, Region},
+    stroke::{Stroke, StrokeStyle},
+    sync::{SyncData, Syncable},
+    text::{ParagraphStyle, TextAlignment},
+    transform::Transformation,
+    video::{
+        BeatNumber, Command, CommandAction, FrameNumber, Hook, HookCondition, LaterHook,
+        LaterHookCondition, LaterRenderFunction, Millisecond, Moment, PaletteKeypoint, Parameter,
+        RenderErrorPolicy, RenderFunction, Video,
+    },
+    Context,
+};
@@ -1,19 +1,44 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, RwLock},
+    thread,
+};
 
 use anyhow::Result;
+use flate2::{write::GzEncoder, Compression};
 use handlebars::Handlebars;
 use itertools::Itertools;
+use serde::Serialize;
 use serde_json::json;
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
 
-use crate::Canvas;
+use crate::{ui::format_log_msg, Canvas, Parameter};
 
 const FRAMES_BUFFER_SIZE: usize = 500;
 
+/// What the preview's scrub bar, meters and sliders show. Also served as JSON on `/timeline`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Timeline {
+    pub duration_ms: usize,
+    /// Start of each beat, see [`crate::SyncData::beats_until`]
+    pub beats: Vec<usize>,
+    /// Marker texts by millisecond
+    pub markers: BTreeMap<usize, String>,
+    /// Relative amplitudes of each stem, one value every `stems_resolution_ms`. See [`crate::Stem::downsampled_amplitudes`].
+    pub stems: BTreeMap<String, Vec<f32>>,
+    pub stems_resolution_ms: usize,
+    pub parameters: Vec<Parameter>,
+}
+
 pub fn render_template(
     frames: &HashMap<usize, String>,
     canvas: &Canvas,
     path_to_audio_file: PathBuf,
     port: usize,
+    timeline: &Timeline,
 ) -> String {
     let template = String::from_utf8_lossy(include_bytes!("../preview/index.html.hbs"));
     let engine_js_source = String::from_utf8_lossy(include_bytes!("../preview/engine.js"));
@@ -25,14 +50,21 @@ pub fn render_template(
             "frames":frames,
             "audiopath": path_to_audio_file,
             "enginesource": engine_js_source,
-            "background": canvas.background.map_or("black".to_string(), |color| color.render(&canvas.colormap)),
+            "background": canvas.background.as_ref().map_or("black".to_string(), |color| color.render(&canvas.colormap)),
             "serverorigin": format!("http://localhost:{}", port),
             "framesbuffersize": FRAMES_BUFFER_SIZE,
+            "timeline": script_json(timeline),
+            "layers": script_json(&canvas.all_layers().map(|layer| &layer.name).collect_vec()),
         }),
     )
     .unwrap()
 }
 
+/// JSON to inline in a <script> element: a "</script>" inside of a string would end the element early
+fn script_json(value: &impl Serialize) -> String {
+    serde_json::to_string(value).unwrap().replace("</", "<\\/")
+}
+
 // rendered_svg_frames should map ms timestamps to SVG strings
 pub fn output_preview(
     canvas: &Canvas,
@@ -40,6 +72,7 @@ pub fn output_preview(
     server_port: usize,
     output_file: PathBuf,
     audio_file: PathBuf,
+    timeline: &Timeline,
 ) -> Result<()> {
     let first_frames = rendered_svg_frames
         .iter()
@@ -49,48 +82,562 @@ pub fn output_preview(
         .map(|(ms, svg)| (*ms, svg.clone()))
         .collect::<HashMap<usize, String>>();
 
-    let contents = render_template(&first_frames, canvas, audio_file, server_port);
+    let contents = render_template(&first_frames, canvas, audio_file, server_port, timeline);
     fs::write(output_file, contents)?;
     Ok(())
 }
 
-pub fn start_preview_server(port: usize, frames: HashMap<usize, String>) -> Result<()> {
-    let server = tiny_http::Server::http(format!("0.0.0.0:{}", port)).unwrap();
-    println!("Preview server running on port {}", port);
-    let sorted_frames: Vec<(&usize, &String)> =
-        frames.iter().sorted_by_key(|(ms, _)| *ms).collect();
-    println!("{} frames available", sorted_frames.len());
+type LiveSocket = WebSocket<Box<dyn tiny_http::ReadWrite + Send>>;
+
+/// Frames from another render, shown next to or over the preview's frames
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    /// SVG frames by frame number
+    pub frames: HashMap<usize, String>,
+    pub fps: usize,
+}
+
+impl Comparison {
+    /// Load the `<frame number>.svg` files of a directory, such as the frames output directory of a previous render
+    pub fn from_directory(directory: impl AsRef<Path>, fps: usize) -> Result<Self> {
+        let mut frames = HashMap::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "svg") {
+                if let Some(Ok(frame)) = path.file_stem().map(|stem| stem.to_string_lossy().parse())
+                {
+                    frames.insert(frame, fs::read_to_string(&path)?);
+                }
+            }
+        }
+        Ok(Self { frames, fps })
+    }
+
+    pub fn frame_at(&self, ms: usize) -> Option<&String> {
+        self.frames.get(&(self.fps * ms / 1000))
+    }
+}
+
+/// Serves frames to preview.html, and pushes updates to it over a WebSocket on `/live`
+pub struct PreviewServer {
+    frames: Arc<RwLock<HashMap<usize, String>>>,
+    comparison: Arc<RwLock<Option<Comparison>>>,
+    sockets: Arc<Mutex<Vec<LiveSocket>>>,
+    requests: Option<mpsc::Sender<PreviewRequest>>,
+    thread: thread::JoinHandle<()>,
+}
+
+/// Size of the PNG frames downloaded from the preview, see [`Canvas::save_as`]
+#[derive(Debug, Clone, Copy)]
+pub struct ExportSize {
+    pub aspect_ratio: f32,
+    pub resolution: usize,
+}
+
+/// What the preview asks of a lazy server, see [`PreviewServer::start_lazy`]
+pub enum PreviewRequest {
+    Frames(FrameRequest),
+    /// A single frame to download. It should be rendered without changing where the preview's frames are rendered from.
+    Export(FrameRequest),
+    /// A parameter was changed with its slider, frames need to be rendered again
+    SetParameter {
+        name: String,
+        value: f32,
+    },
+    /// Watched files changed, see [`PreviewServer::refresh_on_changes`]
+    Refresh,
+}
+
+/// Frames the preview asked for, see [`PreviewServer::start_lazy`]
+pub struct FrameRequest {
+    pub from_ms: usize,
+    pub count: usize,
+    respond_to: mpsc::Sender<HashMap<usize, String>>,
+}
+
+impl FrameRequest {
+    /// Send the rendered frames, by millisecond, to the preview
+    pub fn respond(self, frames: HashMap<usize, String>) {
+        // the preview might have gone away in the meantime
+        let _ = self.respond_to.send(frames);
+    }
+}
 
-    for request in server.incoming_requests() {
-        let (frame_start_ms, requested_frames_count) = get_request_params(request.url());
+impl PreviewServer {
+    /// Serve the given, already rendered, frames
+    pub fn start(
+        port: usize,
+        frames: HashMap<usize, String>,
+        timeline: Timeline,
+        export_size: ExportSize,
+    ) -> Result<Self> {
+        Self::spawn(port, frames, timeline, export_size, None)
+    }
 
-        println!(
-            "Request for {} frames @ {}ms",
-            requested_frames_count, frame_start_ms,
-        );
+    /// Serve frames rendered on demand: each time the preview needs frames, a request is sent to the returned receiver and the server waits for its response.
+    /// Parameter changes are sent there too.
+    pub fn start_lazy(
+        port: usize,
+        timeline: Timeline,
+        export_size: ExportSize,
+    ) -> Result<(Self, mpsc::Receiver<PreviewRequest>)> {
+        let (requests, receiver) = mpsc::channel();
+        let server = Self::spawn(port, HashMap::new(), timeline, export_size, Some(requests))?;
+        Ok((server, receiver))
+    }
 
-        let contents = sorted_frames
+    fn spawn(
+        port: usize,
+        frames: HashMap<usize, String>,
+        timeline: Timeline,
+        export_size: ExportSize,
+        requests: Option<mpsc::Sender<PreviewRequest>>,
+    ) -> Result<Self> {
+        let server = tiny_http::Server::http(format!("0.0.0.0:{}", port))
+            .map_err(|e| anyhow::format_err!("Could not start preview server: {}", e))?;
+        println!("Preview server running on port {}", port);
+        println!("{} frames available", frames.len());
+
+        let frames = Arc::new(RwLock::new(frames));
+        let comparison = Arc::new(RwLock::new(None));
+        let sockets = Arc::new(Mutex::new(vec![]));
+
+        let thread = {
+            let frames = frames.clone();
+            let comparison = comparison.clone();
+            let sockets = sockets.clone();
+            let requests = requests.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let url = request.url().to_string();
+                    // a failed request must not stop the server, the preview can just ask again
+                    if let Err(e) = handle_request(
+                        request,
+                        &frames,
+                        &comparison,
+                        &sockets,
+                        &timeline,
+                        export_size,
+                        &requests,
+                    ) {
+                        eprintln!(
+                            "{}",
+                            format_log_msg("Failed", &format!("to respond to {url}: {e:#}"))
+                        );
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            frames,
+            comparison,
+            sockets,
+            requests,
+            thread,
+        })
+    }
+
+    /// Send the frames of another render along with the preview's frames, for A/B comparisons
+    pub fn compare_with(&self, comparison: Comparison) {
+        println!("Comparing with {} frames", comparison.frames.len());
+        *self.comparison.write().unwrap() = Some(comparison);
+        self.broadcast(json!({ "type": "reload" }));
+    }
+
+    /// Replace all frames and make connected previews drop the ones they have loaded.
+    /// The given frames are pushed to them right away, previews that get none ask for frames again.
+    pub fn reload(&self, frames: HashMap<usize, String>) {
+        self.broadcast(json!({ "type": "reload", "frames": frames }));
+        *self.frames.write().unwrap() = frames;
+    }
+
+    /// Watch the given files and directories in the background, and send a [`PreviewRequest::Refresh`] when one of them changes.
+    /// Only lazy servers can render frames again.
+    pub fn refresh_on_changes(&self, watched: Vec<PathBuf>) -> Result<()> {
+        let Some(requests) = self.requests.clone() else {
+            return Err(anyhow::format_err!(
+                "Only lazy preview servers can render frames again when files change"
+            ));
+        };
+
+        thread::spawn(move || {
+            let mut last_modified = latest_modification(&watched);
+            loop {
+                thread::sleep(std::time::Duration::from_millis(500));
+                let modified = latest_modification(&watched);
+                if modified <= last_modified {
+                    continue;
+                }
+                last_modified = modified;
+
+                // the renderer is gone, so is the server
+                if requests.send(PreviewRequest::Refresh).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Block until the server stops
+    pub fn wait(self) -> Result<()> {
+        self.thread
+            .join()
+            .map_err(|_| anyhow::format_err!("Preview server crashed"))
+    }
+
+    fn broadcast(&self, message: serde_json::Value) {
+        let message = Message::text(message.to_string());
+        // disconnected previews fail to receive and are dropped
+        self.sockets
+            .lock()
+            .unwrap()
+            .retain_mut(|socket| socket.send(message.clone()).is_ok());
+    }
+}
+
+fn handle_request(
+    request: tiny_http::Request,
+    frames: &RwLock<HashMap<usize, String>>,
+    comparison: &RwLock<Option<Comparison>>,
+    sockets: &Mutex<Vec<LiveSocket>>,
+    timeline: &Timeline,
+    export_size: ExportSize,
+    requests: &Option<mpsc::Sender<PreviewRequest>>,
+) -> Result<()> {
+    if request.url().starts_with("/live") {
+        if let Some(socket) = accept_websocket(request) {
+            sockets.lock().unwrap().push(socket);
+        }
+        Ok(())
+    } else if request.url().starts_with("/timeline") {
+        respond(request, serde_json::to_string(timeline)?)
+    } else if let Some(file) = request.url().strip_prefix("/frame/") {
+        let file = file.to_string();
+        export_frame(request, &file, export_size, |ms| {
+            exported_frame(frames, requests, ms)
+        })
+    } else if let Some(parameter) = request.url().strip_prefix("/parameters/") {
+        let parameter = parameter.to_string();
+        set_parameter(request, &parameter, &timeline.parameters, requests)
+    } else {
+        let (from_ms, count) = get_request_params(request.url());
+        let sent = frames_from(frames, requests, from_ms, count)?;
+        respond_with_frames(request, &sent, &comparison.read().unwrap())
+    }
+}
+
+pub fn start_preview_server(
+    port: usize,
+    frames: HashMap<usize, String>,
+    timeline: Timeline,
+    export_size: ExportSize,
+) -> Result<()> {
+    PreviewServer::start(port, frames, timeline, export_size)?.wait()
+}
+
+/// `count` frames starting at `from_ms`, asking for them to be rendered if the server is lazy
+fn frames_from(
+    frames: &RwLock<HashMap<usize, String>>,
+    requests: &Option<mpsc::Sender<PreviewRequest>>,
+    from_ms: usize,
+    count: usize,
+) -> Result<HashMap<usize, String>> {
+    let Some(requests) = requests else {
+        return Ok(frames
+            .read()
+            .unwrap()
             .iter()
-            .filter(|(ms, _)| **ms >= frame_start_ms)
-            .take(requested_frames_count)
-            .map(|(ms, svg_string)| {
-                format!(
-                    r#"<div style="display: none;" id="frame-{}" class="frame">{}</div>"#,
-                    ms, svg_string
+            .filter(|(ms, _)| **ms >= from_ms)
+            .sorted_by_key(|(ms, _)| *ms)
+            .take(count)
+            .map(|(ms, svg)| (*ms, svg.clone()))
+            .collect());
+    };
+
+    let (respond_to, rendered) = mpsc::channel();
+    requests.send(PreviewRequest::Frames(FrameRequest {
+        from_ms,
+        count,
+        respond_to,
+    }))?;
+    Ok(rendered.recv()?)
+}
+
+/// The frame at `ms`, to download. Lazy servers have it rendered apart from the preview's frames, so that the preview does not lose its position.
+fn exported_frame(
+    frames: &RwLock<HashMap<usize, String>>,
+    requests: &Option<mpsc::Sender<PreviewRequest>>,
+    ms: usize,
+) -> Result<Option<(usize, String)>> {
+    let Some(requests) = requests else {
+        return Ok(frames_from(frames, &None, ms, 1)?.into_iter().next());
+    };
+
+    let (respond_to, rendered) = mpsc::channel();
+    requests.send(PreviewRequest::Export(FrameRequest {
+        from_ms: ms,
+        count: 1,
+        respond_to,
+    }))?;
+    Ok(rendered.recv()?.into_iter().next())
+}
+
+/// Responds to `/parameters/<name>?value=<value>`, clamping the value to the parameter's range. Only lazy servers can render frames again, others respond with 404, as they do for unknown parameters.
+fn set_parameter(
+    request: tiny_http::Request,
+    parameter: &str,
+    parameters: &[Parameter],
+    requests: &Option<mpsc::Sender<PreviewRequest>>,
+) -> Result<()> {
+    let (name, querystring) = parameter.split_once('?').unwrap_or((parameter, ""));
+    let value = querystring
+        .strip_prefix("value=")
+        .and_then(|value| value.parse::<f32>().ok())
+        .filter(|value| value.is_finite());
+    let parameter = parameters.iter().find(|parameter| parameter.name == name);
+
+    match (requests, parameter, value) {
+        (Some(requests), Some(parameter), Some(value)) => {
+            requests.send(PreviewRequest::SetParameter {
+                name: name.to_string(),
+                value: parameter.clamp(value),
+            })?;
+            request.respond(tiny_http::Response::empty(204).with_header(
+                tiny_http::Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap(),
+            ))?;
+        }
+        (None, _, _) | (_, None, _) => request.respond(tiny_http::Response::empty(404))?,
+        (_, _, None) => request.respond(tiny_http::Response::empty(400))?,
+    }
+    Ok(())
+}
+
+/// Responds to `/frame/<ms>.svg` and `/frame/<ms>.png` with the frame as a file download
+fn export_frame(
+    request: tiny_http::Request,
+    file: &str,
+    export_size: ExportSize,
+    frame_at: impl Fn(usize) -> Result<Option<(usize, String)>>,
+) -> Result<()> {
+    let Some((Ok(ms), extension)) = file
+        .split_once('.')
+        .map(|(ms, extension)| (ms.parse::<usize>(), extension))
+    else {
+        request.respond(tiny_http::Response::empty(400))?;
+        return Ok(());
+    };
+
+    let Some((ms, svg)) = frame_at(ms)? else {
+        request.respond(tiny_http::Response::empty(404))?;
+        return Ok(());
+    };
+
+    let (contents, content_type) = match extension {
+        "svg" => (svg.into_bytes(), "image/svg+xml"),
+        "png" => {
+            let png_path = std::env::temp_dir().join(format!("shapemaker-frame-{ms}.png"));
+            let converted = Canvas::save_as(
+                &png_path.to_string_lossy(),
+                export_size.aspect_ratio,
+                export_size.resolution,
+                svg,
+            )
+            .map_err(|e| anyhow::format_err!(e))
+            .and_then(|_| Ok(fs::read(&png_path)?));
+            let _ = fs::remove_file(&png_path);
+            match converted {
+                Ok(png) => (png, "image/png"),
+                Err(e) => {
+                    request.respond(
+                        tiny_http::Response::from_string(format!(
+                            "Could not convert to PNG: {e:#}"
+                        ))
+                        .with_status_code(500),
+                    )?;
+                    return Ok(());
+                }
+            }
+        }
+        _ => {
+            request.respond(tiny_http::Response::empty(404))?;
+            return Ok(());
+        }
+    };
+
+    request.respond(
+        tiny_http::Response::from_data(contents)
+            .with_header(tiny_http::Header::from_bytes("Content-Type", content_type).unwrap())
+            .with_header(
+                tiny_http::Header::from_bytes(
+                    "Content-Disposition",
+                    format!("attachment; filename=\"frame-{ms}.{extension}\""),
                 )
-            })
-            .join("\n");
+                .unwrap(),
+            )
+            .with_header(
+                tiny_http::Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap(),
+            ),
+    )?;
+    Ok(())
+}
+
+/// Most recent modification time of the files, recursively for directories
+fn latest_modification(paths: &[PathBuf]) -> Option<std::time::SystemTime> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            let metadata = fs::metadata(path).ok()?;
+            if metadata.is_dir() {
+                let children = fs::read_dir(path)
+                    .ok()?
+                    .filter_map(|entry| Some(entry.ok()?.path()))
+                    .collect_vec();
+                latest_modification(&children)
+            } else {
+                metadata.modified().ok()
+            }
+        })
+        .max()
+}
+
+fn accept_websocket(request: tiny_http::Request) -> Option<LiveSocket> {
+    let key = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+        .map(|header| derive_accept_key(header.value.as_bytes()))?;
+
+    let response = tiny_http::Response::empty(101)
+        .with_header(tiny_http::Header::from_bytes("Upgrade", "websocket").unwrap())
+        .with_header(tiny_http::Header::from_bytes("Connection", "Upgrade").unwrap())
+        .with_header(tiny_http::Header::from_bytes("Sec-WebSocket-Accept", key).unwrap());
+
+    let stream = request.upgrade("websocket", response);
+    Some(WebSocket::from_raw_socket(stream, Role::Server, None))
+}
+
+fn respond_with_frames(
+    request: tiny_http::Request,
+    frames: &HashMap<usize, String>,
+    comparison: &Option<Comparison>,
+) -> Result<()> {
+    let (frame_start_ms, requested_frames_count) = get_request_params(request.url());
+
+    println!(
+        "Request for {} frames @ {}ms",
+        requested_frames_count, frame_start_ms,
+    );
+
+    let sent_frames = frames
+        .iter()
+        .filter(|(ms, _)| **ms >= frame_start_ms)
+        .sorted_by_key(|(ms, _)| *ms)
+        .take(requested_frames_count)
+        .collect_vec();
+
+    let delta_encoded = wants_delta_encoding(request.url());
+    let contents = sent_frames
+        .iter()
+        .enumerate()
+        .map(|(i, (ms, svg_string))| {
+            let svg_string = match i {
+                1.. if delta_encoded => delta_encode(sent_frames[i - 1].1, svg_string),
+                _ => svg_string.to_string(),
+            };
+            let compared = comparison
+                .as_ref()
+                .and_then(|comparison| comparison.frame_at(**ms))
+                .map_or(String::new(), |svg| {
+                    format!(r#"<div class="compare">{}</div>"#, svg)
+                });
+            format!(
+                r#"<div style="display: none;" id="frame-{}" class="frame">{}{}</div>"#,
+                ms, svg_string, compared
+            )
+        })
+        .join("\n");
 
-        request.respond(tiny_http::Response::from_string(contents).with_header(
-            tiny_http::Header {
-                field: "Access-Control-Allow-Origin".parse().unwrap(),
-                value: "*".parse().unwrap(),
-            },
-        ))?;
+    respond(request, contents)
+}
+
+/// Replaces the layer groups that did not change since the previous frame with `<g data-unchanged="i">` placeholders, i being the index of the layer group in the previous frame. The preview puts them back.
+fn delta_encode(previous: &str, current: &str) -> String {
+    let (Ok(previous_document), Ok(current_document)) = (
+        roxmltree::Document::parse(previous),
+        roxmltree::Document::parse(current),
+    ) else {
+        return current.to_string();
+    };
+
+    let previous_layers: HashMap<&str, (usize, &str)> = layer_groups(&previous_document)
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            (
+                node.attribute("data-layer").unwrap_or_default(),
+                (i, &previous[node.range()]),
+            )
+        })
+        .collect();
+
+    let mut encoded = String::with_capacity(current.len());
+    let mut copied_until = 0;
+    for node in layer_groups(&current_document) {
+        let name = node.attribute("data-layer").unwrap_or_default();
+        let range = node.range();
+        if let Some((index, _)) = previous_layers
+            .get(name)
+            .filter(|(_, previous_layer)| *previous_layer == &current[range.clone()])
+        {
+            encoded.push_str(&current[copied_until..range.start]);
+            encoded.push_str(&format!(r#"<g data-unchanged="{}"></g>"#, index));
+            copied_until = range.end;
+        }
+    }
+    encoded.push_str(&current[copied_until..]);
+    encoded
+}
+
+/// Top-level layer groups of a rendered frame
+fn layer_groups<'a, 'input>(
+    document: &'a roxmltree::Document<'input>,
+) -> Vec<roxmltree::Node<'a, 'input>> {
+    document
+        .root_element()
+        .children()
+        .filter(|node| node.attribute("class") == Some("layer"))
+        .collect()
+}
+
+/// Responds gzipped if the client accepts it
+fn respond(request: tiny_http::Request, contents: String) -> Result<()> {
+    let accepts_gzip = request.headers().iter().any(|header| {
+        header.field.equiv("Accept-Encoding") && header.value.as_str().contains("gzip")
+    });
+
+    let cors = tiny_http::Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap();
+
+    if accepts_gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(contents.as_bytes())?;
+        request.respond(
+            tiny_http::Response::from_data(encoder.finish()?)
+                .with_header(cors)
+                .with_header(tiny_http::Header::from_bytes("Content-Encoding", "gzip").unwrap()),
+        )?;
+    } else {
+        request.respond(tiny_http::Response::from_string(contents).with_header(cors))?;
     }
     Ok(())
 }
 
+fn wants_delta_encoding(url: &str) -> bool {
+    url.split_once('?')
+        .is_some_and(|(_, querystring)| querystring.split('&').any(|pair| pair == "delta=1"))
+}
+
 // returns (ms timestamp of first frame to send, number of frames to send)
 fn get_request_params(url: &str) -> (usize, usize) {
     let mut first_frame_ms = 0;
@@ -110,3 +657,16 @@ fn get_request_params(url: &str) -> (usize, usize) {
 
     (first_frame_ms, num_frames)
 }
+
+#[test]
+fn test_delta_encode() {
+    let previous = r#"<svg><g class="layer" data-layer="a"><circle r="1"/></g><g class="layer" data-layer="b"><circle r="2"/></g></svg>"#;
+    let current = r#"<svg><g class="layer" data-layer="b"><circle r="2"/></g><g class="layer" data-layer="a"><circle r="3"/></g></svg>"#;
+    assert_eq!(
+        delta_encode(previous, current),
+        r#"<svg><g data-unchanged="1"></g><g class="layer" data-layer="a"><circle r="3"/></g></svg>"#
+    );
+    assert_eq!(delta_encode("not svg", current), current);
+    assert!(wants_delta_encoding("/frames?from=0&delta=1"));
+    assert!(!wants_delta_encoding("/frames?delta=10"));
+}
@@ -0,0 +1,52 @@
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use rand::{rngs::StdRng, Rng as _, RngCore, SeedableRng};
+
+static RNG: Lazy<Mutex<StdRng>> = Lazy::new(|| Mutex::new(StdRng::from_entropy()));
+
+/// Held by tests that need the generator to themselves, since tests run in parallel
+#[cfg(test)]
+pub(crate) static TEST_RNG_LOCK: Mutex<()> = Mutex::new(());
+
+/// Make all random generation reproducible from now on: the same seed gives the same artwork
+pub fn set_seed(seed: u64) {
+    *RNG.lock().unwrap() = StdRng::seed_from_u64(seed);
+}
+
+/// The random number generator all of shapemaker's randomness goes through, instead of `rand::thread_rng()`. See [`set_seed`].
+pub fn rng() -> SeededRng {
+    SeededRng
+}
+
+/// Whether an event of the given probability happens. Unlike `Rng::gen_bool`, probabilities outside of [0, 1] are clamped instead of panicking, and NaN counts as 0.
+pub fn chance(probability: f64) -> bool {
+    let probability = if probability.is_nan() {
+        0.0
+    } else {
+        probability.clamp(0.0, 1.0)
+    };
+    rng().gen_bool(probability)
+}
+
+/// Handle to the shared generator, see [`rng`]
+#[derive(Debug, Clone, Copy)]
+pub struct SeededRng;
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.lock().unwrap().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RNG.lock().unwrap().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RNG.lock().unwrap().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        RNG.lock().unwrap().try_fill_bytes(dest)
+    }
+}
@@ -1,11 +1,13 @@
-use crate::{Object, Point};
+use crate::{random::rng, Object, Point};
 use anyhow::{format_err, Error, Result};
 use backtrace::Backtrace;
+use itertools::Itertools;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
-#[derive(Debug, Clone, Default, Copy)]
+#[derive(Debug, Clone, Default, Copy, Serialize, Deserialize)]
 pub struct Region {
     pub start: Point,
     pub end: Point,
@@ -25,6 +27,95 @@ impl Region {
         self.iter().filter(|Point(x, y)| x >= y)
     }
 
+    /// iterates over the cells on the edges of the region, in row-major order
+    pub fn iter_border(&self) -> impl Iterator<Item = Point> {
+        let region = *self;
+        self.iter().filter(move |Point(x, y)| {
+            *x == region.start.0 || *x == region.end.0 || *y == region.start.1 || *y == region.end.1
+        })
+    }
+
+    /// Points at `radius` cells (rounded) from `center`, ordered clockwise starting from the right.
+    /// Points that would have negative coordinates are left out.
+    pub fn ring(center: Point, radius: usize) -> Vec<Point> {
+        let (cx, cy) = (center.0 as i32, center.1 as i32);
+        let r = radius as i32;
+        let angle = |&Point(x, y): &Point| {
+            let angle = (y as f32 - cy as f32).atan2(x as f32 - cx as f32);
+            angle.rem_euclid(std::f32::consts::TAU)
+        };
+
+        (-r..=r)
+            .cartesian_product(-r..=r)
+            .filter(|(dx, dy)| ((dx * dx + dy * dy) as f32).sqrt().round() as i32 == r)
+            .filter(|(dx, dy)| cx + dx >= 0 && cy + dy >= 0)
+            .map(|(dx, dy)| Point((cx + dx) as usize, (cy + dy) as usize))
+            .sorted_by(|a, b| angle(a).total_cmp(&angle(b)))
+            .collect()
+    }
+
+    pub fn intersection(&self, other: &Region) -> MultiRegion {
+        MultiRegion {
+            regions: self.intersection_region(other).into_iter().collect(),
+        }
+    }
+
+    pub fn union(&self, other: &Region) -> MultiRegion {
+        MultiRegion::from(*self).union(other)
+    }
+
+    /// whether the two regions share at least one cell
+    pub fn overlaps(&self, other: &Region) -> bool {
+        self.intersection_region(other).is_some()
+    }
+
+    /// cells of this region that are not in the other one
+    pub fn difference(&self, other: &Region) -> MultiRegion {
+        let Some(inter) = self.intersection_region(other) else {
+            return (*self).into();
+        };
+
+        let mut regions = vec![];
+        if inter.start.1 > self.start.1 {
+            regions.push(Region {
+                start: self.start,
+                end: Point(self.end.0, inter.start.1 - 1),
+            });
+        }
+        if inter.end.1 < self.end.1 {
+            regions.push(Region {
+                start: Point(self.start.0, inter.end.1 + 1),
+                end: self.end,
+            });
+        }
+        if inter.start.0 > self.start.0 {
+            regions.push(Region {
+                start: Point(self.start.0, inter.start.1),
+                end: Point(inter.start.0 - 1, inter.end.1),
+            });
+        }
+        if inter.end.0 < self.end.0 {
+            regions.push(Region {
+                start: Point(inter.end.0 + 1, inter.start.1),
+                end: Point(self.end.0, inter.end.1),
+            });
+        }
+
+        MultiRegion { regions }
+    }
+
+    fn intersection_region(&self, other: &Region) -> Option<Region> {
+        Region {
+            start: Point(
+                self.start.0.max(other.start.0),
+                self.start.1.max(other.start.1),
+            ),
+            end: Point(self.end.0.min(other.end.0), self.end.1.min(other.end.1)),
+        }
+        .ensure_valid()
+        .ok()
+    }
+
     pub fn random_point_within(&self) -> Point {
         Point::from(self.random_coordinates_within())
     }
@@ -52,6 +143,79 @@ impl Region {
     }
 }
 
+/// A set of cells made of several non-overlapping regions, as returned by boolean operations on regions
+#[derive(Debug, Clone, Default)]
+pub struct MultiRegion {
+    pub regions: Vec<Region>,
+}
+
+impl MultiRegion {
+    /// iterates over every cell, in row-major order
+    pub fn iter(&self) -> impl Iterator<Item = Point> {
+        self.regions
+            .iter()
+            .flat_map(|region| region.iter())
+            .sorted_by_key(|Point(x, y)| (*y, *x))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// number of cells
+    pub fn len(&self) -> usize {
+        self.regions.iter().map(|r| r.width() * r.height()).sum()
+    }
+
+    pub fn union(&self, other: &Region) -> MultiRegion {
+        let mut result = self.clone();
+        // only add the parts of other that are not already covered, to keep regions disjoint
+        result.regions.extend(
+            self.regions
+                .iter()
+                .fold(MultiRegion::from(*other), |remaining, region| {
+                    remaining.difference(region)
+                })
+                .regions,
+        );
+        result
+    }
+
+    pub fn intersection(&self, other: &Region) -> MultiRegion {
+        MultiRegion {
+            regions: self
+                .regions
+                .iter()
+                .flat_map(|region| region.intersection(other).regions)
+                .collect(),
+        }
+    }
+
+    pub fn difference(&self, other: &Region) -> MultiRegion {
+        MultiRegion {
+            regions: self
+                .regions
+                .iter()
+                .flat_map(|region| region.difference(other).regions)
+                .collect(),
+        }
+    }
+}
+
+impl From<Region> for MultiRegion {
+    fn from(region: Region) -> Self {
+        Self {
+            regions: vec![region],
+        }
+    }
+}
+
+impl Containable<Point> for MultiRegion {
+    fn contains(&self, value: &Point) -> bool {
+        self.regions.iter().any(|region| region.contains(value))
+    }
+}
+
 pub struct RegionIterator {
     region: Region,
     current: Point,
@@ -121,6 +285,17 @@ impl std::ops::Sub for Region {
     }
 }
 
+#[test]
+fn test_difference_and_union_cover_the_same_cells() {
+    let whole = Region::new(0, 0, 5, 5).unwrap();
+    let hole = Region::new(2, 1, 3, 4).unwrap();
+    let difference = whole.difference(&hole);
+
+    assert_eq!(difference.len(), 36 - 8);
+    assert!(!difference.iter().any(|point| hole.contains(&point)));
+    assert_eq!(difference.union(&hole).len(), 36);
+}
+
 #[test]
 fn test_sub_and_transate_coherence() {
     let a = Region::from_origin(Point(3, 3)).unwrap();
@@ -176,8 +351,8 @@ impl Region {
 
     pub fn random_coordinates_within(&self) -> (i32, i32) {
         (
-            rand::thread_rng().gen_range(self.start.0..self.end.0) as i32,
-            rand::thread_rng().gen_range(self.start.1..self.end.1) as i32,
+            rng().gen_range(self.start.0..self.end.0) as i32,
+            rng().gen_range(self.start.1..self.end.1) as i32,
         )
     }
 
@@ -345,6 +520,12 @@ impl Containable<Point> for Region {
     }
 }
 
+impl Containable<Region> for Region {
+    fn contains(&self, value: &Region) -> bool {
+        value.within(self)
+    }
+}
+
 impl std::fmt::Display for Region {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "[{},{}]", self.start, self.end)
@@ -0,0 +1,576 @@
+//! Scene files: the initial canvas and the hooks of a video, described in YAML or JSON instead of Rust.
+//! See `shapemaker video --scene`.
+//!
+//! ```yaml
+//! canvas:
+//!   grid: 3x3
+//!   background: black
+//!   layers:
+//!     - name: shapes
+//!       objects: random
+//!       color: random
+//! hooks:
+//!   - on: beat
+//!     do:
+//!       - regenerate: shapes
+//!   - on: { marker: chorus }
+//!     do:
+//!       - glow: { layer: shapes, intensity: 4 }
+//!   - on: { stem: { name: kick, threshold: 0.7 } }
+//!     do:
+//!       - paint: { layer: shapes, color: random }
+//! ```
+use std::{ops::Range, path::Path};
+
+use anyhow::{format_err, Context as _, Result};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{
+    generators::subdivision::Subdivision, palettes, random::rng, Canvas, ColorMapping, Context,
+    Fill, Filter, Layer, RenderFunction, Video,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct Scene {
+    #[serde(default)]
+    pub canvas: CanvasSetup,
+    #[serde(default)]
+    pub hooks: Vec<SceneHook>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CanvasSetup {
+    /// In the form WIDTHxHEIGHT, like --grid-size
+    pub grid: Option<String>,
+    /// Color name or CSS color
+    pub background: Option<String>,
+    /// Name of a preset palette, like --palette
+    pub palette: Option<String>,
+    /// Generated in order, the first one ends up on top
+    pub layers: Vec<LayerSetup>,
+}
+
+/// A layer made by one of the built-in generators, or by a plugin's
+#[derive(Debug, Clone, Deserialize)]
+pub struct LayerSetup {
+    pub name: String,
+    #[serde(default)]
+    pub generator: Generator,
+    /// Number of objects (or of cells, for voronoi). Defaults to random, which uses the canvas' --objects-count.
+    /// Subdivision layers are split until they stop, so they can't have a number of objects.
+    pub objects: Option<Count>,
+    /// Color name, or random to give each object its own color. Defaults to the generator's own choice of fills.
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Generator {
+    #[default]
+    Shapes,
+    Linelikes,
+    Voronoi,
+    Subdivision,
+    /// Generator of a plugin loaded with --plugins, see the plugins module
+    Plugin(String),
+}
+
+/// A number, an inclusive range like 2..5, or random
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Count {
+    Exactly(usize),
+    Text(String),
+}
+
+impl Default for Count {
+    fn default() -> Self {
+        Count::Text("random".into())
+    }
+}
+
+impl Count {
+    /// The (exclusive) range to pick a number in, never empty
+    fn range(&self, canvas: &Canvas) -> Result<Range<usize>> {
+        let range = match self {
+            Count::Exactly(count) => *count..count + 1,
+            Count::Text(text) if text == "random" => canvas.objects_count_range.clone(),
+            Count::Text(text) => {
+                let (min, max) = text
+                    .split_once("..")
+                    .ok_or_else(|| format_err!("{text} is not a number, a range or random"))?;
+                let (min, max): (usize, usize) = (min.trim().parse()?, max.trim().parse()?);
+                if min > max {
+                    return Err(format_err!(
+                        "{text} is not a valid range: {min} is greater than {max}"
+                    ));
+                }
+                min..max + 1
+            }
+        };
+        if range.is_empty() {
+            return Err(format_err!("Cannot pick a number of objects in {range:?}"));
+        }
+        Ok(range)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SceneHook {
+    pub on: Trigger,
+    #[serde(rename = "do")]
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Trigger {
+    Beat,
+    Frame,
+    /// Marker text, see `Video::on`
+    Marker(String),
+    /// Comma-separated stem names, see `Video::on_note`
+    Note(String),
+    /// Runs on every frame where the stem's relative amplitude is above the threshold
+    Stem {
+        name: String,
+        threshold: f32,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Generate a layer of the canvas setup again, by name
+    Regenerate(String),
+    Generate(LayerSetup),
+    Paint {
+        layer: String,
+        /// Color name, or random to give each object its own color
+        color: String,
+        opacity: Option<f32>,
+    },
+    Glow {
+        layer: String,
+        intensity: f32,
+    },
+    /// Animates the layer's opacity, see `Context::fade_layer`
+    Fade {
+        layer: String,
+        duration: usize,
+        from: f32,
+        to: f32,
+    },
+    Hide(String),
+    Show(String),
+    Toggle(String),
+    /// Removes all of the layer's objects
+    Clear(String),
+    /// Name of a preset palette
+    Palette(String),
+    /// Hook of a plugin loaded with --plugins, see the plugins module
+    Plugin(String),
+}
+
+impl Scene {
+    /// Parses a .yaml, .yml or .json scene file
+    pub fn from_file(path: &str) -> Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Could not read {path}"))?;
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&contents),
+            Some("json") => Self::from_json(&contents),
+            _ => Err(format_err!("{path} should be a .yaml, .yml or .json file")),
+        }
+    }
+
+    pub fn from_yaml(contents: &str) -> Result<Self> {
+        // Going through JSON values, so that actions and triggers are written the same way in both formats.
+        // serde_yaml would otherwise expect YAML tags (!paint) instead of single-key maps for them.
+        Ok(serde_json::from_value(serde_yaml::from_str(contents)?)?)
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self> {
+        Ok(serde_json::from_str(contents)?)
+    }
+
+    /// Sets up the video's initial canvas and adds the scene's hooks.
+    /// The scene is leaked, since hooks live as long as the video.
+    pub fn apply<C: Default + 'static>(self, video: Video<C>) -> Result<Video<C>> {
+        let scene: &'static Scene = Box::leak(Box::new(self));
+        scene.check_layer_setups()?;
+
+        let mut video = video;
+        scene.canvas.setup(&mut video.initial_canvas)?;
+        scene.check_layer_references(&video.initial_canvas)?;
+
+        for hook in &scene.hooks {
+            let actions = &hook.actions;
+            let run: &'static RenderFunction<C> = Box::leak(Box::new(
+                move |canvas: &mut Canvas, context: &mut Context<C>| {
+                    for action in actions {
+                        action.run(&scene.canvas, canvas, context)?;
+                    }
+                    Ok(())
+                },
+            ));
+            video = match &hook.on {
+                Trigger::Beat => video.each_beat(run),
+                Trigger::Frame => video.each_frame(run),
+                Trigger::Marker(marker) => video.on(marker, run),
+                Trigger::Note(stems) => video.on_note(stems, run),
+                Trigger::Stem { name, threshold } => {
+                    video.on_stem(name, *threshold, run, &|_, _| Ok(()))
+                }
+            };
+        }
+
+        Ok(video)
+    }
+
+    fn actions(&self) -> impl Iterator<Item = &Action> {
+        self.hooks.iter().flat_map(|hook| &hook.actions)
+    }
+
+    fn check_layer_setups(&self) -> Result<()> {
+        let generated = self.actions().filter_map(|action| match action {
+            Action::Generate(layer) => Some(layer),
+            _ => None,
+        });
+        for layer in self.canvas.layers.iter().chain(generated) {
+            if let (Generator::Subdivision, Some(_)) = (&layer.generator, &layer.objects) {
+                return Err(format_err!(
+                    "{}: subdivision layers are split until they stop, they cannot have a number of objects",
+                    layer.name
+                ));
+            }
+            if let Some(objects) = &layer.objects {
+                objects
+                    .range(&Canvas::default_settings())
+                    .with_context(|| format!("Invalid number of objects for {}", layer.name))?;
+            }
+            if let Generator::Plugin(generator) = &layer.generator {
+                ensure_plugin_provides("generator", generator)?;
+            }
+        }
+        for action in self.actions() {
+            if let Action::Plugin(hook) = action {
+                ensure_plugin_provides("hook", hook)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Layers that actions refer to should exist in the initial canvas, or be generated by another action.
+    /// Checked before rendering, so that a typo in a scene file does not stop the render halfway.
+    fn check_layer_references(&self, canvas: &Canvas) -> Result<()> {
+        let exists = |name: &str| {
+            canvas.layer_exists(name)
+                || self
+                    .actions()
+                    .any(|action| matches!(action, Action::Generate(layer) if layer.name == name))
+        };
+        for action in self.actions() {
+            if let Action::Regenerate(name) = action {
+                if !self.canvas.layers.iter().any(|layer| &layer.name == name) {
+                    return Err(format_err!(
+                        "Cannot regenerate {name}: it is not one of the canvas' layers"
+                    ));
+                }
+            }
+            if let Some(layer) = action.layer().filter(|layer| !exists(layer)) {
+                return Err(format_err!(
+                    "Unknown layer {layer}: it is neither one of the canvas' layers nor generated by a hook"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CanvasSetup {
+    fn setup(&self, canvas: &mut Canvas) -> Result<()> {
+        if let Some(grid) = &self.grid {
+            let (width, height) = grid.split_once('x').ok_or_else(|| {
+                format_err!("grid should be in the form WIDTHxHEIGHT, not {grid}")
+            })?;
+            canvas.set_grid_size(width.trim().parse()?, height.trim().parse()?);
+        }
+        if let Some(background) = &self.background {
+            canvas.set_background(canvas.colormap.color(background)?);
+        }
+        if let Some(palette) = &self.palette {
+            canvas.colormap = palette_colormap(palette)?;
+        }
+        for layer in &self.layers {
+            let generated = layer.generate(canvas)?;
+            canvas.add_or_replace_layer(generated);
+        }
+        Ok(())
+    }
+}
+
+impl LayerSetup {
+    fn generate(&self, canvas: &Canvas) -> Result<Layer> {
+        let count = rng().gen_range(self.objects.clone().unwrap_or_default().range(canvas)?);
+        let mut layer = match &self.generator {
+            Generator::Shapes => {
+                let mut options = canvas.random_layer_options();
+                options.objects_count_range = count..count + 1;
+                canvas.random_layer_with(&self.name, &options)
+            }
+            Generator::Linelikes => {
+                canvas.n_random_linelikes_within(&self.name, &canvas.world_region, count)
+            }
+            Generator::Voronoi => canvas.random_voronoi_layer(&self.name, count),
+            Generator::Subdivision => {
+                Subdivision::default().layer(&self.name, &canvas.world_region)
+            }
+            Generator::Plugin(generator) => plugin_layer(canvas, generator, &self.name, count)?,
+        };
+        if let Some(color) = &self.color {
+            paint(canvas, &mut layer, color, None)?;
+        }
+        Ok(layer)
+    }
+}
+
+impl Action {
+    /// Name of the existing layer the action works on
+    fn layer(&self) -> Option<&str> {
+        match self {
+            Action::Paint { layer, .. }
+            | Action::Glow { layer, .. }
+            | Action::Fade { layer, .. }
+            | Action::Hide(layer)
+            | Action::Show(layer)
+            | Action::Toggle(layer)
+            | Action::Clear(layer) => Some(layer),
+            Action::Regenerate(_)
+            | Action::Generate(_)
+            | Action::Palette(_)
+            | Action::Plugin(_) => None,
+        }
+    }
+
+    fn run<C>(
+        &'static self,
+        setup: &CanvasSetup,
+        canvas: &mut Canvas,
+        context: &mut Context<C>,
+    ) -> Result<()> {
+        // layers generated by hooks might not be generated yet
+        if let Some(layer) = self.layer().filter(|layer| !canvas.layer_exists(layer)) {
+            return Err(format_err!("Layer {layer} does not exist yet"));
+        }
+
+        match self {
+            Action::Regenerate(name) => {
+                let layer = setup
+                    .layers
+                    .iter()
+                    .find(|layer| &layer.name == name)
+                    .expect("layer references are checked when applying the scene");
+                let generated = layer.generate(canvas)?;
+                canvas.add_or_replace_layer(generated);
+            }
+            Action::Generate(layer) => {
+                let generated = layer.generate(canvas)?;
+                canvas.add_or_replace_layer(generated);
+            }
+            Action::Paint {
+                layer,
+                color,
+                opacity,
+            } => {
+                let mut painted = canvas.layer(layer).clone();
+                paint(canvas, &mut painted, color, *opacity)?;
+                canvas.layer(layer).replace(painted);
+            }
+            Action::Glow { layer, intensity } => {
+                canvas
+                    .layer(layer)
+                    .filter_all_objects(Filter::glow(*intensity));
+            }
+            Action::Fade {
+                layer,
+                duration,
+                from,
+                to,
+            } => context.fade_layer(layer, *duration, *from, *to),
+            Action::Hide(layer) => canvas.layer(layer).hide(),
+            Action::Show(layer) => canvas.layer(layer).show(),
+            Action::Toggle(layer) => canvas.layer(layer).toggle(),
+            Action::Clear(layer) => {
+                canvas.layer(layer).objects.clear();
+                canvas.layer(layer).flush();
+            }
+            Action::Palette(name) => canvas.colormap = palette_colormap(name)?,
+            Action::Plugin(hook) => run_plugin_hook(hook, canvas, context)?,
+        }
+        Ok(())
+    }
+}
+
+/// Fills all of the layer's objects with the color, or each with a random one if color is random
+fn paint(canvas: &Canvas, layer: &mut Layer, color: &str, opacity: Option<f32>) -> Result<()> {
+    for object in layer.objects.values_mut() {
+        let color = match color {
+            "random" => canvas.random_color(),
+            name => canvas.colormap.color(name)?,
+        };
+        object.fill = Some(match opacity {
+            Some(opacity) => Fill::Translucent(color, opacity),
+            None => Fill::Solid(color),
+        });
+    }
+    layer.flush();
+    Ok(())
+}
+
+#[cfg(feature = "plugins")]
+fn ensure_plugin_provides(what: &str, name: &str) -> Result<()> {
+    let plugins = crate::plugins::loaded();
+    let provided = match what {
+        "generator" => plugins.provides_generator(name),
+        _ => plugins.provides_hook(name),
+    };
+    if provided {
+        Ok(())
+    } else {
+        Err(format_err!("No loaded plugin provides the {what} {name}"))
+    }
+}
+
+#[cfg(feature = "plugins")]
+fn plugin_layer(canvas: &Canvas, generator: &str, name: &str, objects: usize) -> Result<Layer> {
+    crate::plugins::loaded().generate(canvas, generator, name, &canvas.world_region, objects)
+}
+
+#[cfg(feature = "plugins")]
+fn run_plugin_hook<C>(hook: &str, canvas: &mut Canvas, context: &Context<C>) -> Result<()> {
+    crate::plugins::loaded().run_hook(hook, canvas, context)
+}
+
+#[cfg(not(feature = "plugins"))]
+fn without_plugins_feature(what: &str, name: &str) -> anyhow::Error {
+    format_err!(
+        "Cannot use the plugin {what} {name}: shapemaker was built without the plugins feature"
+    )
+}
+
+#[cfg(not(feature = "plugins"))]
+fn ensure_plugin_provides(what: &str, name: &str) -> Result<()> {
+    Err(without_plugins_feature(what, name))
+}
+
+#[cfg(not(feature = "plugins"))]
+fn plugin_layer(_: &Canvas, generator: &str, _: &str, _: usize) -> Result<Layer> {
+    Err(without_plugins_feature("generator", generator))
+}
+
+#[cfg(not(feature = "plugins"))]
+fn run_plugin_hook<C>(hook: &str, _: &mut Canvas, _: &Context<C>) -> Result<()> {
+    Err(without_plugins_feature("hook", hook))
+}
+
+fn palette_colormap(name: &str) -> Result<ColorMapping> {
+    palettes::by_name(name)
+        .map(|palette| palette.colormap())
+        .ok_or_else(|| format_err!("Unknown palette: {name}"))
+}
+
+#[test]
+fn test_count_range() {
+    let canvas = Canvas::default_settings();
+    assert_eq!(Count::Exactly(4).range(&canvas).unwrap(), 4..5);
+    assert_eq!(Count::Text("2..5".into()).range(&canvas).unwrap(), 2..6);
+    assert_eq!(Count::Text(" 3 .. 3 ".into()).range(&canvas).unwrap(), 3..4);
+    assert_eq!(
+        Count::Text("random".into()).range(&canvas).unwrap(),
+        canvas.objects_count_range
+    );
+    assert!(Count::Text("5..2".into()).range(&canvas).is_err());
+    assert!(Count::Text("many".into()).range(&canvas).is_err());
+    assert!(Count::Text("1..x".into()).range(&canvas).is_err());
+}
+
+#[test]
+fn test_yaml_and_json_scenes_are_written_the_same_way() {
+    let yaml = Scene::from_yaml(
+        "
+canvas:
+  grid: 3x3
+  layers:
+    - name: shapes
+      objects: 2..4
+      color: random
+hooks:
+  - on: beat
+    do:
+      - regenerate: shapes
+  - on: { stem: { name: kick, threshold: 0.7 } }
+    do:
+      - paint: { layer: shapes, color: red, opacity: 0.5 }
+      - hide: shapes
+",
+    )
+    .unwrap();
+    let json = Scene::from_json(
+        r#"{
+            "canvas": { "grid": "3x3", "layers": [{ "name": "shapes", "objects": "2..4", "color": "random" }] },
+            "hooks": [
+                { "on": "beat", "do": [{ "regenerate": "shapes" }] },
+                {
+                    "on": { "stem": { "name": "kick", "threshold": 0.7 } },
+                    "do": [{ "paint": { "layer": "shapes", "color": "red", "opacity": 0.5 } }, { "hide": "shapes" }]
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    for scene in [yaml, json] {
+        assert_eq!(scene.canvas.grid.as_deref(), Some("3x3"));
+        assert_eq!(scene.canvas.layers[0].name, "shapes");
+        assert!(
+            matches!(&scene.canvas.layers[0].objects, Some(Count::Text(range)) if range == "2..4")
+        );
+        assert!(matches!(scene.hooks[0].on, Trigger::Beat));
+        assert!(
+            matches!(&scene.hooks[1].on, Trigger::Stem { name, threshold } if name == "kick" && *threshold == 0.7)
+        );
+        assert!(matches!(
+            &scene.hooks[1].actions[..],
+            [Action::Paint { layer, color, opacity: Some(_) }, Action::Hide(hidden)] if layer == "shapes" && color == "red" && hidden == "shapes"
+        ));
+    }
+}
+
+#[test]
+fn test_scene_layer_references_are_checked() {
+    let scene = Scene::from_yaml(
+        "
+hooks:
+  - on: { marker: drop }
+    do:
+      - generate: { name: sparks, generator: linelikes }
+  - on: beat
+    do:
+      - glow: { layer: sparks, intensity: 2 }
+",
+    )
+    .unwrap();
+    assert!(scene.check_layer_references(&Canvas::new(vec![])).is_ok());
+
+    let scene = Scene::from_yaml("hooks: [{ on: frame, do: [{ toggle: typo }] }]").unwrap();
+    assert!(scene.check_layer_references(&Canvas::new(vec![])).is_err());
+
+    let scene = Scene::from_yaml(
+        "canvas: { layers: [{ name: grid, generator: subdivision, objects: 3 }] }",
+    )
+    .unwrap();
+    assert!(scene.check_layer_setups().is_err());
+}
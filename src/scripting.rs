@@ -0,0 +1,340 @@
+//! Hooks written in Rhai scripts, reloaded whenever the script file changes, so that sync logic can be tweaked without recompiling.
+//! Only available with the `scripting` feature.
+//!
+//! Scripts get two variables:
+//! - `canvas`, with methods `add(layer, name, object)`, `add(layer, name, object, fill)`, `remove(name)`, `random_layer(name)`, `random_linelikes(name)`,
+//!   `paint(layer, fill)`, `glow(layer, intensity)`, `hide(layer)`, `show(layer)`, `toggle(layer)`, `clear(layer)`, `set_background(color)`, `random_point()` and `random_color()`
+//! - `ctx`, with properties `ms`, `frame`, `beat`, `beat_fractional`, `bpm` and `marker`, and methods `amplitude(stem)` (relative, from 0 to 1), `note(stem)` (whether a note starts) and `param(name)`
+//!
+//! Objects are made with `point(x, y)` and `big_circle(point)`, `small_circle(point)`, `dot(point)`, `line(start, end)`, `curve_outward(start, end)`, `curve_inward(start, end)`, `rectangle(start, end)`, `text(point, content, size)`.
+//! Fills are made with `solid(color)`, `translucent(color, opacity)` and `hatched(color, angle, size, thickness_ratio)`, where colors are names like "red" or CSS colors.
+//! For hatches, `size` is the size of a single hatch pattern tile and `thickness_ratio` the proportion of it that is colored, from 0 to 1.
+//!
+//! ```rhai
+//! if ctx.amplitude("kick") > 0.5 {
+//!     canvas.add("root", "flash", big_circle(point(1, 1)), solid("white"));
+//! } else {
+//!     canvas.remove("flash");
+//! }
+//! ```
+use std::{cell::RefCell, collections::HashMap, path::PathBuf, time::SystemTime};
+
+use anyhow::{format_err, Result};
+use rhai::{Engine, EvalAltResult, Scope, AST, FLOAT, INT};
+
+use crate::{Angle, Canvas, Color, Context, Fill, Filter, Object, Point, RenderFunction};
+
+/// Line width of lines and curves made by scripts
+const LINE_WIDTH: f32 = 2.0;
+
+/// What scripts can read from the hook's context
+#[derive(Debug, Clone)]
+pub struct ScriptContext {
+    pub ms: INT,
+    pub frame: INT,
+    pub beat: INT,
+    pub beat_fractional: FLOAT,
+    pub bpm: INT,
+    pub marker: String,
+    amplitudes: HashMap<String, FLOAT>,
+    notes: Vec<String>,
+    params: HashMap<String, FLOAT>,
+}
+
+impl ScriptContext {
+    fn new<C>(context: &Context<C>) -> Self {
+        let stem_names = context
+            .syncdata
+            .stems
+            .keys()
+            .chain(context.live_stems.keys());
+        Self {
+            ms: context.ms as INT,
+            frame: context.frame as INT,
+            beat: context.beat as INT,
+            beat_fractional: context.beat_fractional as FLOAT,
+            bpm: context.bpm as INT,
+            marker: context.marker(),
+            amplitudes: stem_names
+                .clone()
+                .map(|name| {
+                    (
+                        name.clone(),
+                        context.stem(name).amplitude_relative() as FLOAT,
+                    )
+                })
+                .collect(),
+            notes: stem_names
+                .filter(|name| context.stem(name).notes.iter().any(|note| note.is_on()))
+                .cloned()
+                .collect(),
+            params: context
+                .params
+                .iter()
+                .map(|(name, value)| (name.clone(), *value as FLOAT))
+                .collect(),
+        }
+    }
+}
+
+/// Hook that runs the Rhai script at `path`, e.g. `video.each_beat(scripting::hook("beat.rhai"))`.
+/// The script is compiled again when its file is modified, and failing to compile fails the frame.
+pub fn hook<C>(path: &str) -> &'static RenderFunction<C> {
+    let script = Script {
+        path: PathBuf::from(path),
+        engine: engine(),
+        compiled: RefCell::new(None),
+    };
+    Box::leak(Box::new(
+        move |canvas: &mut Canvas, context: &mut Context<C>| {
+            script.run(canvas, ScriptContext::new(context))
+        },
+    ))
+}
+
+struct Script {
+    path: PathBuf,
+    engine: Engine,
+    /// Compiled script, with the modification time of the file it was compiled from
+    compiled: RefCell<Option<(SystemTime, AST)>>,
+}
+
+impl Script {
+    fn run(&self, canvas: &mut Canvas, context: ScriptContext) -> Result<()> {
+        let modified = std::fs::metadata(&self.path)?.modified()?;
+        let up_to_date =
+            matches!(&*self.compiled.borrow(), Some((compiled_at, _)) if *compiled_at == modified);
+        if !up_to_date {
+            let ast = self
+                .engine
+                .compile_file(self.path.clone())
+                .map_err(|e| format_err!("{}: {}", self.path.display(), e))?;
+            *self.compiled.borrow_mut() = Some((modified, ast));
+        }
+
+        let mut scope = Scope::new();
+        scope.push("canvas", std::mem::replace(canvas, Canvas::new(vec![])));
+        scope.push("ctx", context);
+        let result = self.engine.run_ast_with_scope(
+            &mut scope,
+            &self
+                .compiled
+                .borrow()
+                .as_ref()
+                .expect("script was just compiled")
+                .1,
+        );
+        // `let canvas = ...` shadows our canvas instead of replacing it, so skip over the script's own variables
+        let mut restored = None;
+        while restored.is_none() && scope.contains("canvas") {
+            restored = scope.remove::<Canvas>("canvas");
+        }
+        *canvas = restored.ok_or_else(|| {
+            format_err!(
+                "{}: the canvas variable was assigned something that is not a canvas",
+                self.path.display()
+            )
+        })?;
+        result.map_err(|e| format_err!("{}: {}", self.path.display(), e))
+    }
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+
+    engine
+        .register_type_with_name::<ScriptContext>("Context")
+        .register_get("ms", |ctx: &mut ScriptContext| ctx.ms)
+        .register_get("frame", |ctx: &mut ScriptContext| ctx.frame)
+        .register_get("beat", |ctx: &mut ScriptContext| ctx.beat)
+        .register_get("beat_fractional", |ctx: &mut ScriptContext| {
+            ctx.beat_fractional
+        })
+        .register_get("bpm", |ctx: &mut ScriptContext| ctx.bpm)
+        .register_get("marker", |ctx: &mut ScriptContext| ctx.marker.clone())
+        .register_fn(
+            "amplitude",
+            |ctx: &mut ScriptContext, stem: &str| -> ScriptResult<FLOAT> {
+                ctx.amplitudes
+                    .get(stem)
+                    .copied()
+                    .ok_or_else(|| format!("No stem named {stem}").into())
+            },
+        )
+        .register_fn("note", |ctx: &mut ScriptContext, stem: &str| {
+            ctx.notes.iter().any(|name| name == stem)
+        })
+        .register_fn(
+            "param",
+            |ctx: &mut ScriptContext, name: &str| -> ScriptResult<FLOAT> {
+                ctx.params
+                    .get(name)
+                    .copied()
+                    .ok_or_else(|| format!("No parameter named {name}").into())
+            },
+        );
+
+    engine
+        .register_type_with_name::<Point>("Point")
+        .register_type_with_name::<Object>("Object")
+        .register_type_with_name::<Fill>("Fill")
+        .register_fn("point", |x: INT, y: INT| -> ScriptResult<Point> {
+            match (usize::try_from(x), usize::try_from(y)) {
+                (Ok(x), Ok(y)) => Ok(Point(x, y)),
+                _ => Err(format!("Invalid point ({x}, {y}): coordinates can't be negative").into()),
+            }
+        })
+        .register_fn("big_circle", Object::BigCircle)
+        .register_fn("small_circle", Object::SmallCircle)
+        .register_fn("dot", Object::Dot)
+        .register_fn("line", |start: Point, end: Point| {
+            Object::Line(start, end, LINE_WIDTH)
+        })
+        .register_fn("curve_outward", |start: Point, end: Point| {
+            Object::CurveOutward(start, end, LINE_WIDTH)
+        })
+        .register_fn("curve_inward", |start: Point, end: Point| {
+            Object::CurveInward(start, end, LINE_WIDTH)
+        })
+        .register_fn("rectangle", Object::Rectangle)
+        .register_fn("text", |at: Point, content: &str, size: FLOAT| {
+            Object::Text(at, content.to_string(), size as f32)
+        })
+        .register_fn("solid", |color: &str| -> ScriptResult<Fill> {
+            Ok(Fill::Solid(script_color(color)?))
+        })
+        .register_fn(
+            "translucent",
+            |color: &str, opacity: FLOAT| -> ScriptResult<Fill> {
+                Ok(Fill::Translucent(script_color(color)?, opacity as f32))
+            },
+        )
+        .register_fn(
+            "hatched",
+            |color: &str,
+             angle: FLOAT,
+             size: FLOAT,
+             thickness_ratio: FLOAT|
+             -> ScriptResult<Fill> {
+                Ok(Fill::Hatched(
+                    script_color(color)?,
+                    Angle(angle as f32),
+                    size as f32,
+                    thickness_ratio as f32,
+                ))
+            },
+        );
+
+    engine
+        .register_type_with_name::<Canvas>("Canvas")
+        .register_fn(
+            "add",
+            |canvas: &mut Canvas, layer: &str, name: &str, object: Object| -> ScriptResult<()> {
+                Ok(canvas.add_object(layer, name, object, None)?)
+            },
+        )
+        .register_fn(
+            "add",
+            |canvas: &mut Canvas,
+             layer: &str,
+             name: &str,
+             object: Object,
+             fill: Fill|
+             -> ScriptResult<()> {
+                Ok(canvas.add_object(layer, name, object, Some(fill))?)
+            },
+        )
+        .register_fn("remove", |canvas: &mut Canvas, name: &str| {
+            canvas.remove_object(name)
+        })
+        .register_fn("random_layer", |canvas: &mut Canvas, name: &str| {
+            let layer = canvas.random_layer(name);
+            canvas.add_or_replace_layer(layer);
+        })
+        .register_fn("random_linelikes", |canvas: &mut Canvas, name: &str| {
+            let layer = canvas.random_linelikes(name);
+            canvas.add_or_replace_layer(layer);
+        })
+        .register_fn(
+            "paint",
+            |canvas: &mut Canvas, layer: &str, fill: Fill| -> ScriptResult<()> {
+                existing_layer(canvas, layer)?;
+                canvas.layer(layer).paint_all_objects(fill);
+                Ok(())
+            },
+        )
+        .register_fn(
+            "glow",
+            |canvas: &mut Canvas, layer: &str, intensity: FLOAT| -> ScriptResult<()> {
+                existing_layer(canvas, layer)?;
+                canvas
+                    .layer(layer)
+                    .filter_all_objects(Filter::glow(intensity as f32));
+                Ok(())
+            },
+        )
+        .register_fn(
+            "hide",
+            |canvas: &mut Canvas, layer: &str| -> ScriptResult<()> {
+                existing_layer(canvas, layer)?;
+                canvas.layer(layer).hide();
+                Ok(())
+            },
+        )
+        .register_fn(
+            "show",
+            |canvas: &mut Canvas, layer: &str| -> ScriptResult<()> {
+                existing_layer(canvas, layer)?;
+                canvas.layer(layer).show();
+                Ok(())
+            },
+        )
+        .register_fn(
+            "toggle",
+            |canvas: &mut Canvas, layer: &str| -> ScriptResult<()> {
+                existing_layer(canvas, layer)?;
+                canvas.layer(layer).toggle();
+                Ok(())
+            },
+        )
+        .register_fn(
+            "clear",
+            |canvas: &mut Canvas, layer: &str| -> ScriptResult<()> {
+                existing_layer(canvas, layer)?;
+                canvas.layer(layer).objects.clear();
+                canvas.layer(layer).flush();
+                Ok(())
+            },
+        )
+        .register_fn(
+            "set_background",
+            |canvas: &mut Canvas, color: &str| -> ScriptResult<()> {
+                let color = canvas.colormap.color(color).map_err(|e| e.to_string())?;
+                canvas.set_background(color);
+                Ok(())
+            },
+        )
+        .register_fn("random_point", |canvas: &mut Canvas| {
+            canvas.random_point(&canvas.world_region)
+        })
+        .register_fn("random_color", |canvas: &mut Canvas| {
+            canvas.random_color().name()
+        });
+
+    engine
+}
+
+fn script_color(name: &str) -> ScriptResult<Color> {
+    Color::try_from(name).map_err(|e| e.to_string().into())
+}
+
+/// Errors instead of panicking like `Canvas::layer` does
+fn existing_layer(canvas: &Canvas, layer: &str) -> ScriptResult<()> {
+    if canvas.layer_exists(layer) {
+        Ok(())
+    } else {
+        Err(format!("Layer {layer} does not exist").into())
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StrokeStyle {
+    Solid,
+    /// Dash length, gap length
+    Dashed(f32, f32),
+    /// Round dots, spaced by twice the stroke width
+    Dotted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Stroke {
+    pub width: f32,
+    pub style: StrokeStyle,
+}
+
+impl Stroke {
+    pub fn solid(width: f32) -> Self {
+        Self {
+            width,
+            style: StrokeStyle::Solid,
+        }
+    }
+
+    pub fn dashed(width: f32, length: f32, gap: f32) -> Self {
+        Self {
+            width,
+            style: StrokeStyle::Dashed(length, gap),
+        }
+    }
+
+    pub fn dotted(width: f32) -> Self {
+        Self {
+            width,
+            style: StrokeStyle::Dotted,
+        }
+    }
+
+    pub fn render_css(&self) -> String {
+        let dasharray = match self.style {
+            StrokeStyle::Solid => String::new(),
+            StrokeStyle::Dashed(length, gap) => format!("stroke-dasharray: {} {};", length, gap),
+            StrokeStyle::Dotted => format!(
+                "stroke-dasharray: 0 {}; stroke-linecap: round;",
+                self.width * 2.0
+            ),
+        };
+
+        format!("stroke-width: {};{}", self.width, dasharray)
+    }
+}
+
+impl Default for Stroke {
+    fn default() -> Self {
+        Self::solid(0.5)
+    }
+}
@@ -16,4 +16,28 @@ pub struct SyncData {
     pub stems: HashMap<String, Stem>,
     pub markers: HashMap<TimestampMS, String>,
     pub bpm: usize,
+    /// Start of each beat, following the tempo changes. Empty if only the bpm is known.
+    #[serde(default)]
+    pub beats: Vec<TimestampMS>,
+}
+
+impl SyncData {
+    /// Start of each beat before duration_ms, evenly spaced according to the bpm if the tempo changes aren't known
+    pub fn beats_until(&self, duration_ms: TimestampMS) -> Vec<TimestampMS> {
+        if !self.beats.is_empty() {
+            return self
+                .beats
+                .iter()
+                .copied()
+                .take_while(|ms| *ms < duration_ms)
+                .collect();
+        }
+        if self.bpm == 0 {
+            return vec![];
+        }
+        (0..)
+            .map(|beat| beat * 60_000 / self.bpm)
+            .take_while(|ms| *ms < duration_ms)
+            .collect()
+    }
 }
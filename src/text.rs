@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthStr;
+
+/// Font used for every text object. Inconsolata is monospaced, so measuring text only requires counting columns.
+pub const FONT_FAMILY: &str = "Inconsolata";
+
+/// Advance width of a single Inconsolata glyph, in ems
+pub const CHARACTER_WIDTH_EM: f32 = 0.5;
+
+/// Ascender + descender of Inconsolata, in ems
+pub const LINE_HEIGHT_EM: f32 = 1.05;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum TextAlignment {
+    Start,
+    #[default]
+    Center,
+    End,
+}
+
+impl TextAlignment {
+    pub fn text_anchor(&self) -> &'static str {
+        match self {
+            TextAlignment::Start => "start",
+            TextAlignment::Center => "middle",
+            TextAlignment::End => "end",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ParagraphStyle {
+    /// Distance between baselines, in ems
+    pub line_height: f32,
+    pub alignment: TextAlignment,
+}
+
+impl Default for ParagraphStyle {
+    fn default() -> Self {
+        Self {
+            line_height: 1.2,
+            alignment: TextAlignment::Start,
+        }
+    }
+}
+
+/// Width of a single line of text, in the same unit as font_size. Wide (e.g. CJK) characters count as two columns.
+pub fn text_width(content: &str, font_size: f32) -> f32 {
+    content.width() as f32 * CHARACTER_WIDTH_EM * font_size
+}
+
+/// Largest font size at which content fits on a single line inside a width × height box
+pub fn fitting_font_size(content: &str, width: f32, height: f32) -> f32 {
+    let columns = content.width().max(1) as f32;
+    (width / (columns * CHARACTER_WIDTH_EM)).min(height / LINE_HEIGHT_EM)
+}
+
+/// Split content into lines no wider than width, breaking on whitespace (and inside words that are too long on their own).
+/// Line breaks already present in content are kept.
+pub fn wrap_text(content: &str, font_size: f32, width: f32) -> Vec<String> {
+    let max_columns = ((width / (CHARACTER_WIDTH_EM * font_size)).floor() as usize).max(1);
+    let mut lines = vec![];
+
+    for paragraph in content.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let separator = if line.is_empty() { "" } else { " " };
+            if line.width() + separator.len() + word.width() <= max_columns {
+                line += separator;
+                line += word;
+                continue;
+            }
+
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+
+            for character in word.chars() {
+                if line.width() + character.to_string().width() > max_columns {
+                    lines.push(std::mem::take(&mut line));
+                }
+                line.push(character);
+            }
+        }
+        lines.push(line);
+    }
+
+    lines
+}
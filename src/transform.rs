@@ -1,11 +1,8 @@
-use std::{
-    collections::{HashMap},
-};
-
 use slug::slugify;
 use wasm_bindgen::prelude::*;
 
-use crate::RenderAttributes;
+use crate::{point::GridLayout, PointF};
+use serde::{Deserialize, Serialize};
 
 #[wasm_bindgen]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -14,6 +11,7 @@ pub enum TransformationType {
     Rotate,
     Skew,
     Matrix,
+    RotateAround,
 }
 
 #[wasm_bindgen(getter_with_clone)]
@@ -23,12 +21,53 @@ pub struct TransformationWASM {
     pub parameters: Vec<f32>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Transformation {
     Scale(f32, f32),
     Rotate(f32),
     Skew(f32, f32),
     Matrix(f32, f32, f32, f32, f32, f32),
+    /// Rotation (in degrees) around the given grid position instead of the object's center
+    RotateAround(PointF, f32),
+}
+
+#[wasm_bindgen]
+impl TransformationWASM {
+    pub fn scale(x: f32, y: f32) -> Self {
+        Self {
+            kind: TransformationType::Scale,
+            parameters: vec![x, y],
+        }
+    }
+
+    pub fn rotate(degrees: f32) -> Self {
+        Self {
+            kind: TransformationType::Rotate,
+            parameters: vec![degrees],
+        }
+    }
+
+    pub fn skew(x: f32, y: f32) -> Self {
+        Self {
+            kind: TransformationType::Skew,
+            parameters: vec![x, y],
+        }
+    }
+
+    pub fn matrix(a: f32, b: f32, c: f32, d: f32, e: f32, f: f32) -> Self {
+        Self {
+            kind: TransformationType::Matrix,
+            parameters: vec![a, b, c, d, e, f],
+        }
+    }
+
+    /// Rotation (in degrees) around the given grid position
+    pub fn rotate_around(x: f32, y: f32, degrees: f32) -> Self {
+        Self {
+            kind: TransformationType::RotateAround,
+            parameters: vec![x, y, degrees],
+        }
+    }
 }
 
 impl From<TransformationWASM> for Transformation {
@@ -49,6 +88,10 @@ impl From<TransformationWASM> for Transformation {
                 transformation.parameters[4],
                 transformation.parameters[5],
             ),
+            TransformationType::RotateAround => Transformation::RotateAround(
+                PointF(transformation.parameters[0], transformation.parameters[1]),
+                transformation.parameters[2],
+            ),
         }
     }
 }
@@ -57,7 +100,7 @@ impl Transformation {
     pub fn name(&self) -> String {
         match self {
             Transformation::Matrix(..) => "matrix",
-            Transformation::Rotate(..) => "rotate",
+            Transformation::Rotate(..) | Transformation::RotateAround(..) => "rotate",
             Transformation::Scale(..) => "scale",
             Transformation::Skew(..) => "skew",
         }
@@ -72,28 +115,27 @@ impl Transformation {
     pub fn id(&self) -> String {
         slugify(format!("{:?}", self))
     }
-}
 
-impl RenderAttributes for Transformation {
-    const MULTIPLE_VALUES_JOIN_BY: &'static str = " ";
-
-    fn render_fill_attribute(&self, _colormap: &crate::ColorMapping) -> HashMap<String, String> {
-        let mut attrs = HashMap::new();
-        attrs.insert(
-            "transform".to_string(),
-            match self {
-                Transformation::Scale(x, y) => format!("scale({}  {})", x, y),
-                Transformation::Rotate(angle) => format!("rotate({})", angle),
-                Transformation::Skew(x, y) => format!("skewX({}) skewY({})", x, y),
-                Transformation::Matrix(a, b, c, d, e, f) => {
-                    format!("matrix({}, {}, {}, {}, {}, {})", a, b, c, d, e, f)
-                }
-            },
-        );
-        attrs
-    }
-
-    fn render_stroke_attribute(&self, colormap: &crate::ColorMapping) -> HashMap<String, String> {
-        self.render_fill_attribute(colormap)
+    /// Render as a SVG transform, applied around origin (in SVG coordinates), which is usually the object's center.
+    pub fn render_around(&self, origin: (f32, f32), layout: GridLayout) -> String {
+        let (x, y) = origin;
+        match self {
+            Transformation::Rotate(angle) => format!("rotate({} {} {})", angle, x, y),
+            Transformation::RotateAround(anchor, angle) => {
+                let (x, y) = anchor.coords(layout);
+                format!("rotate({} {} {})", angle, x, y)
+            }
+            Transformation::Scale(sx, sy) => format!(
+                "translate({} {}) scale({} {}) translate({} {})",
+                x, y, sx, sy, -x, -y
+            ),
+            Transformation::Skew(sx, sy) => format!(
+                "translate({} {}) skewX({}) skewY({}) translate({} {})",
+                x, y, sx, sy, -x, -y
+            ),
+            Transformation::Matrix(a, b, c, d, e, f) => {
+                format!("matrix({}, {}, {}, {}, {}, {})", a, b, c, d, e, f)
+            }
+        }
     }
 }
@@ -1,32 +1,44 @@
 use std::process;
 use std::{
     cmp::min,
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     fmt::Formatter,
     fs::{create_dir, create_dir_all, remove_dir_all},
+    ops::Range,
     panic,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{mpsc, Arc},
 };
 
 use std::thread;
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+use base64::Engine as _;
 use chrono::{DateTime, NaiveDateTime};
+use indexmap::IndexMap;
 use indicatif::{ProgressBar, ProgressIterator};
+use itertools::Itertools;
+use serde::Serialize;
+use slug::slugify;
 
 use crate::{
     preview,
     sync::SyncData,
     ui::{self, format_log_msg, setup_progress_bar, Log as _},
-    Canvas, ColoredObject, Context, LayerAnimationUpdateFunction, MidiSynchronizer,
-    MusicalDurationUnit, Syncable,
+    Canvas, ColorMapping, ColoredObject, Context, LayerAnimationUpdateFunction, LiveStem,
+    MidiSynchronizer, MusicalDurationUnit, Syncable, VelocityCurve,
 };
 
 pub type BeatNumber = usize;
 pub type FrameNumber = usize;
 pub type Millisecond = usize;
 
+/// Failed hook invocations sharing an error message list at most this many timestamps
+const MAX_LOGGED_FAILURE_TIMESTAMPS: usize = 5;
+
+/// Stem amplitudes are sent to the preview once every this many milliseconds
+const PREVIEW_STEMS_RESOLUTION_MS: Millisecond = 50;
+
 pub type RenderFunction<C> = dyn Fn(&mut Canvas, &mut Context<C>) -> anyhow::Result<()>;
 pub type CommandAction<C> = dyn Fn(String, &mut Canvas, &mut Context<C>) -> anyhow::Result<()>;
 
@@ -39,6 +51,59 @@ pub type LaterRenderFunction = dyn Fn(&mut Canvas, Millisecond) -> anyhow::Resul
 /// Arguments: canvas, context, previous rendered beat
 pub type LaterHookCondition<C> = dyn Fn(&Canvas, &Context<C>, BeatNumber) -> bool;
 
+/// What to do when a hook, later hook or command returns an error during rendering
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RenderErrorPolicy {
+    /// Abort the render on the first error
+    #[default]
+    FailFast,
+    /// Log the error, skip the failed hook invocation and keep rendering. A summary of all failures is printed at the end.
+    LogAndContinue,
+}
+
+/// A point on the timeline, either a timestamp in milliseconds or the first occurrence of a marker
+#[derive(Debug, Clone, PartialEq)]
+pub enum Moment {
+    Ms(Millisecond),
+    Marker(String),
+}
+
+impl From<usize> for Moment {
+    fn from(ms: usize) -> Self {
+        Moment::Ms(ms)
+    }
+}
+
+impl From<&str> for Moment {
+    fn from(marker: &str) -> Self {
+        Moment::Marker(marker.to_string())
+    }
+}
+
+/// A palette change, see `Video::palette_at`
+#[derive(Debug, Clone)]
+pub struct PaletteKeypoint {
+    pub at: Moment,
+    pub colormap: ColorMapping,
+    /// How long it takes to go from the previous palette to this one, starting at `at`
+    pub crossfade_ms: Millisecond,
+}
+
+/// A tweakable value, see [`Video::parameter`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Parameter {
+    pub name: String,
+    pub range: Range<f32>,
+    pub default: f32,
+}
+
+impl Parameter {
+    /// The closest value to `value` within the parameter's range
+    pub fn clamp(&self, value: f32) -> f32 {
+        value.max(self.range.start).min(self.range.end)
+    }
+}
+
 #[derive(Debug)]
 pub struct Video<C> {
     pub fps: usize,
@@ -53,6 +118,20 @@ pub struct Video<C> {
     pub duration_override: Option<usize>,
     pub start_rendering_at: usize,
     pub progress_bar: indicatif::ProgressBar,
+    /// Velocity curves applied to MIDI stems when loading sync data, by stem name ("*" for all stems)
+    pub velocity_curves: HashMap<String, VelocityCurve>,
+    /// Whether to add a "metronome" stem synthesized from the tempo map when loading sync data
+    pub synthesize_metronome: bool,
+    pub error_policy: RenderErrorPolicy,
+    pub palette_keypoints: Vec<PaletteKeypoint>,
+    /// Number of previous frames drawn, translucent, under each frame. Meant for debugging motion, 0 disables it.
+    pub onion_skin_frames: usize,
+    /// Frames output directory of a previous render, shown along with the frames in the preview
+    pub preview_comparison: Option<PathBuf>,
+    /// Values that hooks read with `Context::param`, and that can be tweaked with sliders in the preview
+    pub parameters: Vec<Parameter>,
+    /// Only layers matching these patterns are rendered, see [`Canvas::render_layers`]
+    pub layers: Vec<String>,
 }
 pub struct Hook<C> {
     pub when: Box<HookCondition<C>>,
@@ -95,6 +174,28 @@ impl<AdditionalContext: Default> Default for Video<AdditionalContext> {
     }
 }
 
+/// Rough average size of a rendered PNG frame, per pixel. Flat artwork compresses well.
+const ESTIMATED_PNG_BYTES_PER_PIXEL: f32 = 0.5;
+
+/// Returns the available disk space, in bytes, on the filesystem containing `path`. Returns None if it can't be determined.
+fn available_disk_space(path: &Path) -> Option<u64> {
+    let output = process::Command::new("df")
+        .arg("-Pk")
+        .arg(path)
+        .output()
+        .ok()?;
+
+    // Second line, fourth column is the available space in 1024-byte blocks
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse::<u64>()
+        .ok()
+        .map(|kilobytes| kilobytes * 1024)
+}
+
 fn is_binary_installed(binary: &str) -> bool {
     process::Command::new("which")
         .arg(binary)
@@ -118,12 +219,107 @@ impl<AdditionalContext: Default> Video<AdditionalContext> {
             duration_override: None,
             start_rendering_at: 0,
             progress_bar: setup_progress_bar(0, ""),
+            velocity_curves: HashMap::new(),
+            synthesize_metronome: false,
+            error_policy: RenderErrorPolicy::default(),
+            palette_keypoints: vec![],
+            onion_skin_frames: 0,
+            preview_comparison: None,
+            parameters: vec![],
+            layers: vec!["*".to_string()],
+        }
+    }
+
+    pub fn with_error_policy(self, error_policy: RenderErrorPolicy) -> Self {
+        Self {
+            error_policy,
+            ..self
+        }
+    }
+
+    /// Declare a parameter, readable from hooks with `Context::param`. The preview shows a slider for it, moving it renders the frames again with the new value.
+    pub fn parameter(self, name: &str, range: Range<f32>, default: f32) -> Self {
+        let mut parameters = self.parameters;
+        parameters.push(Parameter {
+            name: name.to_string(),
+            range,
+            default,
+        });
+        Self { parameters, ..self }
+    }
+
+    /// Draw the given number of previous frames under each frame, fading out as they get older. See `onion_skin_frames`.
+    pub fn with_onion_skin(self, frames: usize) -> Self {
+        Self {
+            onion_skin_frames: frames,
+            ..self
+        }
+    }
+
+    /// Shorthand for `with_error_policy(RenderErrorPolicy::LogAndContinue)`
+    pub fn continue_on_errors(self) -> Self {
+        self.with_error_policy(RenderErrorPolicy::LogAndContinue)
+    }
+
+    /// Applies the error policy to the result of a hook invocation: returns the error if the policy is to fail fast, logs it and records it in `failures` otherwise.
+    fn handle_hook_result(
+        &self,
+        result: Result<()>,
+        hook: &str,
+        context: &Context<AdditionalContext>,
+        failures: &mut Vec<(String, anyhow::Error)>,
+    ) -> Result<()> {
+        let result = result.with_context(|| {
+            let marker = context.marker();
+            format!(
+                "{hook} failed at {} (ms {}, beat {:.2}{})",
+                context.timestamp,
+                context.ms,
+                context.beat_fractional,
+                if marker.is_empty() {
+                    String::new()
+                } else {
+                    format!(", marker {marker:?}")
+                }
+            )
+        });
+
+        match (result, self.error_policy) {
+            (Ok(()), _) => Ok(()),
+            (Err(e), RenderErrorPolicy::FailFast) => Err(e),
+            (Err(e), RenderErrorPolicy::LogAndContinue) => {
+                self.progress_bar.log("Failed", &format!("{e:#}"));
+                failures.push((context.timestamp.clone(), e));
+                Ok(())
+            }
+        }
+    }
+
+    /// Add a "metronome" stem with a note on every beat (and accented downbeats), synthesized from the MIDI file's tempo map. Must be called before `sync_audio_with`.
+    pub fn with_metronome(self) -> Self {
+        Self {
+            synthesize_metronome: true,
+            ..self
+        }
+    }
+
+    /// Remap note velocities of the given stem when loading sync data. Must be called before `sync_audio_with`.
+    pub fn with_velocity_curve(self, stem: &str, curve: VelocityCurve) -> Self {
+        let mut velocity_curves = self.velocity_curves;
+        velocity_curves.insert(stem.to_string(), curve);
+        Self {
+            velocity_curves,
+            ..self
         }
     }
 
     pub fn sync_audio_with(self, sync_data_path: &str) -> Self {
         if sync_data_path.ends_with(".mid") || sync_data_path.ends_with(".midi") {
-            let loader = MidiSynchronizer::new(sync_data_path);
+            let mut loader = MidiSynchronizer::new(sync_data_path)
+                .with_velocity_curves(self.velocity_curves.clone());
+            if self.synthesize_metronome {
+                loader = loader.with_metronome();
+            }
             let syncdata = loader.load(Some(&self.progress_bar));
             self.progress_bar.finish();
             self.progress_bar.log(
@@ -498,6 +694,116 @@ impl<AdditionalContext: Default> Video<AdditionalContext> {
         })
     }
 
+    /// Switch the canvas' colormap at the given moment (milliseconds or marker text)
+    pub fn palette_at(self, at: impl Into<Moment>, colormap: ColorMapping) -> Self {
+        self.crossfading_palette_at(at, colormap, 0)
+    }
+
+    /// Gradually go from the current colormap to the given one, starting at the given moment and over crossfade_ms milliseconds
+    pub fn crossfading_palette_at(
+        self,
+        at: impl Into<Moment>,
+        colormap: ColorMapping,
+        crossfade_ms: Millisecond,
+    ) -> Self {
+        let mut palette_keypoints = self.palette_keypoints;
+        palette_keypoints.push(PaletteKeypoint {
+            at: at.into(),
+            colormap,
+            crossfade_ms,
+        });
+        Self {
+            palette_keypoints,
+            ..self
+        }
+    }
+
+    /// Gradually switch to the inverted counterpart (see `ColorMapping::inverted`) of the latest palette given with `palette_at` (or of the canvas' colormap), e.g. for day/night scene switches.
+    /// Keypoints are taken in the order they were added, so call this after the `palette_at` it should flip.
+    pub fn inverted_palette_at(self, at: impl Into<Moment>, crossfade_ms: Millisecond) -> Self {
+        let colormap = self
+            .palette_keypoints
+            .last()
+            .map_or(&self.initial_canvas.colormap, |keypoint| &keypoint.colormap)
+            .inverted();
+        self.crossfading_palette_at(at, colormap, crossfade_ms)
+    }
+
+    /// Palette keypoints with their timestamp, in chronological order. Keypoints on markers that never occur are left out.
+    fn resolved_palette_keypoints(&self) -> Vec<(Millisecond, &PaletteKeypoint)> {
+        let mut keypoints: Vec<_> = self
+            .palette_keypoints
+            .iter()
+            .filter_map(|keypoint| match &keypoint.at {
+                Moment::Ms(ms) => Some((*ms, keypoint)),
+                Moment::Marker(text) => self
+                    .syncdata
+                    .markers
+                    .iter()
+                    .filter(|(_, marker)| *marker == text)
+                    .map(|(ms, _)| *ms)
+                    .min()
+                    .map(|ms| (ms, keypoint)),
+            })
+            .collect();
+        keypoints.sort_by_key(|(ms, _)| *ms);
+        keypoints
+    }
+
+    /// Colormap at the given time according to the palette keypoints, None before the first one
+    fn palette_at_ms(
+        &self,
+        keypoints: &[(Millisecond, &PaletteKeypoint)],
+        ms: Millisecond,
+    ) -> Option<ColorMapping> {
+        let current = keypoints.iter().rposition(|(at, _)| *at <= ms)?;
+        let (at, keypoint) = keypoints[current];
+        let elapsed = ms - at;
+
+        if elapsed >= keypoint.crossfade_ms {
+            return Some(keypoint.colormap.clone());
+        }
+
+        let previous = match current {
+            0 => &self.initial_canvas.colormap,
+            _ => &keypoints[current - 1].1.colormap,
+        };
+        Some(previous.lerp(
+            &keypoint.colormap,
+            elapsed as f32 / keypoint.crossfade_ms as f32,
+        ))
+    }
+
+    /// Estimates the disk space needed to store all PNG frames
+    pub fn estimated_frames_disk_usage(&self) -> u64 {
+        let aspect_ratio = self.initial_canvas.aspect_ratio();
+        let pixels_per_frame =
+            (self.resolution as f32).powi(2) * aspect_ratio.max(1.0 / aspect_ratio);
+
+        (self.total_frames() as f32 * pixels_per_frame * ESTIMATED_PNG_BYTES_PER_PIXEL) as u64
+    }
+
+    /// Fails if the frames output directory is on a filesystem that does not have enough space left to store all frames
+    pub fn ensure_enough_disk_space(&self) -> Result<()> {
+        let required = self.estimated_frames_disk_usage();
+        match available_disk_space(Path::new(self.frames_output_directory)) {
+            Some(available) if available < required => Err(anyhow::format_err!(
+                "Not enough disk space to render frames in {}: about {} MiB needed, {} MiB available",
+                self.frames_output_directory,
+                required / 1024 / 1024,
+                available / 1024 / 1024
+            )),
+            Some(_) => Ok(()),
+            None => {
+                self.progress_bar.log(
+                    "Skipped",
+                    "disk space check: could not determine available disk space",
+                );
+                Ok(())
+            }
+        }
+    }
+
     pub fn total_frames(&self) -> usize {
         self.fps * (self.duration_ms() + self.start_rendering_at) / 1000
     }
@@ -515,25 +821,160 @@ impl<AdditionalContext: Default> Video<AdditionalContext> {
             .unwrap()
     }
 
+    /// Start a preview server. Frames are rendered when the preview asks for them.
     pub fn preview_on(&self, port: usize) -> Result<()> {
-        let mut rendered_frames: HashMap<usize, String> = HashMap::new();
-        let progress_bar = self.setup_progress_bar();
-
-        for (frame, _, ms) in self.render_frames(&progress_bar, true)? {
-            rendered_frames.insert(ms, frame);
-        }
-
-        progress_bar.finish_and_clear();
+        let (server, requests) = self.start_preview_server(port)?;
+        self.answer_preview_requests(&server, &requests, &mut None, false)?;
+        server.wait()
+    }
 
+    fn start_preview_server(
+        &self,
+        port: usize,
+    ) -> Result<(
+        preview::PreviewServer,
+        mpsc::Receiver<preview::PreviewRequest>,
+    )> {
         preview::output_preview(
             &self.initial_canvas,
-            &rendered_frames,
+            &HashMap::new(),
             port,
             PathBuf::from(".").join("preview.html"),
             self.audiofile.clone(),
+            &self.preview_timeline(),
+        )?;
+
+        let (server, requests) = preview::PreviewServer::start_lazy(
+            port,
+            self.preview_timeline(),
+            preview::ExportSize {
+                aspect_ratio: self.initial_canvas.aspect_ratio(),
+                resolution: self.resolution,
+            },
         )?;
+        if let Some(directory) = &self.preview_comparison {
+            server.compare_with(preview::Comparison::from_directory(directory, self.fps)?);
+        }
+        Ok((server, requests))
+    }
+
+    /// Render frames for the preview's requests, until the server stops (returns false) or watched files change (returns true).
+    /// `window` is the last window of frames previews asked for: it is rendered again and pushed to them when a parameter changes, and right away if `push_window` is set.
+    fn answer_preview_requests(
+        &self,
+        server: &preview::PreviewServer,
+        requests: &mpsc::Receiver<preview::PreviewRequest>,
+        window: &mut Option<(Millisecond, usize)>,
+        push_window: bool,
+    ) -> Result<bool> {
+        let mut renderer = FrameRenderer::new(self, &self.progress_bar, true);
+        // exported frames can be anywhere in the video, the preview's renderer would have to start over to render them
+        let mut exporter = FrameRenderer::new(self, &self.progress_bar, true);
+
+        if push_window {
+            match renderer.rendered_window(*window) {
+                Ok(frames) => server.reload(frames),
+                Err(e) => self.progress_bar.log("Failed", &format!("{e:#}")),
+            }
+        }
+
+        for request in requests {
+            match request {
+                preview::PreviewRequest::Frames(request) => {
+                    *window = Some((request.from_ms, request.count));
+                    let frames = renderer.render_window(request.from_ms, request.count)?;
+                    request.respond(frames.into_iter().map(|(svg, _, ms)| (ms, svg)).collect());
+                }
+                preview::PreviewRequest::Export(request) => {
+                    let ms = request.from_ms;
+                    match exporter.render_at(ms) {
+                        Ok(frame) => request.respond(HashMap::from([(ms, frame)])),
+                        Err(e) => {
+                            self.progress_bar
+                                .log("Failed", &format!("to export frame at {ms}ms: {e:#}"));
+                            request.respond(HashMap::new());
+                        }
+                    }
+                }
+                preview::PreviewRequest::SetParameter { name, value } => {
+                    let rerendered = renderer
+                        .set_parameter(&name, value)
+                        .and_then(|_| exporter.set_parameter(&name, value))
+                        .and_then(|_| renderer.rendered_window(*window));
+                    match rerendered {
+                        Ok(frames) => server.reload(frames),
+                        Err(e) => self.progress_bar.log("Failed", &format!("{e:#}")),
+                    }
+                }
+                preview::PreviewRequest::Refresh => {
+                    renderer.log_failures();
+                    return Ok(true);
+                }
+            }
+        }
+
+        renderer.log_failures();
+        Ok(false)
+    }
+
+    fn preview_timeline(&self) -> preview::Timeline {
+        preview::Timeline {
+            duration_ms: self.duration_ms(),
+            beats: self.syncdata.beats_until(self.duration_ms()),
+            markers: self
+                .syncdata
+                .markers
+                .iter()
+                .map(|(ms, marker)| (*ms, marker.clone()))
+                .collect(),
+            stems: self
+                .syncdata
+                .stems
+                .iter()
+                .map(|(name, stem)| {
+                    (
+                        name.clone(),
+                        stem.downsampled_amplitudes(PREVIEW_STEMS_RESOLUTION_MS),
+                    )
+                })
+                .collect(),
+            stems_resolution_ms: PREVIEW_STEMS_RESOLUTION_MS,
+            parameters: self.parameters.clone(),
+        }
+    }
+
+    /// Like `preview_on`, but when one of the watched files changes, the video is built again with `reload` and the frames the preview is showing are rendered again and pushed to it.
+    /// If `reload` fails, the error is logged and the preview keeps showing the previous video. Changes to the program itself need a restart.
+    pub fn preview_watch(
+        &self,
+        port: usize,
+        watched: Vec<PathBuf>,
+        reload: impl Fn() -> Result<Self>,
+    ) -> Result<()> {
+        let (server, requests) = self.start_preview_server(port)?;
+        server.refresh_on_changes(watched)?;
+
+        let mut reloaded: Option<Self> = None;
+        let mut window = None;
+        let mut push_window = false;
+        while reloaded.as_ref().unwrap_or(self).answer_preview_requests(
+            &server,
+            &requests,
+            &mut window,
+            push_window,
+        )? {
+            self.progress_bar
+                .log("Reloading", "the video, watched files changed");
+            match reload() {
+                Ok(video) => reloaded = Some(video),
+                Err(e) => self
+                    .progress_bar
+                    .log("Failed", &format!("to reload the video: {e:#}")),
+            }
+            push_window = true;
+        }
 
-        preview::start_preview_server(port, rendered_frames)
+        server.wait()
     }
 
     pub fn render_to(
@@ -545,21 +986,51 @@ impl<AdditionalContext: Default> Video<AdditionalContext> {
         self.render(output_file, true, workers_count, preview_only)
     }
 
+    /// Only render layers matching these patterns
+    pub fn with_layers(self, patterns: &[&str]) -> Self {
+        Self {
+            layers: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            ..self
+        }
+    }
+
+    /// Renders each layer to its own video, without background, in output_directory
     pub fn render_layers_in(&self, output_directory: String, workers_count: usize) -> Result<()> {
-        for composition in self
+        let compositions = self
             .initial_canvas
             .layers
             .iter()
-            .map(|l| vec![l.name.as_str()])
-        {
-            self.render(
-                format!("{}/{}.mov", output_directory, composition.join("+")),
-                false,
-                workers_count,
-                false,
-            )?;
+            .map(|layer| (layer.name.clone(), vec![layer.name.clone()]))
+            .collect();
+        self.render_compositions_in(&compositions, output_directory, workers_count)
+    }
+
+    /// Renders each composition (layer name patterns, by composition name) to its own video, without background, in output_directory
+    /// Composition names are slugified to get file names, so that they stay in output_directory.
+    pub fn render_compositions_in(
+        &self,
+        compositions: &IndexMap<String, Vec<String>>,
+        output_directory: String,
+        workers_count: usize,
+    ) -> Result<()> {
+        let files = compositions.keys().map(slugify).collect_vec();
+        if let Some(file) = files.iter().duplicates().next() {
+            return Err(anyhow::format_err!(
+                "Several compositions would be saved to {output_directory}/{file}.mov, rename them"
+            ));
         }
-        Ok(())
+
+        compositions
+            .values()
+            .zip(files)
+            .try_for_each(|(patterns, file)| {
+                self.render_layers_to(
+                    format!("{}/{}.mov", output_directory, file),
+                    patterns,
+                    false,
+                    workers_count,
+                )
+            })
     }
 
     // Returns a triple of (SVG content, frame number, millisecond at frame)
@@ -568,108 +1039,33 @@ impl<AdditionalContext: Default> Video<AdditionalContext> {
         progress_bar: &ProgressBar,
         render_background: bool,
     ) -> Result<Vec<(String, usize, usize)>> {
-        let mut context = Context {
-            frame: 0,
-            beat: 0,
-            beat_fractional: 0.0,
-            timestamp: "00:00:00.000".to_string(),
-            ms: 0,
-            bpm: self.syncdata.bpm,
-            syncdata: &self.syncdata,
-            extra: AdditionalContext::default(),
-            later_hooks: vec![],
-            audiofile: self.audiofile.clone(),
-            duration_override: self.duration_override,
-        };
-
-        let mut canvas = self.initial_canvas.clone();
+        self.render_layer_frames(&self.layers, progress_bar, render_background)
+    }
 
-        let mut previous_rendered_beat = 0;
-        let mut previous_rendered_frame = 0;
+    /// Like `render_frames`, but only rendering layers matching the given patterns
+    fn render_layer_frames(
+        &self,
+        layers: &[String],
+        progress_bar: &ProgressBar,
+        render_background: bool,
+    ) -> Result<Vec<(String, usize, usize)>> {
+        let mut renderer =
+            FrameRenderer::new(self, progress_bar, render_background).with_layers(layers);
         let mut frames_to_write: Vec<(String, usize, usize)> = vec![];
 
         let render_ms_range = 0..self.duration_ms() + self.start_rendering_at;
-
         self.progress_bar.set_length(render_ms_range.len() as u64);
 
         for _ in render_ms_range
             .into_iter()
             .progress_with(self.progress_bar.clone())
         {
-            context.ms += 1_usize;
-            context.timestamp = milliseconds_to_timestamp(context.ms).to_string();
-            context.beat_fractional = (context.bpm * context.ms) as f32 / (1000.0 * 60.0);
-            context.beat = context.beat_fractional as usize;
-            context.frame = self.fps * context.ms / 1000;
-
-            progress_bar.set_message(context.timestamp.clone());
-
-            if context.marker() != "" {
-                progress_bar.println(format!(
-                    "{}: marker {}",
-                    context.timestamp,
-                    context.marker()
-                ));
-            }
-
-            if context.marker().starts_with(':') {
-                let marker_text = context.marker();
-                let commandline = marker_text.trim_start_matches(':').to_string();
-
-                for command in &self.commands {
-                    if commandline.starts_with(&command.name) {
-                        let args = commandline
-                            .trim_start_matches(&command.name)
-                            .trim()
-                            .to_string();
-                        (command.action)(args, &mut canvas, &mut context)?;
-                    }
-                }
-            }
-
-            // Render later hooks first, so that for example animations that aren't finished yet get overwritten by next frame's hook, if the next frames touches the same object
-            // This is way better to cancel early animations such as fading out an object that appears on every note of a stem, if the next note is too close for the fade-out to finish.
-
-            let mut later_hooks_to_delete: Vec<usize> = vec![];
-
-            for (i, hook) in context.later_hooks.iter().enumerate() {
-                if (hook.when)(&canvas, &context, previous_rendered_beat) {
-                    (hook.render_function)(&mut canvas, context.ms)?;
-                    if hook.once {
-                        later_hooks_to_delete.push(i);
-                    }
-                } else if !hook.once {
-                    later_hooks_to_delete.push(i);
-                }
-            }
-
-            for i in later_hooks_to_delete {
-                if i < context.later_hooks.len() {
-                    context.later_hooks.remove(i);
-                }
-            }
-
-            for hook in &self.hooks {
-                if (hook.when)(
-                    &canvas,
-                    &context,
-                    previous_rendered_beat,
-                    previous_rendered_frame,
-                ) {
-                    (hook.render_function)(&mut canvas, &mut context)?;
-                }
-            }
-
-            if context.frame != previous_rendered_frame {
-                let rendered = canvas.render(render_background)?;
-
-                previous_rendered_beat = context.beat;
-                previous_rendered_frame = context.frame;
-
-                frames_to_write.push((rendered, context.frame, context.ms))
+            if let Some(frame) = renderer.step(true)? {
+                frames_to_write.push(frame);
             }
         }
 
+        renderer.log_failures();
         Ok(frames_to_write)
     }
 
@@ -683,6 +1079,17 @@ impl<AdditionalContext: Default> Video<AdditionalContext> {
         render_background: bool,
         workers_count: usize,
         _preview_only: bool,
+    ) -> Result<()> {
+        self.render_layers_to(output_file, &self.layers, render_background, workers_count)
+    }
+
+    /// Like `render`, but only rendering layers matching the given patterns
+    fn render_layers_to(
+        &self,
+        output_file: String,
+        layers: &[String],
+        render_background: bool,
+        workers_count: usize,
     ) -> Result<()> {
         // Ensure resvg is installed
         if !is_binary_installed("resvg") {
@@ -701,6 +1108,8 @@ impl<AdditionalContext: Default> Video<AdditionalContext> {
         create_dir(self.frames_output_directory)?;
         create_dir_all(Path::new(&output_file).parent().unwrap())?;
 
+        self.ensure_enough_disk_space()?;
+
         let total_frames = self.total_frames();
         let aspect_ratio =
             self.initial_canvas.grid_size.0 as f32 / self.initial_canvas.grid_size.1 as f32;
@@ -710,7 +1119,9 @@ impl<AdditionalContext: Default> Video<AdditionalContext> {
         self.progress_bar.set_prefix("Rendering");
         self.progress_bar.set_message("");
 
-        for (frame, no, ms) in self.render_frames(&self.progress_bar, render_background)? {
+        for (frame, no, ms) in
+            self.render_layer_frames(layers, &self.progress_bar, render_background)?
+        {
             frames_to_write.push((frame, no, ms));
         }
 
@@ -784,6 +1195,321 @@ impl<AdditionalContext: Default> Video<AdditionalContext> {
     }
 }
 
+/// Runs hooks millisecond by millisecond and renders frames as they change, keeping the state of the video between calls.
+/// Used to render every frame, or only the ones the preview asks for.
+pub struct FrameRenderer<'a, C> {
+    video: &'a Video<C>,
+    context: Context<'a, C>,
+    canvas: Canvas,
+    progress_bar: ProgressBar,
+    render_background: bool,
+    previous_rendered_beat: BeatNumber,
+    previous_rendered_frame: FrameNumber,
+    palette_keypoints: Vec<(Millisecond, &'a PaletteKeypoint)>,
+    /// Most recent first, rendered without background. See `Video::onion_skin_frames`.
+    ghost_frames: VecDeque<String>,
+    failures: Vec<(String, anyhow::Error)>,
+    /// Patterns of the layers to render, `Video::layers` unless set with `with_layers`
+    layers: &'a [String],
+}
+
+impl<'a, AdditionalContext: Default> FrameRenderer<'a, AdditionalContext> {
+    pub fn new(
+        video: &'a Video<AdditionalContext>,
+        progress_bar: &ProgressBar,
+        render_background: bool,
+    ) -> Self {
+        Self {
+            video,
+            context: Context {
+                frame: 0,
+                beat: 0,
+                beat_fractional: 0.0,
+                timestamp: "00:00:00.000".to_string(),
+                ms: 0,
+                bpm: video.syncdata.bpm,
+                syncdata: &video.syncdata,
+                extra: AdditionalContext::default(),
+                later_hooks: vec![],
+                params: video
+                    .parameters
+                    .iter()
+                    .map(|parameter| (parameter.name.clone(), parameter.default))
+                    .collect(),
+                live_stems: HashMap::new(),
+                audiofile: video.audiofile.clone(),
+                duration_override: video.duration_override,
+            },
+            canvas: video.initial_canvas.clone(),
+            progress_bar: progress_bar.clone(),
+            render_background,
+            previous_rendered_beat: 0,
+            previous_rendered_frame: 0,
+            palette_keypoints: video.resolved_palette_keypoints(),
+            ghost_frames: VecDeque::new(),
+            failures: vec![],
+            layers: &video.layers,
+        }
+    }
+
+    /// Only render layers matching these patterns, instead of the video's
+    pub fn with_layers(self, patterns: &'a [String]) -> Self {
+        Self {
+            layers: patterns,
+            ..self
+        }
+    }
+
+    /// Millisecond the renderer is at
+    pub fn ms(&self) -> Millisecond {
+        self.context.ms
+    }
+
+    /// Advance by one millisecond. Returns the new frame as (SVG content, frame number, millisecond at frame) if the frame changed and `render` is true.
+    pub fn step(&mut self, render: bool) -> Result<Option<(String, usize, usize)>> {
+        let Self {
+            video,
+            context,
+            canvas,
+            progress_bar,
+            render_background,
+            previous_rendered_beat,
+            previous_rendered_frame,
+            palette_keypoints,
+            ghost_frames,
+            failures,
+            layers,
+        } = self;
+
+        context.ms += 1_usize;
+        context.timestamp = milliseconds_to_timestamp(context.ms).to_string();
+        context.beat_fractional = (context.bpm * context.ms) as f32 / (1000.0 * 60.0);
+        context.beat = context.beat_fractional as usize;
+        context.frame = video.fps * context.ms / 1000;
+
+        progress_bar.set_message(context.timestamp.clone());
+
+        if context.marker() != "" {
+            progress_bar.println(format!(
+                "{}: marker {}",
+                context.timestamp,
+                context.marker()
+            ));
+        }
+
+        if context.marker().starts_with(':') {
+            let marker_text = context.marker();
+            let commandline = marker_text.trim_start_matches(':').to_string();
+
+            for command in &video.commands {
+                if commandline.starts_with(&command.name) {
+                    let args = commandline
+                        .trim_start_matches(&command.name)
+                        .trim()
+                        .to_string();
+                    let result = (command.action)(args, canvas, context);
+                    video.handle_hook_result(
+                        result,
+                        &format!("command {:?}", command.name),
+                        context,
+                        failures,
+                    )?;
+                }
+            }
+        }
+
+        // Render later hooks first, so that for example animations that aren't finished yet get overwritten by next frame's hook, if the next frames touches the same object
+        // This is way better to cancel early animations such as fading out an object that appears on every note of a stem, if the next note is too close for the fade-out to finish.
+
+        let mut later_hooks_to_delete: Vec<usize> = vec![];
+
+        for (i, hook) in context.later_hooks.iter().enumerate() {
+            if (hook.when)(canvas, context, *previous_rendered_beat) {
+                let result = (hook.render_function)(canvas, context.ms);
+                video.handle_hook_result(result, &format!("later hook #{i}"), context, failures)?;
+                if hook.once {
+                    later_hooks_to_delete.push(i);
+                }
+            } else if !hook.once {
+                later_hooks_to_delete.push(i);
+            }
+        }
+
+        for i in later_hooks_to_delete {
+            if i < context.later_hooks.len() {
+                context.later_hooks.remove(i);
+            }
+        }
+
+        for (i, hook) in video.hooks.iter().enumerate() {
+            if (hook.when)(
+                canvas,
+                context,
+                *previous_rendered_beat,
+                *previous_rendered_frame,
+            ) {
+                let result = (hook.render_function)(canvas, context);
+                video.handle_hook_result(result, &format!("hook #{i}"), context, failures)?;
+            }
+        }
+
+        canvas.step_physics(1);
+
+        if context.frame == *previous_rendered_frame {
+            return Ok(None);
+        }
+
+        if let Some(colormap) = video.palette_at_ms(palette_keypoints, context.ms) {
+            canvas.colormap = colormap;
+        }
+        *previous_rendered_beat = context.beat;
+        *previous_rendered_frame = context.frame;
+
+        if !render {
+            // a frame is missing, so the ghosts don't precede the next frame anymore
+            ghost_frames.clear();
+            return Ok(None);
+        }
+
+        let layers = layers.iter().map(String::as_str).collect_vec();
+        let rendered = if video.onion_skin_frames > 0 {
+            let current = canvas.render_document(&layers, false)?;
+            let ghost = current.to_string();
+            let rendered =
+                onion_skinned(canvas, current, ghost_frames, *render_background).to_string();
+            ghost_frames.push_front(ghost);
+            ghost_frames.truncate(video.onion_skin_frames);
+            rendered
+        } else {
+            canvas.render_layers(&layers, *render_background)?
+        };
+
+        Ok(Some((rendered, context.frame, context.ms)))
+    }
+
+    /// Render at most `count` frames starting at `from_ms`. Hooks run (without rendering) up to `from_ms` first, starting over from the beginning if the renderer is already past it.
+    pub fn render_window(
+        &mut self,
+        from_ms: Millisecond,
+        count: usize,
+    ) -> Result<Vec<(String, usize, usize)>> {
+        if from_ms < self.ms() {
+            self.restart();
+        }
+
+        let end_ms = self.video.duration_ms() + self.video.start_rendering_at;
+        // the frames right before the window are rendered too, as ghosts for the first frames of the window
+        let ghosts_from_ms = match self.video.onion_skin_frames {
+            0 => from_ms,
+            frames => from_ms.saturating_sub((frames + 1) * 1000 / self.video.fps.max(1)),
+        };
+        while self.ms() + 1 < ghosts_from_ms.min(end_ms) {
+            self.step(false)?;
+        }
+        while self.ms() + 1 < from_ms.min(end_ms) {
+            self.step(true)?;
+        }
+
+        let mut frames = vec![];
+        while frames.len() < count && self.ms() < end_ms {
+            if let Some(frame) = self.step(true)? {
+                frames.push(frame);
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Run hooks up to `ms`, and render the canvas as it is then, without onion skinning. Starts over if the renderer is already past `ms`.
+    pub fn render_at(&mut self, ms: Millisecond) -> Result<String> {
+        if ms < self.ms() {
+            self.restart();
+        }
+
+        while self.ms() < ms {
+            self.step(false)?;
+        }
+        let layers = self.layers.iter().map(String::as_str).collect_vec();
+        self.canvas.render_layers(&layers, self.render_background)
+    }
+
+    /// Change a parameter's value, clamped to its range. Hooks might have used the previous value, so rendering starts over.
+    pub fn set_parameter(&mut self, name: &str, value: f32) -> Result<()> {
+        let parameter = self
+            .video
+            .parameters
+            .iter()
+            .find(|parameter| parameter.name == name)
+            .ok_or_else(|| anyhow::format_err!("No parameter named {name}"))?;
+        self.context
+            .params
+            .insert(name.to_string(), parameter.clamp(value));
+        self.restart();
+        Ok(())
+    }
+
+    /// Update a stem analysed as the video plays, see `Context::live_stems`
+    pub fn set_live_stem(&mut self, name: &str, stem: LiveStem) {
+        self.context.live_stems.insert(name.to_string(), stem);
+    }
+
+    /// Go back to the beginning of the video, keeping parameter values and live stems
+    fn restart(&mut self) {
+        let params = std::mem::take(&mut self.context.params);
+        let live_stems = std::mem::take(&mut self.context.live_stems);
+        *self = Self::new(self.video, &self.progress_bar, self.render_background)
+            .with_layers(self.layers);
+        self.context.params = params;
+        self.context.live_stems = live_stems;
+    }
+
+    /// Frames of the window, by millisecond, for previews to replace theirs with. No window gives no frames.
+    fn rendered_window(
+        &mut self,
+        window: Option<(Millisecond, usize)>,
+    ) -> Result<HashMap<Millisecond, String>> {
+        let Some((from_ms, count)) = window else {
+            return Ok(HashMap::new());
+        };
+        Ok(self
+            .render_window(from_ms, count)?
+            .into_iter()
+            .map(|(svg, _, ms)| (ms, svg))
+            .collect())
+    }
+
+    /// Logs the failed hook invocations, one line per distinct error message
+    pub fn log_failures(&self) {
+        let mut timestamps_by_message: IndexMap<String, Vec<&str>> = IndexMap::new();
+        for (timestamp, error) in &self.failures {
+            timestamps_by_message
+                .entry(format!("{:#}", error))
+                .or_default()
+                .push(timestamp);
+        }
+        for (message, timestamps) in timestamps_by_message {
+            let mut at = timestamps
+                .iter()
+                .take(MAX_LOGGED_FAILURE_TIMESTAMPS)
+                .join(", ");
+            if timestamps.len() > MAX_LOGGED_FAILURE_TIMESTAMPS {
+                at += &format!(
+                    " and {} more",
+                    timestamps.len() - MAX_LOGGED_FAILURE_TIMESTAMPS
+                );
+            }
+            self.video.progress_bar.log(
+                "Failed",
+                &format!(
+                    "{} hook invocations, at {}: {}",
+                    timestamps.len(),
+                    at,
+                    message
+                ),
+            );
+        }
+    }
+}
+
 pub fn milliseconds_to_timestamp(ms: usize) -> String {
     format!(
         "{}",
@@ -792,3 +1518,38 @@ pub fn milliseconds_to_timestamp(ms: usize) -> String {
             .format("%H:%M:%S%.3f")
     )
 }
+
+/// The current frame, with the ghost frames (most recent first, all rendered without background) drawn under it at decreasing opacities.
+/// Ghosts are embedded as images, so that their clip paths, patterns and filters don't clash with the current frame's.
+fn onion_skinned(
+    canvas: &Canvas,
+    mut current: svg::Document,
+    ghost_frames: &VecDeque<String>,
+    render_background: bool,
+) -> svg::Document {
+    let (origin_x, origin_y) = canvas.viewbox_origin();
+    let mut underlay: Vec<Box<dyn svg::Node>> = vec![];
+    if render_background {
+        underlay.push(Box::new(canvas.background_rect()));
+    }
+    for (age, ghost) in ghost_frames.iter().enumerate().rev() {
+        let opacity = 0.5 * (1.0 - age as f32 / ghost_frames.len() as f32);
+        underlay.push(Box::new(
+            svg::node::element::Image::new()
+                .set("x", origin_x)
+                .set("y", origin_y)
+                .set("width", canvas.width())
+                .set("height", canvas.height())
+                .set("opacity", opacity)
+                .set(
+                    "href",
+                    format!(
+                        "data:image/svg+xml;base64,{}",
+                        base64::engine::general_purpose::STANDARD.encode(ghost)
+                    ),
+                ),
+        ));
+    }
+    current.get_children_mut().splice(0..0, underlay);
+    current
+}
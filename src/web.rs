@@ -1,12 +1,26 @@
 #![allow(unused)]
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 
+use indicatif::ProgressBar;
+
 use once_cell::sync::Lazy;
-use wasm_bindgen::prelude::wasm_bindgen;
-use wasm_bindgen::{JsValue, UnwrapThrowExt};
+use wasm_bindgen::prelude::{wasm_bindgen, Closure};
+use wasm_bindgen::{JsCast, JsValue, UnwrapThrowExt};
 
-use crate::{examples, Canvas, Color, ColorMapping, Fill, Filter, Layer, Object, Point};
+use crate::{
+    color::random_color, examples, transform::TransformationWASM, Angle, Canvas, Color, ColorMapping, ColoredObject, Fill, Filter, Layer,
+    Object, Point,
+    Context, FrameNumber, Millisecond, MusicalDurationUnit, Region, StemAtInstant,
+    Video,
+};
+use crate::video::{FrameRenderer, RenderFunction};
+use crate::web_audio::LiveAudio;
 
 static WEB_CANVAS: Lazy<Mutex<Canvas>> = Lazy::new(|| Mutex::new(Canvas::default_settings()));
 
@@ -14,11 +28,225 @@ fn canvas() -> std::sync::MutexGuard<'static, Canvas> {
     WEB_CANVAS.lock().unwrap()
 }
 
+/// Canvas states as JSON, most recent last. See `WebCanvas::undo`.
+static UNDO_STACK: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(vec![]));
+static REDO_STACK: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(vec![]));
+
+/// Older states are forgotten
+const MAX_UNDO_STEPS: usize = 100;
+
+/// Off while a video hook runs: hooks edit the frame's canvas, not the user's, see `js_hook`
+static RECORD_HISTORY: AtomicBool = AtomicBool::new(true);
+
+/// Like `canvas()`, but saves the current state first, so that the edit can be undone
+fn edited_canvas() -> std::sync::MutexGuard<'static, Canvas> {
+    let canvas = canvas();
+    if !RECORD_HISTORY.load(Ordering::Relaxed) {
+        return canvas;
+    }
+    match canvas.to_json() {
+        Ok(snapshot) => {
+            let mut undo_stack = UNDO_STACK.lock().unwrap();
+            undo_stack.push(snapshot);
+            if undo_stack.len() > MAX_UNDO_STEPS {
+                undo_stack.remove(0);
+            }
+            REDO_STACK.lock().unwrap().clear();
+        }
+        Err(error) => log(&format!("This edit can't be undone: {error:#}")),
+    }
+    canvas
+}
+
+/// Pops a state from `from` and restores it, pushing the current state on `to`. Returns false if `from` is empty.
+fn restore_from_history(from: &Mutex<Vec<String>>, to: &Mutex<Vec<String>>) -> bool {
+    let Some(snapshot) = from.lock().unwrap().pop() else {
+        return false;
+    };
+    let mut canvas = canvas();
+    to.lock().unwrap().push(canvas.to_json().unwrap_throw());
+    *canvas = Canvas::from_json(&snapshot).unwrap_throw();
+    true
+}
+
+/// Parameters set with MIDI controls, by name
+static PARAMETERS: Lazy<Mutex<HashMap<String, f32>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Parameter name and value range
+type MidiControlBinding = (String, Range<f32>);
+
+/// By MIDI controller number
+static MIDI_CONTROLS: Lazy<Mutex<HashMap<u8, MidiControlBinding>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Layer names, by MIDI pitch
+static MIDI_PADS: Lazy<Mutex<HashMap<u8, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+
+/// Named colors, as seen from JavaScript.
+/// wasm-bindgen only supports enums without data, so custom colors aren't available there.
+#[wasm_bindgen(js_name = Color)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WebColor {
+    Black,
+    White,
+    Red,
+    Green,
+    Blue,
+    Yellow,
+    Orange,
+    Purple,
+    Brown,
+    Cyan,
+    Pink,
+    Gray,
+}
+
+impl From<WebColor> for Color {
+    fn from(color: WebColor) -> Self {
+        match color {
+            WebColor::Black => Color::Black,
+            WebColor::White => Color::White,
+            WebColor::Red => Color::Red,
+            WebColor::Green => Color::Green,
+            WebColor::Blue => Color::Blue,
+            WebColor::Yellow => Color::Yellow,
+            WebColor::Orange => Color::Orange,
+            WebColor::Purple => Color::Purple,
+            WebColor::Brown => Color::Brown,
+            WebColor::Cyan => Color::Cyan,
+            WebColor::Pink => Color::Pink,
+            WebColor::Gray => Color::Gray,
+        }
+    }
+}
+
+impl TryFrom<Color> for WebColor {
+    type Error = String;
+
+    fn try_from(color: Color) -> Result<Self, Self::Error> {
+        match color {
+            Color::Black => Ok(WebColor::Black),
+            Color::White => Ok(WebColor::White),
+            Color::Red => Ok(WebColor::Red),
+            Color::Green => Ok(WebColor::Green),
+            Color::Blue => Ok(WebColor::Blue),
+            Color::Yellow => Ok(WebColor::Yellow),
+            Color::Orange => Ok(WebColor::Orange),
+            Color::Purple => Ok(WebColor::Purple),
+            Color::Brown => Ok(WebColor::Brown),
+            Color::Cyan => Ok(WebColor::Cyan),
+            Color::Pink => Ok(WebColor::Pink),
+            Color::Gray => Ok(WebColor::Gray),
+            Color::Custom(name) => Err(format!("Custom color {} has no JavaScript equivalent", name)),
+        }
+    }
+}
+
+/// Fills, as seen from JavaScript. Angles are in degrees.
+#[wasm_bindgen(js_name = Fill)]
+#[derive(Debug, Clone)]
+pub struct WebFill(Fill);
+
+#[wasm_bindgen(js_class = Fill)]
+impl WebFill {
+    pub fn solid(color: WebColor) -> Self {
+        Self(Fill::Solid(color.into()))
+    }
+
+    pub fn translucent(color: WebColor, opacity: f32) -> Self {
+        Self(Fill::Translucent(color.into(), opacity))
+    }
+
+    /// `size` is the size of a single hatch pattern tile, and `thickness_ratio` the proportion of it that is colored
+    pub fn hatched(color: WebColor, angle: f32, size: f32, thickness_ratio: f32) -> Self {
+        Self(Fill::Hatched(color.into(), Angle(angle), size, thickness_ratio))
+    }
+
+    pub fn cross_hatched(color: WebColor, angle: f32, size: f32, thickness_ratio: f32) -> Self {
+        Self(Fill::CrossHatched(color.into(), Angle(angle), size, thickness_ratio))
+    }
+
+    pub fn dotted(color: WebColor, diameter: f32, spacing: f32) -> Self {
+        Self(Fill::Dotted(color.into(), diameter, spacing))
+    }
+
+    /// Gradient between two colors. An angle of 0 goes from left to right.
+    pub fn linear_gradient(from: WebColor, to: WebColor, angle: f32) -> Self {
+        Self(Fill::LinearGradient(
+            vec![(from.into(), 0.0), (to.into(), 1.0)],
+            Angle(angle),
+        ))
+    }
+
+    /// `focus` is how far from the center (from 0.0 to 1.0) the center color extends before fading to the edge color
+    pub fn radial_gradient(center: WebColor, edge: WebColor, focus: f32) -> Self {
+        Self(Fill::RadialGradient(center.into(), edge.into(), focus))
+    }
+
+    pub fn noise(color: WebColor, grain_size: f32, opacity: f32) -> Self {
+        Self(Fill::Noise(color.into(), grain_size, opacity))
+    }
+
+    /// A hex color, used as is instead of going through the palette
+    pub fn hex(color: String) -> Self {
+        Self(Fill::Hex(color))
+    }
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const COLOR_MAPPING_OBJECT: &str = r#"
+/** CSS colors for each of shapemaker's color names. See `ColorMapping.from_object`. */
+export interface ColorMappingObject {
+    black: string;
+    white: string;
+    red: string;
+    green: string;
+    blue: string;
+    yellow: string;
+    orange: string;
+    purple: string;
+    brown: string;
+    cyan: string;
+    pink: string;
+    gray: string;
+    /** Colors beyond the twelve named ones */
+    extra?: Record<string, string>;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "ColorMappingObject")]
+    pub type ColorMappingObject;
+}
+
+#[wasm_bindgen]
+impl ColorMapping {
+    /// From a plain object, e.g. `ColorMapping.from_object({ black: "#000", white: "#fff", ... })`
+    pub fn from_object(colors: ColorMappingObject) -> Result<ColorMapping, JsValue> {
+        let json: String = js_sys::JSON::stringify(&colors)?.into();
+        serde_json::from_str(&json).map_err(|error| JsValue::from_str(&error.to_string()))
+    }
+}
+
+#[wasm_bindgen(js_name = random_color)]
+pub fn random_web_color(except: Option<WebColor>) -> WebColor {
+    random_color(except.map(Color::from))
+        .try_into()
+        .unwrap_throw()
+}
 
 // Can't bind Color.name directly, see https://github.com/rustwasm/wasm-bindgen/issues/1715
 #[wasm_bindgen]
-pub fn color_name(c: Color) -> String {
-    c.name()
+pub fn color_name(c: WebColor) -> String {
+    Color::from(c).name()
+}
+
+/// Make all random generation reproducible from now on: the same seed gives the same artwork
+#[wasm_bindgen]
+pub fn set_seed(seed: u64) {
+    crate::set_seed(seed);
 }
 
 #[wasm_bindgen]
@@ -28,11 +256,11 @@ extern "C" {
 }
 
 macro_rules! console_log {
-    ($($t:tt)*) => (crate::log(&format_args!($($t)*).to_string()))
+    ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
 #[wasm_bindgen]
-pub fn render_image(opacity: f32, color: Color) -> Result<(), JsValue> {
+pub fn render_image(opacity: f32, color: WebColor) -> Result<(), JsValue> {
     let mut canvas = examples::dna_analysis_machine();
     canvas.colormap = ColorMapping {
         black: "#ffffff".into(),
@@ -47,27 +275,102 @@ pub fn render_image(opacity: f32, color: Color) -> Result<(), JsValue> {
         pink: "#e92e76".into(),
         gray: "#81a0a8".into(),
         cyan: "#4fecec".into(),
+        extra: HashMap::new(),
     };
 
-    *WEB_CANVAS.lock().unwrap() = canvas;
-    render_canvas_at(String::from("body"));
+    *edited_canvas() = canvas;
+    WebCanvas::render_at(String::from("body"));
+
+    Ok(())
+}
 
+/// Listen to all connected MIDI inputs, and apply bindings made with `bind_midi_control` and `bind_midi_pad`.
+/// `on_change` is called after each message that changed something, to render the canvas again for example.
+#[wasm_bindgen]
+pub fn map_to_midi_controller(on_change: js_sys::Function) -> Result<(), JsValue> {
+    let navigator = web_sys::window()
+        .expect_throw("no global `window` exists")
+        .navigator();
+
+    let on_access = Closure::<dyn FnMut(JsValue)>::new(move |access: JsValue| {
+        let access: web_sys::MidiAccess = access.unchecked_into();
+        for input in access.inputs().values() {
+            let input: web_sys::MidiInput = input.unwrap_throw().unchecked_into();
+            let on_change = on_change.clone();
+            let on_message = Closure::<dyn FnMut(web_sys::MidiMessageEvent)>::new(
+                move |event: web_sys::MidiMessageEvent| {
+                    let Some(message) = event
+                        .data()
+                        .ok()
+                        .and_then(|data| MidiMessage::from_bytes(&data))
+                    else {
+                        return;
+                    };
+                    if apply_midi_message(message) {
+                        on_change.call0(&JsValue::NULL).unwrap_throw();
+                    }
+                },
+            );
+            input.set_onmidimessage(Some(on_message.as_ref().unchecked_ref()));
+            on_message.forget();
+        }
+    });
+
+    let _ = navigator.request_midi_access()?.then(&on_access);
+    on_access.forget();
     Ok(())
 }
 
+/// Turning the knob or moving the fader that sends `controller` sets the parameter, from `min` to `max`
 #[wasm_bindgen]
-pub fn map_to_midi_controller() {}
+pub fn bind_midi_control(controller: u8, parameter: &str, min: f32, max: f32) {
+    MIDI_CONTROLS
+        .lock()
+        .unwrap()
+        .insert(controller, (parameter.to_string(), min..max));
+}
 
+/// Hitting the pad or key that plays `pitch` shows or hides the layer
 #[wasm_bindgen]
-pub fn render_canvas_into(selector: String) {
-    let svgstring = canvas().render(false).unwrap_throw();
-    append_new_div_inside(svgstring, selector)
+pub fn bind_midi_pad(pitch: u8, layer: &str) {
+    MIDI_PADS.lock().unwrap().insert(pitch, layer.to_string());
 }
 
+/// Current value of a parameter bound with `bind_midi_control`, if its control was moved already
 #[wasm_bindgen]
-pub fn render_canvas_at(selector: String) {
-    let svgstring = canvas().render(false).unwrap_throw();
-    replace_content_with(svgstring, selector)
+pub fn parameter(name: &str) -> Option<f32> {
+    PARAMETERS.lock().unwrap().get(name).copied()
+}
+
+/// Returns whether the message changed a parameter or a layer
+fn apply_midi_message(message: MidiMessage) -> bool {
+    match message {
+        MidiMessage::ControlChange(controller, Percentage(amount)) => {
+            match MIDI_CONTROLS.lock().unwrap().get(&controller) {
+                Some((name, range)) => {
+                    PARAMETERS.lock().unwrap().insert(
+                        name.clone(),
+                        range.start + amount * (range.end - range.start),
+                    );
+                    true
+                }
+                None => false,
+            }
+        }
+        MidiMessage::NoteOn(MidiPitch(pitch), _) => {
+            match MIDI_PADS.lock().unwrap().get(&pitch) {
+                Some(name) => match canvas().layer_safe(name) {
+                    Some(layer) => {
+                        layer.toggle();
+                        true
+                    }
+                    None => false,
+                },
+                None => false,
+            }
+        }
+        _ => false,
+    }
 }
 
 #[wasm_bindgen]
@@ -105,6 +408,19 @@ pub enum MidiMessage {
     ControlChange(u8, Percentage),
 }
 
+impl MidiMessage {
+    /// Parses a message as received from Web MIDI: a status byte followed by its data bytes. Messages other than notes and control changes give `None`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let data = |i: usize| bytes.get(i).copied().unwrap_or(0);
+        match bytes.first()? & 0xf0 {
+            0x80 => Some((MidiEvent::Note, MidiEventData([data(1), 0, 0])).into()),
+            0x90 => Some((MidiEvent::Note, MidiEventData([data(1), data(2), 0])).into()),
+            0xb0 => Some((MidiEvent::ControlChange, MidiEventData([data(1), data(2), 0])).into()),
+            _ => None,
+        }
+    }
+}
+
 impl From<(MidiEvent, MidiEventData)> for MidiMessage {
     fn from(value: (MidiEvent, MidiEventData)) -> Self {
         match value {
@@ -122,49 +438,210 @@ impl From<(MidiEvent, MidiEventData)> for MidiMessage {
                     MidiMessage::PedalOn
                 }
             }
-            (MidiEvent::ControlChange, MidiEventData([_, controller, value])) => {
+            (MidiEvent::ControlChange, MidiEventData([controller, value, _])) => {
                 MidiMessage::ControlChange(controller, value.into())
             }
         }
     }
 }
 
-#[wasm_bindgen]
-pub fn render_canvas(render_background: Option<bool>) {
-    canvas()
-        .render(render_background.unwrap_or(false))
-        .unwrap_throw();
+fn download_blob(blob: &web_sys::Blob, filename: &str) -> Result<(), JsValue> {
+    let url = web_sys::Url::create_object_url_with_blob(blob)?;
+    let link: web_sys::HtmlAnchorElement = document().create_element("a")?.unchecked_into();
+    link.set_href(&url);
+    link.set_download(filename);
+    link.click();
+    web_sys::Url::revoke_object_url(&url)
 }
 
 #[wasm_bindgen]
-pub fn set_palette(palette: ColorMapping) {
-    canvas().colormap = palette;
+pub fn region(
+    start_x: usize,
+    start_y: usize,
+    end_x: usize,
+    end_y: usize,
+) -> Result<Region, JsValue> {
+    Region::new(start_x, start_y, end_x, end_y)
+        .map_err(|err| JsValue::from_str(&err.to_string()))
 }
 
-#[wasm_bindgen]
-pub fn new_layer(name: &str) -> LayerWeb {
-    canvas().add_or_replace_layer(Layer::new(name));
-    LayerWeb {
-        name: name.to_string(),
+/// The canvas everything is drawn on, as seen from JavaScript. There's only one, so all methods are static.
+#[wasm_bindgen(js_name = Canvas)]
+pub struct WebCanvas;
+
+#[wasm_bindgen(js_class = Canvas)]
+impl WebCanvas {
+    pub fn render(render_background: Option<bool>) -> String {
+        canvas()
+            .render(render_background.unwrap_or(false))
+            .unwrap_throw()
     }
-}
 
-#[wasm_bindgen]
-pub fn get_layer(name: &str) -> Result<LayerWeb, JsValue> {
-    match canvas().layer_safe(name) {
-        Some(layer) => Ok(LayerWeb {
-            name: layer.name.clone(),
-        }),
-        None => Err(JsValue::from_str("Layer not found")),
+    pub fn render_into(selector: String) {
+        let svgstring = canvas().render(false).unwrap_throw();
+        append_new_div_inside(svgstring, selector)
     }
-}
 
-#[wasm_bindgen]
-pub fn random_linelikes(name: &str) -> LayerWeb {
-    let layer = canvas().random_linelikes(name);
-    canvas().add_or_replace_layer(layer);
-    LayerWeb {
-        name: name.to_string(),
+    pub fn render_at(selector: String) {
+        let svgstring = canvas().render(false).unwrap_throw();
+        replace_content_with(svgstring, selector)
+    }
+
+    /// Render only the layers matching the comma-separated patterns, e.g. "drums*,!debug*"
+    pub fn render_layers(layers: String, render_background: Option<bool>) -> String {
+        canvas()
+            .render_layers(
+                &layers.split(',').map(str::trim).collect::<Vec<_>>(),
+                render_background.unwrap_or(false),
+            )
+            .unwrap_throw()
+    }
+
+    /// Render the canvas to a PNG, and download it as `filename`. `resolution` works like in `Canvas::save_as`.
+    /// The browser does the rasterization, so this works without resvg.
+    pub fn download_png(filename: &str, resolution: usize) -> Result<(), JsValue> {
+        let (svg, (width, height)) = {
+            let mut canvas = canvas();
+            (
+                canvas.render(true).unwrap_throw(),
+                Canvas::raster_size(canvas.aspect_ratio(), resolution),
+            )
+        };
+
+        let options = web_sys::BlobPropertyBag::new();
+        options.set_type("image/svg+xml");
+        let svg_blob = web_sys::Blob::new_with_str_sequence_and_options(
+            &js_sys::Array::of1(&JsValue::from_str(&svg)),
+            &options,
+        )?;
+        let svg_url = web_sys::Url::create_object_url_with_blob(&svg_blob)?;
+
+        let image = web_sys::HtmlImageElement::new()?;
+        let filename = filename.to_string();
+        let on_load = Closure::once({
+            let image = image.clone();
+            let svg_url = svg_url.clone();
+            move || -> Result<(), JsValue> {
+                web_sys::Url::revoke_object_url(&svg_url)?;
+                let raster: web_sys::HtmlCanvasElement =
+                    document().create_element("canvas")?.unchecked_into();
+                raster.set_width(width as u32);
+                raster.set_height(height as u32);
+                raster
+                    .get_context("2d")?
+                    .expect_throw("could not get a 2D context")
+                    .unchecked_into::<web_sys::CanvasRenderingContext2d>()
+                    .draw_image_with_html_image_element_and_dw_and_dh(
+                        &image,
+                        0.0,
+                        0.0,
+                        width as f64,
+                        height as f64,
+                    )?;
+
+                let on_blob = Closure::once(move |png: web_sys::Blob| {
+                    download_blob(&png, &filename).unwrap_throw()
+                });
+                raster.to_blob(on_blob.as_ref().unchecked_ref())?;
+                on_blob.forget();
+                Ok(())
+            }
+        });
+        image.set_onload(Some(on_load.as_ref().unchecked_ref()));
+        on_load.forget();
+        image.set_src(&svg_url);
+        Ok(())
+    }
+
+    /// Go back to the state before the last edit. Returns false if there's nothing to undo.
+    pub fn undo() -> bool {
+        restore_from_history(&UNDO_STACK, &REDO_STACK)
+    }
+
+    /// Re-apply the last undone edit. Returns false if there's nothing to redo.
+    pub fn redo() -> bool {
+        restore_from_history(&REDO_STACK, &UNDO_STACK)
+    }
+
+    pub fn can_undo() -> bool {
+        !UNDO_STACK.lock().unwrap().is_empty()
+    }
+
+    pub fn can_redo() -> bool {
+        !REDO_STACK.lock().unwrap().is_empty()
+    }
+
+    pub fn set_palette(palette: ColorMapping) {
+        edited_canvas().colormap = palette;
+    }
+
+    pub fn set_grid_size(width: usize, height: usize) {
+        edited_canvas().set_grid_size(width, height);
+    }
+
+    pub fn set_background(color: WebColor) {
+        edited_canvas().set_background(color.into());
+    }
+
+    pub fn remove_background() {
+        edited_canvas().remove_background();
+    }
+
+    /// The whole grid
+    pub fn world_region() -> Region {
+        canvas().world_region
+    }
+
+    pub fn random_region() -> Region {
+        canvas().random_region()
+    }
+
+    pub fn new_layer(name: &str) -> LayerWeb {
+        edited_canvas().add_or_replace_layer(Layer::new(name));
+        LayerWeb {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn layer(name: &str) -> Result<LayerWeb, JsValue> {
+        match canvas().layer_safe(name) {
+            Some(layer) => Ok(LayerWeb {
+                name: layer.name.clone(),
+            }),
+            None => Err(JsValue::from_str(&format!("Layer {name} not found"))),
+        }
+    }
+
+    pub fn random_layer(name: &str) -> LayerWeb {
+        let layer = canvas().random_layer(name);
+        edited_canvas().add_or_replace_layer(layer);
+        LayerWeb {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn random_linelikes(name: &str) -> LayerWeb {
+        let layer = canvas().random_linelikes(name);
+        edited_canvas().add_or_replace_layer(layer);
+        LayerWeb {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn random_layer_within(name: &str, region: &Region) -> LayerWeb {
+        let layer = canvas().random_layer_within(name, region);
+        edited_canvas().add_or_replace_layer(layer);
+        LayerWeb {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn n_random_linelikes_within(name: &str, region: &Region, count: usize) -> LayerWeb {
+        let layer = canvas().n_random_linelikes_within(name, region, count);
+        edited_canvas().add_or_replace_layer(layer);
+        LayerWeb {
+            name: name.to_string(),
+        }
     }
 }
 
@@ -175,7 +652,7 @@ fn document() -> web_sys::Document {
         .expect_throw("should have a document on window")
 }
 
-fn query_selector(selector: String) -> web_sys::Element {
+pub(crate) fn query_selector(selector: String) -> web_sys::Element {
     document()
         .query_selector(&selector)
         .expect_throw(&format!("selector '{}' not found", selector))
@@ -193,17 +670,19 @@ fn replace_content_with(content: String, selector: String) {
     query_selector(selector).set_inner_html(&content);
 }
 
-#[wasm_bindgen(getter_with_clone)]
+/// A layer of the canvas, as seen from JavaScript. Get one with `Canvas.layer` or `Canvas.new_layer`.
+#[wasm_bindgen(js_name = Layer, getter_with_clone)]
 pub struct LayerWeb {
     pub name: String,
 }
 
-// #[wasm_bindgen()]
-
-#[wasm_bindgen]
+#[wasm_bindgen(js_class = Layer)]
 impl LayerWeb {
+    /// Render this layer only
     pub fn render(&self) -> String {
-        canvas().render(false).unwrap_throw()
+        canvas()
+            .render_layers(&[self.name.as_str()], false)
+            .unwrap_throw()
     }
 
     pub fn render_into(&self, selector: String) {
@@ -214,102 +693,385 @@ impl LayerWeb {
         replace_content_with(self.render(), selector)
     }
 
-    pub fn paint_all(&self, color: Color, opacity: Option<f32>, filter: Filter) {
-        canvas()
-            .layer(&self.name)
-            .paint_all_objects(Fill::Translucent(color, opacity.unwrap_or(1.0)));
-        canvas().layer(&self.name).filter_all_objects(filter);
+    pub fn paint_all(&self, color: WebColor, opacity: Option<f32>, filter: Filter) {
+        let mut canvas = edited_canvas();
+        let layer = canvas.layer(&self.name);
+        layer.paint_all_objects(Fill::Translucent(color.into(), opacity.unwrap_or(1.0)));
+        layer.filter_all_objects(filter);
     }
 
-    pub fn random(name: &str) -> Self {
-        let layer = canvas().random_layer(name);
-        canvas().add_or_replace_layer(layer);
-        LayerWeb {
-            name: name.to_string(),
+    pub fn fill_all(&self, fill: &WebFill) {
+        edited_canvas().layer(&self.name).paint_all_objects(fill.0.clone());
+    }
+
+    pub fn fill_object(&self, object: &str, fill: &WebFill) {
+        let mut canvas = edited_canvas();
+        let layer = canvas.layer(&self.name);
+        layer.object(object).fill = Some(fill.0.clone());
+        layer.flush();
+    }
+
+    pub fn filter_all(&self, filter: Filter) {
+        edited_canvas().layer(&self.name).filter_all_objects(filter);
+    }
+
+    pub fn transform(&self, transformation: TransformationWASM) {
+        edited_canvas().layer(&self.name).transform(transformation.into());
+    }
+
+    pub fn clear_transformations(&self) {
+        edited_canvas().layer(&self.name).clear_transformations();
+    }
+
+    pub fn clip_to(&self, region: &Region) {
+        edited_canvas().layer(&self.name).clip_to(*region);
+    }
+
+    pub fn unclip(&self) {
+        edited_canvas().layer(&self.name).unclip();
+    }
+
+    pub fn set_opacity(&self, opacity: f32) {
+        edited_canvas().layer(&self.name).set_opacity(opacity);
+    }
+
+    pub fn hide(&self) {
+        edited_canvas().layer(&self.name).hide();
+    }
+
+    pub fn show(&self) {
+        edited_canvas().layer(&self.name).show();
+    }
+
+    pub fn add_object(&self, name: &str, object: &WebObject) {
+        edited_canvas().layer(&self.name).add_object(name, object.0.clone());
+    }
+
+    pub fn remove_object(&self, name: &str) {
+        edited_canvas().layer(&self.name).remove_object(name);
+    }
+}
+
+/// Objects, as seen from JavaScript. Create one with a factory, e.g. `Shape.line(start, end, thickness)`, then add it to a layer with `Layer.add_object`.
+/// Not named `Object`, which would shadow JavaScript's own in the generated bindings.
+#[wasm_bindgen(js_name = Shape)]
+#[derive(Debug, Clone)]
+pub struct WebObject(ColoredObject);
+
+#[wasm_bindgen(js_class = Shape)]
+impl WebObject {
+    pub fn line(start: Point, end: Point, thickness: f32) -> Self {
+        Self(Object::Line(start, end, thickness).into())
+    }
+
+    pub fn curve_outward(start: Point, end: Point, thickness: f32) -> Self {
+        Self(Object::CurveOutward(start, end, thickness).into())
+    }
+
+    pub fn curve_inward(start: Point, end: Point, thickness: f32) -> Self {
+        Self(Object::CurveInward(start, end, thickness).into())
+    }
+
+    pub fn small_circle(center: Point) -> Self {
+        Self(Object::SmallCircle(center).into())
+    }
+
+    pub fn dot(center: Point) -> Self {
+        Self(Object::Dot(center).into())
+    }
+
+    pub fn big_circle(topleft: Point) -> Self {
+        Self(Object::BigCircle(topleft).into())
+    }
+
+    pub fn text(anchor: Point, text: String, font_size: f32) -> Self {
+        Self(Object::Text(anchor, text, font_size).into())
+    }
+
+    pub fn centered_text(center: Point, text: String, font_size: f32) -> Self {
+        Self(Object::CenteredText(center, text, font_size).into())
+    }
+
+    pub fn rectangle(topleft: Point, bottomright: Point) -> Self {
+        Self(Object::Rectangle(topleft, bottomright).into())
+    }
+
+    /// The region's outline, as a rectangle
+    pub fn region(region: &Region) -> Self {
+        Self(region.rectangle().into())
+    }
+
+    pub fn filled(&self, fill: &WebFill) -> Self {
+        Self(ColoredObject {
+            fill: Some(fill.0.clone()),
+            ..self.0.clone()
+        })
+    }
+
+    pub fn filtered(&self, filter: Filter) -> Self {
+        let mut object = self.0.clone();
+        object.filters.push(filter);
+        Self(object)
+    }
+
+    pub fn transformed(&self, transformation: TransformationWASM) -> Self {
+        let mut object = self.0.clone();
+        object.transformations.push(transformation.into());
+        Self(object)
+    }
+}
+
+/// A video, as seen from JavaScript. Hooks are JavaScript functions that receive a `VideoContext`,
+/// and draw on the video's canvas with the same functions as images (`Canvas.layer`, `Canvas.new_layer`, etc.).
+#[wasm_bindgen(js_name = Video)]
+pub struct VideoWeb {
+    /// Only `None` while a builder method runs
+    video: Option<Video<()>>,
+    live_audio: Vec<(String, LiveAudio)>,
+}
+
+#[wasm_bindgen(js_class = Video)]
+impl VideoWeb {
+    /// Starts from the current canvas
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            video: Some(Video::new(canvas().clone())),
+            live_audio: vec![],
         }
     }
 
-    pub fn new_line(
-        &self,
-        name: &str,
-        start: Point,
-        end: Point,
-        thickness: f32,
-        color: Color,
-    ) {
-        canvas().layer(name).add_object(
-            name,
-            (
-                Object::Line(start, end, thickness),
-                Some(Fill::Solid(color)),
-            )
-                .into(),
-        )
-    }
-    pub fn new_curve_outward(
-        &self,
-        name: &str,
-        start: Point,
-        end: Point,
-        thickness: f32,
-        color: Color,
-    ) {
-        canvas().layer(name).add_object(
-            name,
-            Object::CurveOutward(start, end, thickness).color(Fill::Solid(color)),
-        )
-    }
-    pub fn new_curve_inward(
-        &self,
-        name: &str,
-        start: Point,
-        end: Point,
-        thickness: f32,
-        color: Color,
-    ) {
-        canvas().layer(name).add_object(
-            name,
-            Object::CurveInward(start, end, thickness).color(Fill::Solid(color)),
-        )
-    }
-    pub fn new_small_circle(&self, name: &str, center: Point, color: Color) {
-        canvas()
-            .layer(name)
-            .add_object(name, Object::SmallCircle(center).color(Fill::Solid(color)))
+    fn update(&mut self, builder: impl FnOnce(Video<()>) -> Video<()>) {
+        self.video = self.video.take().map(builder);
     }
-    pub fn new_dot(&self, name: &str, center: Point, color: Color) {
-        canvas()
-            .layer(name)
-            .add_object(name, Object::Dot(center).color(Fill::Solid(color)))
+
+    fn video(&mut self) -> &mut Video<()> {
+        self.video.as_mut().unwrap_throw()
     }
-    pub fn new_big_circle(&self, name: &str, center: Point, color: Color) {
-        canvas()
-            .layer(name)
-            .add_object(name, Object::BigCircle(center).color(Fill::Solid(color)))
-    }
-    pub fn new_text(
-        &self,
-        name: &str,
-        anchor: Point,
-        text: String,
-        font_size: f32,
-        color: Color,
-    ) {
-        canvas().layer(name).add_object(
-            name,
-            Object::Text(anchor, text, font_size).color(Fill::Solid(color)),
-        )
-    }
-    pub fn new_rectangle(
-        &self,
-        name: &str,
-        topleft: Point,
-        bottomright: Point,
-        color: Color,
-    ) {
-        canvas().layer(name).add_object(
-            name,
-            Object::Rectangle(topleft, bottomright).color(Fill::Solid(color)),
-        )
+
+    pub fn set_fps(&mut self, fps: usize) {
+        self.video().fps = fps;
+    }
+
+    pub fn set_bpm(&mut self, bpm: usize) {
+        self.video().syncdata.bpm = bpm;
+    }
+
+    pub fn set_duration(&mut self, ms: usize) {
+        self.video().duration_override = Some(ms);
+    }
+
+    pub fn init(&mut self, hook: js_sys::Function) {
+        self.update(|video| video.init(js_hook(hook)));
+    }
+
+    pub fn each_beat(&mut self, hook: js_sys::Function) {
+        self.update(|video| video.each_beat(js_hook(hook)));
+    }
+
+    pub fn each_frame(&mut self, hook: js_sys::Function) {
+        self.update(|video| video.each_frame(js_hook(hook)));
+    }
+
+    pub fn each_n_frame(&mut self, n: usize, hook: js_sys::Function) {
+        self.update(|video| video.each_n_frame(n, js_hook(hook)));
+    }
+
+    pub fn every_n_beats(&mut self, beats: f32, hook: js_sys::Function) {
+        self.update(|video| video.every(beats, MusicalDurationUnit::Beats, js_hook(hook)));
+    }
+
+    /// See `WebVideo::with_live_audio`. The `LiveAudio` can't be used from JavaScript afterwards.
+    pub fn with_live_audio(&mut self, name: &str, audio: LiveAudio) {
+        self.live_audio.push((name.to_string(), audio));
+    }
+
+    /// See `WebVideo::new`. The video can't be used from JavaScript afterwards.
+    pub fn play(self, audio: &str, target: &str) {
+        let web_video = WebVideo::new(self.video.unwrap_throw(), audio, target);
+        Self::with_all_live_audio(web_video, self.live_audio).play()
+    }
+
+    /// See `WebVideo::live`
+    pub fn play_live(self, target: &str) {
+        let web_video = WebVideo::live(self.video.unwrap_throw(), target);
+        Self::with_all_live_audio(web_video, self.live_audio).play()
+    }
+
+    fn with_all_live_audio(web_video: WebVideo<()>, live_audio: Vec<(String, LiveAudio)>) -> WebVideo<()> {
+        live_audio
+            .into_iter()
+            .fold(web_video, |web_video, (name, audio)| web_video.with_live_audio(&name, audio))
+    }
+}
+
+impl Default for VideoWeb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What JavaScript hooks receive
+#[wasm_bindgen(getter_with_clone)]
+pub struct VideoContext {
+    pub frame: usize,
+    pub beat: usize,
+    pub beat_fractional: f32,
+    pub ms: usize,
+    pub bpm: usize,
+    pub timestamp: String,
+    #[wasm_bindgen(skip)]
+    pub params: HashMap<String, f32>,
+    #[wasm_bindgen(skip)]
+    pub live_stems_amplitudes: HashMap<String, f32>,
+}
+
+#[wasm_bindgen]
+impl VideoContext {
+    /// See `Context::param`
+    pub fn param(&self, name: &str) -> Option<f32> {
+        self.params.get(name).copied()
+    }
+
+    /// Relative amplitude of a live stem, see `WebVideo::with_live_audio`
+    pub fn stem_amplitude(&self, name: &str) -> Option<f32> {
+        self.live_stems_amplitudes.get(name).copied()
+    }
+}
+
+impl From<&Context<'_, ()>> for VideoContext {
+    fn from(context: &Context<()>) -> Self {
+        Self {
+            frame: context.frame,
+            beat: context.beat,
+            beat_fractional: context.beat_fractional,
+            ms: context.ms,
+            bpm: context.bpm,
+            timestamp: context.timestamp.clone(),
+            params: context.params.clone(),
+            live_stems_amplitudes: context
+                .live_stems
+                .iter()
+                .map(|(name, stem)| (name.clone(), StemAtInstant::from(stem).amplitude_relative()))
+                .collect(),
+        }
+    }
+}
+
+/// Runs the JavaScript function with the video's canvas as the current canvas. The hook is kept for as long as the page is open.
+/// Edits made by the hook are not recorded in the undo history.
+fn js_hook(callback: js_sys::Function) -> &'static RenderFunction<()> {
+    Box::leak(Box::new(move |video_canvas: &mut Canvas, context: &mut Context<()>| {
+        std::mem::swap(video_canvas, &mut canvas());
+        let was_recording = RECORD_HISTORY.swap(false, Ordering::Relaxed);
+        let result = callback.call1(&JsValue::NULL, &VideoContext::from(&*context).into());
+        RECORD_HISTORY.store(was_recording, Ordering::Relaxed);
+        std::mem::swap(video_canvas, &mut canvas());
+        result
+            .map(|_| ())
+            .map_err(|error| anyhow::anyhow!("JavaScript hook failed: {:?}", error))
+    }))
+}
+
+/// Plays a video in the browser: on each animation frame, hooks run up to the current time, and the rendered frame replaces the target element's content.
+/// With an audio element, playback is controlled with it, and seeking backwards runs the hooks from the beginning again.
+pub struct WebVideo<C: 'static> {
+    renderer: FrameRenderer<'static, C>,
+    fps: usize,
+    audio: Option<web_sys::HtmlAudioElement>,
+    /// When the video started, in `performance.now()` milliseconds. Used when there's no audio element.
+    started_at: f64,
+    target: web_sys::Element,
+    displayed_frame: Option<FrameNumber>,
+    live_audio: Vec<(String, LiveAudio)>,
+}
+
+type AnimationFrameCallback = Rc<RefCell<Option<Closure<dyn FnMut()>>>>;
+
+impl<C: Default + 'static> WebVideo<C> {
+    /// `audio` and `target` are CSS selectors. The video is kept around for as long as the page is open.
+    pub fn new(video: Video<C>, audio: &str, target: &str) -> Self {
+        Self {
+            audio: Some(
+                query_selector(audio.to_string())
+                    .dyn_into()
+                    .expect_throw("the audio selector should match an <audio> element"),
+            ),
+            ..Self::live(video, target)
+        }
+    }
+
+    /// Play without an audio element: time starts flowing when the page loads. Useful with live audio from the microphone.
+    pub fn live(video: Video<C>, target: &str) -> Self {
+        let video: &'static Video<C> = Box::leak(Box::new(video));
+        Self {
+            renderer: FrameRenderer::new(video, &ProgressBar::hidden(), true),
+            fps: video.fps,
+            audio: None,
+            started_at: performance().now(),
+            target: query_selector(target.to_string()),
+            displayed_frame: None,
+            live_audio: vec![],
+        }
+    }
+
+    /// Feed the audio's amplitude and spectrum to hooks as the stem `name`, see `Context::stem` and `Context::spectrum`
+    pub fn with_live_audio(self, name: &str, audio: LiveAudio) -> Self {
+        let mut live_audio = self.live_audio;
+        live_audio.push((name.to_string(), audio));
+        Self { live_audio, ..self }
+    }
+
+    fn current_ms(&self) -> Millisecond {
+        match &self.audio {
+            Some(audio) => (audio.current_time() * 1000.0) as Millisecond,
+            None => (performance().now() - self.started_at) as Millisecond,
+        }
+    }
+
+    /// Render the frame at the current time, unless it's already displayed
+    fn tick(&mut self) {
+        let ms = self.current_ms();
+        let frame = self.fps * ms / 1000;
+        if self.displayed_frame == Some(frame) {
+            return;
+        }
+
+        for (name, audio) in &mut self.live_audio {
+            self.renderer.set_live_stem(name, audio.analyse());
+        }
+
+        match self.renderer.render_at(ms) {
+            Ok(svg) => {
+                self.target.set_inner_html(&svg);
+                self.displayed_frame = Some(frame);
+            }
+            Err(error) => console_log!("Could not render frame at {ms}ms: {error:#}"),
+        }
+    }
+
+    /// Render on every animation frame, for as long as the page is open
+    pub fn play(mut self) {
+        let callback: AnimationFrameCallback = Rc::new(RefCell::new(None));
+        let next = callback.clone();
+        *callback.borrow_mut() = Some(Closure::new(move || {
+            self.tick();
+            request_animation_frame(next.borrow().as_ref().unwrap_throw());
+        }));
+        request_animation_frame(callback.borrow().as_ref().unwrap_throw());
     }
 }
+
+fn performance() -> web_sys::Performance {
+    web_sys::window()
+        .expect_throw("no global `window` exists")
+        .performance()
+        .expect_throw("performance should be available")
+}
+
+fn request_animation_frame(callback: &Closure<dyn FnMut()>) {
+    web_sys::window()
+        .expect_throw("no global `window` exists")
+        .request_animation_frame(callback.as_ref().unchecked_ref())
+        .expect_throw("could not request an animation frame");
+}
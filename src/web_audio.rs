@@ -0,0 +1,103 @@
+use wasm_bindgen::prelude::{wasm_bindgen, Closure};
+use wasm_bindgen::{JsCast, JsValue, UnwrapThrowExt};
+use web_sys::{AnalyserNode, AudioContext, AudioNode};
+
+use crate::web::{log, query_selector};
+use crate::LiveStem;
+
+/// Samples analysed at once. Also twice the number of frequency bins in the spectrum.
+const FFT_SIZE: u32 = 2048;
+
+/// Analyses audio from the microphone or from a media element while it plays, to feed hooks without pre-computed sync data.
+/// See `WebVideo::with_live_audio`, and `Video.with_live_audio` from JavaScript.
+#[wasm_bindgen]
+pub struct LiveAudio {
+    analyser: AnalyserNode,
+    samples: Vec<f32>,
+    amplitude_max: f32,
+}
+
+impl LiveAudio {
+    fn new(audio_context: &AudioContext, source: &AudioNode) -> Result<Self, JsValue> {
+        // contexts created before the user interacted with the page start suspended
+        let _ = audio_context.resume()?;
+        let analyser = audio_context.create_analyser()?;
+        analyser.set_fft_size(FFT_SIZE);
+        source.connect_with_audio_node(&analyser)?;
+        Ok(Self {
+            analyser,
+            samples: vec![0.0; FFT_SIZE as usize],
+            // so that relative amplitudes are 0 instead of NaN until something is heard
+            amplitude_max: f32::EPSILON,
+        })
+    }
+
+    /// Ask for access to the microphone, and call `on_ready` once it's granted
+    pub fn from_microphone(on_ready: impl FnOnce(LiveAudio) + 'static) -> Result<(), JsValue> {
+        let constraints = web_sys::MediaStreamConstraints::new();
+        constraints.set_audio(&JsValue::TRUE);
+        let access = web_sys::window()
+            .expect_throw("no global `window` exists")
+            .navigator()
+            .media_devices()?
+            .get_user_media_with_constraints(&constraints)?;
+
+        let on_access = Closure::once(move |stream: JsValue| {
+            let stream: web_sys::MediaStream = stream.unchecked_into();
+            let audio_context = AudioContext::new().unwrap_throw();
+            let source = audio_context
+                .create_media_stream_source(&stream)
+                .unwrap_throw();
+            on_ready(Self::new(&audio_context, &source).unwrap_throw());
+        });
+        let _ = access.then(&on_access);
+        on_access.forget();
+        Ok(())
+    }
+
+    /// Amplitude and spectrum of the latest samples
+    pub fn analyse(&mut self) -> LiveStem {
+        self.analyser.get_float_time_domain_data(&mut self.samples);
+        let amplitude = (self
+            .samples
+            .iter()
+            .map(|sample| sample * sample)
+            .sum::<f32>()
+            / self.samples.len() as f32)
+            .sqrt();
+        self.amplitude_max = self.amplitude_max.max(amplitude);
+
+        let mut spectrum = vec![0.0; self.analyser.frequency_bin_count() as usize];
+        self.analyser.get_float_frequency_data(&mut spectrum);
+
+        LiveStem {
+            amplitude,
+            amplitude_max: self.amplitude_max,
+            spectrum,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl LiveAudio {
+    /// Analyse what an `<audio>` or `<video>` element plays. It can still be heard.
+    pub fn from_element(selector: &str) -> Result<Self, JsValue> {
+        let element: web_sys::HtmlMediaElement = query_selector(selector.to_string())
+            .dyn_into()
+            .expect_throw("the selector should match an <audio> or <video> element");
+        let audio_context = AudioContext::new()?;
+        let source = audio_context.create_media_element_source(&element)?;
+        source.connect_with_audio_node(&audio_context.destination())?;
+        Self::new(&audio_context, &source)
+    }
+
+    /// See `from_microphone`. `on_ready` receives the `LiveAudio`.
+    #[wasm_bindgen(js_name = from_microphone)]
+    pub fn from_microphone_js(on_ready: js_sys::Function) -> Result<(), JsValue> {
+        Self::from_microphone(move |audio| {
+            if let Err(error) = on_ready.call1(&JsValue::NULL, &audio.into()) {
+                log(&format!("Live audio callback failed: {:?}", error));
+            }
+        })
+    }
+}